@@ -0,0 +1,107 @@
+//! Enumerate a USB MIDI device on the simulated bus and exchange a few messages with it, all on the
+//! host machine.
+//!
+//! Run with `cargo run --example sim-host --features sim`.
+
+use midly_usb::{
+    class::MidiClass,
+    live::LiveEvent,
+    num::u4,
+    router::{Output, Port, Route, Router},
+    sim::{self, SimBus},
+    MidiMessage, UsbMidiPacket,
+};
+use usb_device::{bus::UsbBusAllocator, prelude::*};
+
+fn main() {
+    let bus = SimBus::new();
+    let host = bus.host();
+    let alloc = UsbBusAllocator::new(bus);
+    let mut midi = MidiClass::builder(&alloc, 64)
+        .streaming_interface_name("Simulated MIDI")
+        .cable("Input")
+        .cable("Echo")
+        .build();
+    let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x5e4))
+        .manufacturer("midly")
+        .product("Simulated MIDI")
+        .build();
+
+    //Enumerate the device like an operating system would
+    let config = host
+        .enumerate(|| {
+            device.poll(&mut [&mut midi]);
+        })
+        .expect("enumeration failed");
+    println!("device enumerated at address {}", host.address());
+    let mut endpoints = Vec::new();
+    let mut jack_names = Vec::new();
+    for desc in sim::descriptors(&config) {
+        println!("  descriptor {:#04x}: {:02x?}", desc[1], desc);
+        if desc[1] == sim::DESCRIPTOR_ENDPOINT {
+            endpoints.push(desc[2]);
+        }
+        //MIDI IN and OUT jacks end with their string index
+        if desc[1] == 0x24 && (desc[2] == 0x02 || desc[2] == 0x03) {
+            jack_names.push((desc[4], desc[desc.len() - 1]));
+        }
+    }
+    for (jack, string) in jack_names {
+        let name = host
+            .get_string(
+                || {
+                    device.poll(&mut [&mut midi]);
+                },
+                string,
+                0x0409,
+            )
+            .expect("unnamed jack");
+        println!("  jack {} is named {:?}", jack, name);
+    }
+    let out_ep = endpoints.iter().copied().find(|ep| ep & 0x80 == 0).unwrap();
+    let in_ep = endpoints.iter().copied().find(|ep| ep & 0x80 != 0).unwrap();
+
+    //The device echoes every message from cable 0 back to the host on cable 1
+    let mut router = Router::<4, 1>::new();
+    router
+        .add_route(Route::new(Port::Usb(u4::new(0)), Port::Usb(u4::new(1))))
+        .unwrap();
+
+    let note_on = LiveEvent::Midi {
+        channel: 0.into(),
+        message: MidiMessage::NoteOn {
+            key: 60.into(),
+            vel: 100.into(),
+        },
+    };
+    let mut packet = Vec::new();
+    UsbMidiPacket::new(u4::new(0), midly_usb::CIN::NoteOn, note_on)
+        .write(&mut packet)
+        .unwrap();
+    host.bulk_out(out_ep, &packet).expect("device not ready");
+    println!("host sent {:02x?}", packet);
+
+    //Device side: read the packet, route it and write the result back
+    device.poll(&mut [&mut midi]);
+    let mut buf = [0; 64];
+    let len = midi.read_packet(&mut buf).expect("no packet received");
+    let mut replies = Vec::new();
+    for raw in buf[..len].chunks_exact(4) {
+        router.feed_usb([raw[0], raw[1], raw[2], raw[3]], |out| {
+            if let Output::Usb(reply) = out {
+                replies.extend_from_slice(&reply);
+            }
+        });
+    }
+    midi.write_packet(&replies).expect("device busy");
+
+    //Host side: receive the echo
+    let echo = host.bulk_in(in_ep).expect("no echo");
+    for raw in echo.chunks_exact(4) {
+        let packet = UsbMidiPacket::parse(raw).expect("invalid packet");
+        println!(
+            "host received {:?} on cable {}",
+            packet.event, packet.cable_number
+        );
+    }
+}
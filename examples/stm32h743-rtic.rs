@@ -0,0 +1,142 @@
+//! USB MIDI on an STM32H743 with RTIC: the USB interrupt owns the device and the class, while an
+//! application task receives notes and echoes them back an octave higher through the queue-backed
+//! sender and receiver handles.
+//!
+//! This example uses the USB1 peripheral, with D+/D- on PB15/PB14.
+//! Build it with
+//! `cargo build --example stm32h743-rtic --target thumbv7em-none-eabihf --no-default-features --features embedded`,
+//! along with the `memory.x` linker script of `stm32h7xx-hal` and the `-C link-arg=-Tlink.x`
+//! rustflag.
+//! On any other target the example does nothing.
+
+#![cfg_attr(all(target_arch = "arm", target_os = "none"), no_std, no_main)]
+
+#[cfg(not(all(target_arch = "arm", target_os = "none")))]
+fn main() {
+    eprintln!("this example runs on an STM32H743, build it for `thumbv7em-none-eabihf`");
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+use panic_halt as _;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+#[rtic::app(device = stm32h7xx_hal::pac, peripherals = true, dispatchers = [SPI1])]
+mod app {
+    use midly_usb::{
+        class::{MidiClass, MidiQueues, MidiReceiver, MidiSender},
+        live::LiveEvent,
+        MidiMessage,
+    };
+    use stm32h7xx_hal::{
+        pac::Interrupt,
+        prelude::*,
+        rcc::rec::UsbClkSel,
+        usb_hs::{UsbBus, USB1},
+    };
+    use usb_device::{bus::UsbBusAllocator, prelude::*};
+
+    type Bus = UsbBus<USB1>;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        usb_dev: UsbDevice<'static, Bus>,
+        midi: MidiClass<'static, Bus>,
+        sender: MidiSender<'static>,
+        receiver: MidiReceiver<'static>,
+    }
+
+    #[init(local = [
+        ep_memory: [u32; 1024] = [0; 1024],
+        usb_bus: Option<UsbBusAllocator<Bus>> = None,
+        queues: MidiQueues = MidiQueues::new(),
+    ])]
+    fn init(cx: init::Context) -> (Shared, Local) {
+        let dp = cx.device;
+
+        let pwr = dp.PWR.constrain();
+        let vos = pwr.freeze();
+        let rcc = dp.RCC.constrain();
+        let mut ccdr = rcc.sys_ck(200.MHz()).freeze(vos, &dp.SYSCFG);
+        let _ = ccdr.clocks.hsi48_ck().expect("HSI48 must run");
+        ccdr.peripheral.kernel_usb_clk_mux(UsbClkSel::Hsi48);
+
+        let gpiob = dp.GPIOB.split(ccdr.peripheral.GPIOB);
+        let usb = USB1::new(
+            dp.OTG1_HS_GLOBAL,
+            dp.OTG1_HS_DEVICE,
+            dp.OTG1_HS_PWRCLK,
+            gpiob.pb14.into_alternate(),
+            gpiob.pb15.into_alternate(),
+            ccdr.peripheral.USB1OTG,
+            &ccdr.clocks,
+        );
+        let usb_bus: &'static _ = cx
+            .local
+            .usb_bus
+            .insert(UsbBus::new(usb, cx.local.ep_memory));
+
+        let mut midi = MidiClass::new(usb_bus, 64);
+        let (sender, receiver) = midi.split(cx.local.queues);
+        let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x16c0, 0x5e4))
+            .manufacturer("midly")
+            .product("STM32H743 MIDI")
+            .build();
+
+        (
+            Shared {},
+            Local {
+                usb_dev,
+                midi,
+                sender,
+                receiver,
+            },
+        )
+    }
+
+    /// Poll the device and move packets between the endpoints and the queues.
+    #[task(binds = OTG_HS, local = [usb_dev, midi], priority = 2)]
+    fn usb(cx: usb::Context) {
+        cx.local.usb_dev.poll(&mut [cx.local.midi]);
+        cx.local.midi.poll_queues();
+        //The echo task may already be running, in which case it picks up the new packets anyway
+        echo::spawn().ok();
+    }
+
+    /// Echo every note an octave higher.
+    #[task(local = [sender, receiver], priority = 1)]
+    async fn echo(cx: echo::Context) {
+        while let Some(packet) = cx.local.receiver.receive() {
+            let cable = packet.cable_number;
+            let event = match packet.event {
+                LiveEvent::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn { key, vel },
+                } if key < 116 => LiveEvent::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn {
+                        key: key + 12.into(),
+                        vel,
+                    },
+                },
+                LiveEvent::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff { key, vel },
+                } if key < 116 => LiveEvent::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff {
+                        key: key + 12.into(),
+                        vel,
+                    },
+                },
+                _ => continue,
+            };
+            //Notes are dropped if the host is not reading them fast enough
+            cx.local.sender.send_event(cable, &event).ok();
+        }
+        //Start transmitting the queued notes
+        rtic::pend(Interrupt::OTG_HS);
+    }
+}
@@ -1,30 +1,43 @@
+//! USB MIDI for `usb-device`, which works with any microcontroller that has a `UsbBus`
+//! implementation.
+//!
+//! This module is only available with the `embedded` feature enabled.
+
+#![cfg(feature = "embedded")]
+
 #[allow(unused)]
 #[allow(dead_code)]
 use core::convert::TryInto;
+use heapless::{
+    spsc::{Consumer, Producer, Queue},
+    Vec as HVec,
+};
 use usb_device::class_prelude::*;
 use usb_device::Result as UsbResult;
 
-/// This should be used as `device_class` when building the `UsbDevice`.
-pub const USB_CLASS_AUDIO: u8 = 0x01;
-pub const USB_SUBCLASS_MIDISTREAMING: u8 = 0x03;
-pub const MIDI_PROTOCOL_NONE: u8 = 0x00;
+use crate::descriptor::{self, EndpointJacks, CS_ENDPOINT, CS_INTERFACE, MAX_CABLES, MAX_ELEMENTS};
+pub use crate::descriptor::{
+    Element, ElementCap, ElementCaps, MIDI_PROTOCOL_NONE, USB_CLASS_AUDIO,
+    USB_SUBCLASS_AUDIOCONTROL, USB_SUBCLASS_MIDISTREAMING,
+};
+pub use crate::embedded::SendError;
+use crate::{
+    live::{LiveEvent, SystemCommon},
+    packet::{packetize, UsbMidiPacket},
+    prelude::*,
+};
 
 // MS Class-Specific Interface Descriptor Types
 const CS_UNDEFINED: u8 = 0x20;
 const CS_DEVICE: u8 = 0x21;
 const CS_CONFIGURATION: u8 = 0x22;
 const CS_STRING: u8 = 0x23;
-const CS_ENDPOINT: u8 = 0x25;
 const CS_GR_TRM_BLOCK: u8 = 0x26;
 
 //MS Class-Specific Interface Descriptor Subtypes
 const MS_DESCRIPTOR_UNDEFINED: u8 = 0x00;
 const MS_HEADER: u8 = 0x01;
-const MIDI_IN_JACK: u8 = 0x02;
-const MIDI_OUT_JACK: u8 = 0x03;
-const ELEMENT: u8 = 0x04;
 //
-const CS_INTERFACE: u8 = 0x24;
 //
 const REQ_SEND_ENCAPSULATED_COMMAND: u8 = 0x00;
 const REQ_GET_ENCAPSULATED_COMMAND: u8 = 0x01;
@@ -32,6 +45,18 @@ const REQ_SET_LINE_CODING: u8 = 0x20;
 const REQ_GET_LINE_CODING: u8 = 0x21;
 const REQ_SET_CONTROL_LINE_STATE: u8 = 0x22;
 
+/// The slots of the control and streaming interface names in `MidiClass::strings`, followed by the
+/// names of each cable and then of each element.
+const CONTROL_NAME: usize = 0;
+const STREAMING_NAME: usize = 1;
+const CABLE_NAMES: usize = 2;
+const ELEMENT_NAMES: usize = CABLE_NAMES + MAX_CABLES;
+const STRING_SLOTS: usize = ELEMENT_NAMES + MAX_ELEMENTS;
+
+/// The largest max packet size supported by the queue-backed handles, which is the largest one
+/// allowed for full-speed bulk endpoints.
+const MAX_TRANSFER: usize = 64;
+
 /// Packet level implementation of a CDC-ACM serial port.
 ///
 /// This class can be used directly and it has the least overhead due to directly reading and
@@ -46,25 +71,98 @@ const REQ_SET_CONTROL_LINE_STATE: u8 = 0x22;
 ///   host operating system until a subsequent shorter packet is sent. A zero-length packet (ZLP)
 ///   can be sent if there is no other data to send. This is because USB bulk transactions must be
 ///   terminated with a short packet, even if the bulk endpoint is used for stream-like data.
-pub struct MidiClass<'a, B: UsbBus> {
+///
+/// Alternatively, the class can be [`split`](#method.split) into a
+/// [`MidiSender`](struct.MidiSender.html) and a [`MidiReceiver`](struct.MidiReceiver.html), backed
+/// by lock-free single-producer single-consumer queues of `N` slots.
+/// The class then fills and drains the queues whenever the `UsbDevice` is polled, so the handles
+/// can be moved into other tasks while the USB interrupt handler owns the device and the class.
+///
+/// Use a [`MidiClassBuilder`](struct.MidiClassBuilder.html) to expose several cables, to give
+/// names to the ports or to describe the capabilities of the device.
+pub struct MidiClass<'a, B: UsbBus, const N: usize = 16> {
     comm_if: InterfaceNumber,
     comm_ep: EndpointIn<'a, B>,
     data_if: InterfaceNumber,
     read_ep: EndpointOut<'a, B>,
     write_ep: EndpointIn<'a, B>,
+    /// The receiving end of the transmit queue, if the class was split.
+    tx: Option<Consumer<'a, [u8; 4], N>>,
+    /// The sending end of the receive queue, if the class was split.
+    rx: Option<Producer<'a, [u8; 4], N>>,
+    /// Packets taken from the transmit queue but not written to the endpoint yet.
+    tx_buf: [u8; MAX_TRANSFER],
+    tx_len: usize,
+    /// Whether the last transfer filled the endpoint, so it must be ended by a short packet.
+    tx_zlp: bool,
+    /// Packets read from the endpoint but not moved into the receive queue yet.
+    rx_buf: [u8; MAX_TRANSFER],
+    rx_pos: usize,
+    rx_len: usize,
+    cables: u8,
+    elements: HVec<Element<'a>, MAX_ELEMENTS>,
+    /// The interface, cable and element names, along with their allocated string indices.
+    strings: [Option<(StringIndex, &'a str)>; STRING_SLOTS],
 }
 
-impl<B: UsbBus> MidiClass<'_, B> {
-    /// Creates a new CdcAcmClass with the provided UsbBus and max_packet_size in bytes. For
+impl<'a, B: UsbBus> MidiClass<'a, B> {
+    /// Creates a new MidiClass with the provided UsbBus and max_packet_size in bytes. For
     /// full-speed devices, max_packet_size has to be one of 8, 16, 32 or 64.
-    pub fn new(alloc: &UsbBusAllocator<B>, max_packet_size: u16) -> MidiClass<'_, B> {
-        MidiClass {
-            comm_if: alloc.interface(),
-            comm_ep: alloc.interrupt(8, 255),
-            data_if: alloc.interface(),
-            read_ep: alloc.bulk(max_packet_size),
-            write_ep: alloc.bulk(max_packet_size),
-        }
+    #[inline]
+    pub fn new(alloc: &'a UsbBusAllocator<B>, max_packet_size: u16) -> MidiClass<'a, B> {
+        MidiClass::with_queue_size(alloc, max_packet_size)
+    }
+
+    /// Start building a MidiClass with several cables or named ports.
+    #[inline]
+    pub fn builder(alloc: &'a UsbBusAllocator<B>, max_packet_size: u16) -> MidiClassBuilder<'a, B> {
+        MidiClassBuilder::new(alloc, max_packet_size)
+    }
+}
+
+impl<'a, B: UsbBus, const N: usize> MidiClass<'a, B, N> {
+    /// Like [`new`](#method.new), but for use with [`MidiQueues`](struct.MidiQueues.html) of a
+    /// size other than the default, such as `MidiClass::<_, 64>::with_queue_size(&alloc, 64)`.
+    pub fn with_queue_size(
+        alloc: &'a UsbBusAllocator<B>,
+        max_packet_size: u16,
+    ) -> MidiClass<'a, B, N> {
+        MidiClassBuilder::new(alloc, max_packet_size).build()
+    }
+
+    /// Split off queue-backed sender and receiver handles, which can be moved into other tasks.
+    ///
+    /// From then on, the packets are moved between the queues and the endpoints by
+    /// [`poll_queues`](#method.poll_queues), which should be called after every poll of the
+    /// `UsbDevice`.
+    /// Tasks sending events should then trigger a poll (for example by pending the USB interrupt)
+    /// to start the transfer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the max packet size is larger than 64.
+    pub fn split(
+        &mut self,
+        queues: &'a mut MidiQueues<N>,
+    ) -> (MidiSender<'a, N>, MidiReceiver<'a, N>) {
+        assert!(
+            usize::from(self.max_packet_size()) <= MAX_TRANSFER,
+            "queue-backed usb midi handles require a max packet size of at most 64"
+        );
+        let (tx_producer, tx_consumer) = queues.tx.split();
+        let (rx_producer, rx_consumer) = queues.rx.split();
+        self.tx = Some(tx_consumer);
+        self.rx = Some(rx_producer);
+        self.tx_len = 0;
+        self.tx_zlp = false;
+        self.rx_len = 0;
+        (
+            MidiSender { queue: tx_producer },
+            MidiReceiver {
+                queue: rx_consumer,
+                last: [0; 4],
+            },
+        )
     }
 
     /// Gets the maximum packet size in bytes.
@@ -73,6 +171,18 @@ impl<B: UsbBus> MidiClass<'_, B> {
         self.read_ep.max_packet_size()
     }
 
+    /// Gets the amount of cables, which are numbered from 0.
+    #[inline]
+    pub fn cables(&self) -> u8 {
+        self.cables
+    }
+
+    /// Gets the string index allocated for the given slot of `strings`, if it has a name.
+    #[inline]
+    fn string_index(&self, slot: usize) -> Option<StringIndex> {
+        self.strings[slot].map(|(index, _)| index)
+    }
+
     /// Writes a single packet into the IN endpoint.
     pub fn write_packet(&mut self, data: &[u8]) -> UsbResult<usize> {
         self.write_ep.write(data)
@@ -87,94 +197,135 @@ impl<B: UsbBus> MidiClass<'_, B> {
     pub(crate) fn write_ep_address(&self) -> EndpointAddress {
         self.write_ep.address()
     }
+    /// Move received packets into the receiver queue and queued packets out to the host, if the
+    /// class was split.
+    ///
+    /// This is done automatically whenever polling the `UsbDevice` reports USB activity, but
+    /// packets queued while the bus is idle are only sent once this method is called, so it should
+    /// be called right after polling the device.
+    pub fn poll_queues(&mut self) {
+        if let Some(rx) = &mut self.rx {
+            //Only read the next transfer once the previous one is fully queued, so that the host
+            //is throttled
+            'read: loop {
+                while self.rx_pos < self.rx_len {
+                    let packet = &self.rx_buf[self.rx_pos..self.rx_pos + 4];
+                    if rx
+                        .enqueue([packet[0], packet[1], packet[2], packet[3]])
+                        .is_err()
+                    {
+                        break 'read;
+                    }
+                    self.rx_pos += 4;
+                }
+                match self.read_ep.read(&mut self.rx_buf) {
+                    Ok(len) => {
+                        self.rx_pos = 0;
+                        self.rx_len = len - len % 4;
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+        if let Some(tx) = &mut self.tx {
+            let max_len = usize::from(self.write_ep.max_packet_size());
+            while self.tx_len + 4 <= max_len {
+                match tx.dequeue() {
+                    Some(packet) => {
+                        self.tx_buf[self.tx_len..self.tx_len + 4].copy_from_slice(&packet);
+                        self.tx_len += 4;
+                    }
+                    None => break,
+                }
+            }
+            //On failure, typically because the previous transfer is still pending, the packets
+            //are kept for the next poll
+            if self.tx_len > 0 {
+                if self.write_ep.write(&self.tx_buf[..self.tx_len]).is_ok() {
+                    //A full transfer is not processed by the host until a short packet arrives
+                    self.tx_zlp = self.tx_len == max_len;
+                    self.tx_len = 0;
+                }
+            } else if self.tx_zlp && self.write_ep.write(&[]).is_ok() {
+                self.tx_zlp = false;
+            }
+        }
+    }
 }
 
-impl<B: UsbBus> UsbClass<B> for MidiClass<'_, B> {
-    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> UsbResult<()> {
-        // writer.iad(
-        //     self.comm_if,
-        //     2,
-        //     USB_CLASS_AUDIO,
-        //     USB_SUBCLASS_MIDISTREAMING,
-        //     MIDI_PROTOCOL_NONE,
-        // )?;
+impl<B: UsbBus, const N: usize> UsbClass<B> for MidiClass<'_, B, N> {
+    fn reset(&mut self) {
+        self.tx_len = 0;
+        self.tx_zlp = false;
+        self.rx_len = 0;
+    }
 
-        // B.3.1 Standard AC Interface Descriptor
-        writer.interface(self.comm_if, USB_CLASS_AUDIO, 0x01, MIDI_PROTOCOL_NONE)?;
-        // B.3.2 Class-specific AC Interface Descriptor
-        writer.write(CS_INTERFACE, &[0x01, 0x00, 0x01, 0x09, 0x00, 0x01, 0x01])?;
+    #[inline]
+    fn poll(&mut self) {
+        self.poll_queues();
+    }
 
-        writer.interface(
-            self.data_if,
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> UsbResult<()> {
+        // B.3.1 Standard AC Interface Descriptor
+        writer.interface_alt(
+            self.comm_if,
+            0,
             USB_CLASS_AUDIO,
-            USB_SUBCLASS_MIDISTREAMING,
+            USB_SUBCLASS_AUDIOCONTROL,
             MIDI_PROTOCOL_NONE,
+            self.string_index(CONTROL_NAME),
         )?;
+        // B.3.2 Class-specific AC Interface Descriptor
         writer.write(
             CS_INTERFACE,
-            &[
-                MIDI_IN_JACK, // bDescriptorSubtype
-                0x01,         // bJackType
-                0x01,         // bJackId
-                0x00,         // iJack
-            ],
-        )?;
-        writer.write(
-            CS_INTERFACE,
-            &[
-                MIDI_IN_JACK, // bDescriptorSubtype
-                0x02,         // bJackType
-                0x02,         // bJackId
-                0x00,         // iJack
-            ],
+            &descriptor::audio_control_header(self.data_if.into()),
         )?;
 
-        writer.write(
-            CS_INTERFACE,
-            &[
-                MIDI_OUT_JACK, // bDescriptorSubtype
-                0x01,          // bJackType
-                0x03,          // bJackId
-                0x01,          // bNrInputPins
-                0x02,          // BaSourceID
-                0x01,          // BaSourcePin
-                0x00,          // iJack (unused)
-            ],
+        // B.4.1 Standard MS Interface Descriptor
+        writer.interface_alt(
+            self.data_if,
+            0,
+            USB_CLASS_AUDIO,
+            USB_SUBCLASS_MIDISTREAMING,
+            MIDI_PROTOCOL_NONE,
+            self.string_index(STREAMING_NAME),
         )?;
-        writer.write(
-            CS_INTERFACE,
-            &[
-                MIDI_OUT_JACK, // bDescriptorSubtype
-                0x02,          // bJackType
-                0x04,          // bJackId
-                0x01,          // bNrInputPins
-                0x02,          // BaSourceID
-                0x01,          // BaSourcePin
-                0x00,          // iJack (unused)
-            ],
+        // B.4.2 Class-specific MS Interface Descriptor, B.4.3 MIDI IN and B.4.4 MIDI OUT Jack
+        // Descriptors
+        descriptor::write_streaming_interface(
+            self.cables,
+            &self.elements,
+            |cable| {
+                self.string_index(CABLE_NAMES + usize::from(cable))
+                    .map_or(0, u8::from)
+            },
+            |idx| self.string_index(ELEMENT_NAMES + idx).map_or(0, u8::from),
+            |kind, data| writer.write(kind, data),
         )?;
+        // B.5 Bulk OUT and B.6 Bulk IN Endpoint Descriptors
         writer.endpoint(&self.read_ep)?;
         writer.write(
             CS_ENDPOINT,
-            &[
-                0x01, // bDescriptorSubtype (MS_GENERAL)
-                0x01, // bNumEmbMidiJack
-                0x01, // bAssocJackId(1)
-            ],
+            EndpointJacks::out_endpoint(self.cables).as_bytes(),
         )?;
         writer.endpoint(&self.write_ep)?;
         writer.write(
             CS_ENDPOINT,
-            &[
-                0x01, // bDescriptorSubtype (MS_GENERAL)
-                0x01, // bNumEmbMidiJack
-                0x03, // bAssocJackId(1)
-            ],
+            EndpointJacks::in_endpoint(self.cables).as_bytes(),
         )?;
 
         Ok(())
     }
 
+    fn get_string(&self, index: StringIndex, _lang_id: u16) -> Option<&str> {
+        //The names are served for any language, since `usb-device` only announces English
+        self.strings
+            .iter()
+            .flatten()
+            .find(|(string, _)| *string == index)
+            .map(|(_, name)| *name)
+    }
+
     fn control_in(&mut self, xfer: ControlIn<B>) {
         let req = xfer.request();
 
@@ -224,3 +375,262 @@ impl<B: UsbBus> UsbClass<B> for MidiClass<'_, B> {
         };
     }
 }
+
+/// A builder for a [`MidiClass`](struct.MidiClass.html) with several cables, named ports and
+/// elements.
+///
+/// Each cable shows up on the host as a separate MIDI port in each direction, named after the
+/// cable, and each [`Element`](struct.Element.html) tells the host what the device does with the
+/// MIDI data of the cables it is wired to:
+///
+/// ```rust,ignore
+/// let midi = MidiClass::builder(&alloc, 64)
+///     .streaming_interface_name("Synth")
+///     .cable("Synth A")
+///     .cable("Synth B")
+///     .element(
+///         Element::new(ElementCaps::NONE.with(ElementCap::Gm1).with(ElementCap::Gs))
+///             .input(u4::new(0))
+///             .name("GS Synth"),
+///     )
+///     .build();
+/// ```
+///
+/// Without any cable, the class has a single unnamed cable.
+pub struct MidiClassBuilder<'a, B: UsbBus, const N: usize = 16> {
+    alloc: &'a UsbBusAllocator<B>,
+    max_packet_size: u16,
+    cables: u8,
+    elements: HVec<Element<'a>, MAX_ELEMENTS>,
+    names: [Option<&'a str>; ELEMENT_NAMES],
+}
+impl<'a, B: UsbBus, const N: usize> MidiClassBuilder<'a, B, N> {
+    /// Start building a class with the provided UsbBus and max_packet_size in bytes.
+    /// For full-speed devices, max_packet_size has to be one of 8, 16, 32 or 64.
+    #[inline]
+    pub fn new(alloc: &'a UsbBusAllocator<B>, max_packet_size: u16) -> MidiClassBuilder<'a, B, N> {
+        MidiClassBuilder {
+            alloc,
+            max_packet_size,
+            cables: 0,
+            elements: HVec::new(),
+            names: [None; ELEMENT_NAMES],
+        }
+    }
+
+    /// Name the Audio Control interface.
+    #[inline]
+    pub fn control_interface_name(mut self, name: &'a str) -> Self {
+        self.names[CONTROL_NAME] = Some(name);
+        self
+    }
+
+    /// Name the MIDI Streaming interface, which some hosts show as the device name.
+    #[inline]
+    pub fn streaming_interface_name(mut self, name: &'a str) -> Self {
+        self.names[STREAMING_NAME] = Some(name);
+        self
+    }
+
+    /// Add a cable, whose input and output jacks are given the name `name`.
+    ///
+    /// Cables are numbered in the order they are added, starting from 0.
+    /// Each cable adds 32 bytes to the configuration descriptor, which `usb-device` limits to 256
+    /// bytes, so in practice a class can have up to 5 cables.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the class already has 16 cables, the most a USB MIDI packet can address.
+    pub fn cable(mut self, name: &'a str) -> Self {
+        assert!(
+            usize::from(self.cables) < MAX_CABLES,
+            "usb midi supports at most 16 cables"
+        );
+        self.names[CABLE_NAMES + usize::from(self.cables)] = Some(name);
+        self.cables += 1;
+        self
+    }
+
+    /// Add an element, wired to cables that must be added to the builder too.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the class already has 4 elements.
+    pub fn element(mut self, element: Element<'a>) -> Self {
+        assert!(
+            self.elements.push(element).is_ok(),
+            "usb midi class supports at most 4 elements"
+        );
+        self
+    }
+
+    /// Allocate the interfaces, endpoints and strings of the class.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an element is wired to a cable that was not added.
+    pub fn build(self) -> MidiClass<'a, B, N> {
+        let cables = self.cables.max(1);
+        let mask = (1u32 << cables) - 1;
+        assert!(
+            self.elements
+                .iter()
+                .all(|element| u32::from(element.inputs | element.outputs) & !mask == 0),
+            "usb midi element wired to a missing cable"
+        );
+        let alloc = self.alloc;
+        let comm_if = alloc.interface();
+        let comm_ep = alloc.interrupt(8, 255);
+        let data_if = alloc.interface();
+        let read_ep = alloc.bulk(self.max_packet_size);
+        let write_ep = alloc.bulk(self.max_packet_size);
+        let mut strings = [None; STRING_SLOTS];
+        let element_names = self.elements.iter().map(|element| element.name);
+        for (string, name) in strings
+            .iter_mut()
+            .zip(self.names.into_iter().chain(element_names))
+        {
+            *string = name.map(|name| (alloc.string(), name));
+        }
+        MidiClass {
+            comm_if,
+            comm_ep,
+            data_if,
+            read_ep,
+            write_ep,
+            tx: None,
+            rx: None,
+            tx_buf: [0; MAX_TRANSFER],
+            tx_len: 0,
+            tx_zlp: false,
+            rx_buf: [0; MAX_TRANSFER],
+            rx_pos: 0,
+            rx_len: 0,
+            cables,
+            elements: self.elements,
+            strings,
+        }
+    }
+}
+
+/// The storage for the queues of a split [`MidiClass`](struct.MidiClass.html), which should usually
+/// be placed in a `static`.
+///
+/// Each queue holds up to `N - 1` USB MIDI event packets.
+#[derive(Debug)]
+pub struct MidiQueues<const N: usize = 16> {
+    tx: Queue<[u8; 4], N>,
+    rx: Queue<[u8; 4], N>,
+}
+impl<const N: usize> MidiQueues<N> {
+    /// Create empty queues.
+    #[inline]
+    pub const fn new() -> MidiQueues<N> {
+        MidiQueues {
+            tx: Queue::new(),
+            rx: Queue::new(),
+        }
+    }
+}
+impl<const N: usize> Default for MidiQueues<N> {
+    #[inline]
+    fn default() -> MidiQueues<N> {
+        MidiQueues::new()
+    }
+}
+
+/// A handle to send events through a split [`MidiClass`](struct.MidiClass.html), from any task.
+pub struct MidiSender<'a, const N: usize = 16> {
+    queue: Producer<'a, [u8; 4], N>,
+}
+impl<'a, const N: usize> MidiSender<'a, N> {
+    /// Queue a single event on the cable of the given packet.
+    ///
+    /// The code index number of the packet is ignored and derived from the event instead.
+    #[inline]
+    pub fn send(&mut self, packet: &UsbMidiPacket) -> StdResult<(), SendError> {
+        self.send_event(packet.cable_number, &packet.event)
+    }
+
+    /// Queue a single event on the given cable.
+    ///
+    /// SysEx messages are split into as many USB MIDI event packets as necessary, and are either
+    /// queued entirely or not at all.
+    /// Undefined System Common messages with more than 2 data bytes cannot be sent over USB and
+    /// are silently dropped.
+    pub fn send_event(&mut self, cable: u4, event: &LiveEvent) -> StdResult<(), SendError> {
+        let packets = packetize(cable, event);
+        ensure!(
+            self.queue.capacity() - self.queue.len() >= packets.clone().count(),
+            SendError::QueueFull
+        );
+        for packet in packets {
+            self.send_raw(packet)?;
+        }
+        Ok(())
+    }
+
+    /// Queue a raw USB MIDI event packet.
+    #[inline]
+    pub fn send_raw(&mut self, packet: [u8; 4]) -> StdResult<(), SendError> {
+        self.queue.enqueue(packet).map_err(|_| SendError::QueueFull)
+    }
+
+    /// How many packets are waiting to be transmitted.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+/// A handle to receive events from a split [`MidiClass`](struct.MidiClass.html), from any task.
+pub struct MidiReceiver<'a, const N: usize = 16> {
+    queue: Consumer<'a, [u8; 4], N>,
+    /// The last packet received through `receive`, which the returned event borrows from.
+    last: [u8; 4],
+}
+impl<'a, const N: usize> MidiReceiver<'a, N> {
+    /// Take the next complete event out of the queue, if any.
+    ///
+    /// Packets that do not hold a complete message, such as fragments of long SysEx messages or
+    /// packets with a reserved code index number, are skipped.
+    /// Use [`receive_raw`](#method.receive_raw) to reassemble long SysEx messages manually.
+    pub fn receive(&mut self) -> Option<UsbMidiPacket<'_>> {
+        //The payload can only borrow from `last` once the loop is done with it
+        let mut packet = loop {
+            let raw = self.queue.dequeue()?;
+            if let Ok(packet) = UsbMidiPacket::parse(&raw) {
+                self.last = raw;
+                break UsbMidiPacket {
+                    event: packet.event.to_static(),
+                    ..packet
+                };
+            }
+        };
+        //Payloads are made up of all data bytes after the status byte
+        let len = UsbMidiPacket::packet_length(packet.code_index_number);
+        let data = u7::slice_from_int(&self.last[2..=len]);
+        packet.event = match packet.event {
+            LiveEvent::Common(SystemCommon::SysEx(_)) => {
+                LiveEvent::Common(SystemCommon::SysEx(data))
+            }
+            LiveEvent::Common(SystemCommon::Undefined(status, _)) => {
+                LiveEvent::Common(SystemCommon::Undefined(status, data))
+            }
+            event => event,
+        };
+        Some(packet)
+    }
+
+    /// Take the next raw USB MIDI event packet out of the queue, if any.
+    #[inline]
+    pub fn receive_raw(&mut self) -> Option<[u8; 4]> {
+        self.queue.dequeue()
+    }
+
+    /// How many packets are waiting to be received.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
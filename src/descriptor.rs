@@ -0,0 +1,346 @@
+//! The class-specific descriptors of a USB MIDI 1.0 device, shared by all USB backends.
+//!
+//! The device exposes an Audio Control interface followed by a MIDI Streaming interface with a bulk
+//! endpoint in each direction.
+//! Each cable of the MIDI Streaming interface is made of one embedded and one external jack in each
+//! direction.
+//! Backends write the standard interface and endpoint descriptors themselves, and the
+//! class-specific ones through the functions in this module.
+//! Optionally, MIDI Elements describe the functional blocks of the device that are wired to the
+//! cables, along with their capabilities.
+
+use crate::num::u4;
+
+/// This should be used as `device_class` when building the USB device.
+pub const USB_CLASS_AUDIO: u8 = 0x01;
+/// The subclass of the Audio Control interface.
+pub const USB_SUBCLASS_AUDIOCONTROL: u8 = 0x01;
+/// The subclass of the MIDI Streaming interface.
+pub const USB_SUBCLASS_MIDISTREAMING: u8 = 0x03;
+/// The protocol of both interfaces.
+pub const MIDI_PROTOCOL_NONE: u8 = 0x00;
+
+//Class-specific descriptor types
+pub(crate) const CS_INTERFACE: u8 = 0x24;
+pub(crate) const CS_ENDPOINT: u8 = 0x25;
+
+//Class-specific descriptor subtypes
+const HEADER: u8 = 0x01;
+const MIDI_IN_JACK: u8 = 0x02;
+const MIDI_OUT_JACK: u8 = 0x03;
+const ELEMENT: u8 = 0x04;
+const MS_GENERAL: u8 = 0x01;
+
+//Jack types
+const EMBEDDED: u8 = 0x01;
+const EXTERNAL: u8 = 0x02;
+
+//Jack ids of the first cable, the jacks of cable `n` are offset by `4 * n`
+const EMBEDDED_IN_JACK: u8 = 0x01;
+const EXTERNAL_IN_JACK: u8 = 0x02;
+const EMBEDDED_OUT_JACK: u8 = 0x03;
+const EXTERNAL_OUT_JACK: u8 = 0x04;
+
+/// The maximum amount of cables in a MIDI Streaming interface.
+pub(crate) const MAX_CABLES: usize = 16;
+/// The maximum amount of elements in a MIDI Streaming interface.
+pub(crate) const MAX_ELEMENTS: usize = 4;
+
+/// The size of `bmElementCaps`, which holds up to 16 capabilities.
+const ELEMENT_CAPS_SIZE: u8 = 2;
+
+/// The length of a standard (non-audio) endpoint descriptor, as written by the backends.
+const ENDPOINT_LEN: u16 = 7;
+
+/// The class-specific Audio Control interface header, pointing at the MIDI Streaming interface.
+pub(crate) fn audio_control_header(streaming_interface: u8) -> [u8; 7] {
+    [
+        HEADER,              // bDescriptorSubtype
+        0x00,                // bcdADC
+        0x01,                //
+        0x09,                // wTotalLength
+        0x00,                //
+        0x01,                // bInCollection
+        streaming_interface, // baInterfaceNr(1)
+    ]
+}
+
+/// A capability of an [`Element`](struct.Element.html), as listed in its `bmElementCaps` field.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum ElementCap {
+    /// Some capability that is not defined by the specification.
+    Custom,
+    /// Responds to or generates MIDI Clock.
+    MidiClock,
+    /// Synchronizes to or generates MIDI Time Code.
+    Mtc,
+    /// Responds to or generates MIDI Machine Control.
+    Mmc,
+    /// A General MIDI System Level 1 synthesizer.
+    Gm1,
+    /// A General MIDI System Level 2 synthesizer.
+    Gm2,
+    /// A Roland GS synthesizer.
+    Gs,
+    /// A Yamaha XG synthesizer.
+    Xg,
+    /// An effects processor.
+    Efx,
+    /// A MIDI patch bay.
+    PatchBay,
+    /// Supports Downloadable Sounds Level 1.
+    Dls1,
+    /// Supports Downloadable Sounds Level 2.
+    Dls2,
+}
+
+/// The set of capabilities of an [`Element`](struct.Element.html).
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Default)]
+pub struct ElementCaps(u16);
+impl ElementCaps {
+    /// No capabilities at all.
+    pub const NONE: ElementCaps = ElementCaps(0);
+
+    /// Also advertise the given capability.
+    #[inline]
+    pub const fn with(self, cap: ElementCap) -> ElementCaps {
+        ElementCaps(self.0 | 1 << cap as u8)
+    }
+
+    /// Whether the given capability is advertised.
+    #[inline]
+    pub fn contains(self, cap: ElementCap) -> bool {
+        self.0 & 1 << cap as u8 != 0
+    }
+
+    /// The raw `bmElementCaps` bitmap.
+    #[inline]
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+}
+
+/// A functional block inside the device, such as a synthesizer or a sequencer, described to the
+/// host through a MIDI Element descriptor.
+///
+/// Elements are wired to the USB side of cables: each input cable feeds an input pin of the
+/// element with the MIDI data sent by the host, and each output pin of the element feeds an output
+/// cable with MIDI data for the host.
+/// Pins are numbered in increasing cable order.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Element<'a> {
+    /// The capabilities of the element.
+    pub caps: ElementCaps,
+    /// A bitmask of the cables that feed the input pins, where bit `n` corresponds to cable `n`.
+    pub inputs: u16,
+    /// A bitmask of the cables fed by the output pins, where bit `n` corresponds to cable `n`.
+    pub outputs: u16,
+    /// The name of the element, if any.
+    pub name: Option<&'a str>,
+}
+impl<'a> Element<'a> {
+    /// Create an element with the given capabilities and no pins.
+    #[inline]
+    pub fn new(caps: ElementCaps) -> Element<'a> {
+        Element {
+            caps,
+            inputs: 0,
+            outputs: 0,
+            name: None,
+        }
+    }
+
+    /// Add an input pin fed by the given cable.
+    #[inline]
+    pub fn input(mut self, cable: u4) -> Element<'a> {
+        self.inputs |= 1 << cable.as_int();
+        self
+    }
+
+    /// Add an output pin that feeds the given cable.
+    #[inline]
+    pub fn output(mut self, cable: u4) -> Element<'a> {
+        self.outputs |= 1 << cable.as_int();
+        self
+    }
+
+    /// Name the element.
+    #[inline]
+    pub fn name(mut self, name: &'a str) -> Element<'a> {
+        self.name = Some(name);
+        self
+    }
+
+    /// The output pin number that feeds the given cable, if any.
+    #[inline]
+    fn output_pin(&self, cable: u8) -> Option<u8> {
+        if self.outputs & 1 << cable == 0 {
+            return None;
+        }
+        Some((self.outputs & ((1 << cable) - 1)).count_ones() as u8 + 1)
+    }
+}
+
+/// The cables in the given bitmask, in increasing order.
+fn cables_in(mask: u16) -> impl Iterator<Item = u8> {
+    (0..MAX_CABLES as u8).filter(move |cable| mask & 1 << cable != 0)
+}
+
+/// The class-specific descriptor that follows an endpoint, listing its embedded jacks.
+pub(crate) struct EndpointJacks {
+    buf: [u8; 2 + MAX_CABLES],
+    len: usize,
+}
+impl EndpointJacks {
+    fn new(first_jack: u8, cables: u8) -> EndpointJacks {
+        let mut buf = [0; 2 + MAX_CABLES];
+        buf[0] = MS_GENERAL; // bDescriptorSubtype
+        buf[1] = cables; // bNumEmbMIDIJack
+        for cable in 0..cables {
+            buf[2 + cable as usize] = first_jack + 4 * cable; // baAssocJackID
+        }
+        EndpointJacks {
+            buf,
+            len: 2 + cables as usize,
+        }
+    }
+
+    /// The class-specific descriptor that follows the OUT endpoint (host to device), which feeds
+    /// the embedded IN jacks.
+    #[inline]
+    pub(crate) fn out_endpoint(cables: u8) -> EndpointJacks {
+        EndpointJacks::new(EMBEDDED_IN_JACK, cables)
+    }
+
+    /// The class-specific descriptor that follows the IN endpoint (device to host), which is fed by
+    /// the embedded OUT jacks.
+    #[inline]
+    pub(crate) fn in_endpoint(cables: u8) -> EndpointJacks {
+        EndpointJacks::new(EMBEDDED_OUT_JACK, cables)
+    }
+
+    #[inline]
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Write the class-specific MIDI Streaming interface descriptors: the header, the jacks of each
+/// cable and the elements.
+///
+/// The elements must only refer to existing cables.
+/// `jack_string` receives a cable number and returns the string index (or 0 for none) of its
+/// jacks, and `element_string` does the same for the index of an element.
+/// `write` receives the descriptor type and the descriptor contents after the type byte.
+pub(crate) fn write_streaming_interface<E>(
+    cables: u8,
+    elements: &[Element],
+    jack_string: impl Fn(u8) -> u8,
+    element_string: impl Fn(usize) -> u8,
+    mut write: impl FnMut(u8, &[u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    //The total length covers the header, the jacks, the elements and both endpoints along with
+    //their class-specific descriptors, each descriptor being prefixed by its length and type
+    const IN_JACK_LEN: u16 = 6;
+    const OUT_JACK_LEN: u16 = 9;
+    const ELEMENT_LEN: u16 = 10 + ELEMENT_CAPS_SIZE as u16;
+    let element_len: u16 = elements
+        .iter()
+        .map(|element| {
+            //Each output pin is also listed as a source by the jack it feeds
+            let pins = element.inputs.count_ones() + element.outputs.count_ones();
+            ELEMENT_LEN + 2 * pins as u16
+        })
+        .sum();
+    let total_len = (2 + 5)
+        + u16::from(cables) * 2 * (IN_JACK_LEN + OUT_JACK_LEN)
+        + element_len
+        + 2 * (ENDPOINT_LEN + 2 + 2 + u16::from(cables));
+    let total_len = total_len.to_le_bytes();
+    write(
+        CS_INTERFACE,
+        &[
+            HEADER,       // bDescriptorSubtype
+            0x00,         // bcdMSC
+            0x01,         //
+            total_len[0], // wTotalLength
+            total_len[1], //
+        ],
+    )?;
+    for cable in 0..cables {
+        let offset = 4 * cable;
+        let string = jack_string(cable);
+        write(
+            CS_INTERFACE,
+            &[MIDI_IN_JACK, EMBEDDED, EMBEDDED_IN_JACK + offset, string],
+        )?;
+        write(
+            CS_INTERFACE,
+            &[MIDI_IN_JACK, EXTERNAL, EXTERNAL_IN_JACK + offset, string],
+        )?;
+        //The embedded OUT jack is fed by the external IN jack and by the elements that output to
+        //this cable
+        let mut jack = [0; 4 + 2 * (1 + MAX_ELEMENTS) + 1];
+        jack[..6].copy_from_slice(&[
+            MIDI_OUT_JACK,
+            EMBEDDED,
+            EMBEDDED_OUT_JACK + offset,
+            0x01,                      // bNrInputPins
+            EXTERNAL_IN_JACK + offset, // baSourceID(1)
+            0x01,                      // baSourcePin(1)
+        ]);
+        let mut len = 6;
+        for (idx, element) in elements.iter().enumerate() {
+            if let Some(pin) = element.output_pin(cable) {
+                jack[3] += 1;
+                jack[len] = element_id(cables, idx); // baSourceID
+                jack[len + 1] = pin; // baSourcePin
+                len += 2;
+            }
+        }
+        jack[len] = string; // iJack
+        write(CS_INTERFACE, &jack[..len + 1])?;
+        write(
+            CS_INTERFACE,
+            &[
+                MIDI_OUT_JACK,
+                EXTERNAL,
+                EXTERNAL_OUT_JACK + offset,
+                0x01,                      // bNrInputPins
+                EMBEDDED_IN_JACK + offset, // baSourceID(1)
+                0x01,                      // baSourcePin(1)
+                string,                    // iJack
+            ],
+        )?;
+    }
+    for (idx, element) in elements.iter().enumerate() {
+        let mut desc = [0; 3 + 2 * MAX_CABLES + 7];
+        desc[0] = ELEMENT; // bDescriptorSubtype
+        desc[1] = element_id(cables, idx); // bElementID
+        desc[2] = element.inputs.count_ones() as u8; // bNrInputPins
+        let mut len = 3;
+        for cable in cables_in(element.inputs) {
+            desc[len] = EMBEDDED_IN_JACK + 4 * cable; // baSourceID
+            desc[len + 1] = 0x01; // baSourcePin
+            len += 2;
+        }
+        let caps = element.caps.bits().to_le_bytes();
+        desc[len..len + 7].copy_from_slice(&[
+            element.outputs.count_ones() as u8, // bNrOutputPins
+            0x00,                               // bInTerminalLink
+            0x00,                               // bOutTerminalLink
+            ELEMENT_CAPS_SIZE,                  // bElCapsSize
+            caps[0],                            // bmElementCaps
+            caps[1],                            //
+            element_string(idx),                // iElement
+        ]);
+        write(CS_INTERFACE, &desc[..len + 7])?;
+    }
+    Ok(())
+}
+
+/// The id of the element at the given index, which comes after the ids of all jacks.
+#[inline]
+fn element_id(cables: u8, idx: usize) -> u8 {
+    4 * cables + 1 + idx as u8
+}
@@ -0,0 +1,1030 @@
+//! A human-readable, line-oriented representation of Standard Midi Files, meant for keeping MIDI
+//! fixtures in version control and reviewing changes to them.
+//!
+//! A [`Dump`](struct.Dump.html) can be created out of raw SMF bytes, printed as text with its
+//! `Display` implementation, read back with [`Dump::from_text`](struct.Dump.html#method.from_text)
+//! and encoded back into the exact same bytes with
+//! [`Dump::to_bytes`](struct.Dump.html#method.to_bytes).
+//! With the `serde` feature enabled, dumps can also be serialized into any `serde` format, such as
+//! JSON.
+//!
+//! The text format has one item per line, with comma-separated fields:
+//!
+//! ```text
+//! Header, 1, 2, 480
+//! Track, 0
+//! 0, 0, Tempo, 500000
+//! 0, 0, EndOfTrack
+//! Track, 1
+//! 1, 0, Text, 3, "Piano"
+//! 1, 0, NoteOn, 0, 60, 100
+//! 1, 480, NoteOn, 0, 60, 0, running
+//! 1, 480, SysEx, 7e7f0901f7
+//! 1, 480, EndOfTrack
+//! ```
+//!
+//! The header line holds the format, the declared track count and the raw timing division.
+//! Each track starts with a `Track` line, followed by its events, each one starting with the track
+//! index and the absolute tick of the event.
+//! MIDI events that make use of running status are marked with a trailing `running` field.
+//! Events that cannot be represented exactly in a readable way (for example, meta events with
+//! extra data) are stored as `Raw` events, holding the hex-encoded bytes of the whole event,
+//! including its delta time.
+//! Empty lines and lines starting with `#` are ignored.
+//!
+//! Only the header and track chunks are kept: RIFF wrappers and unknown chunks are dropped.
+
+#![cfg(feature = "alloc")]
+
+use crate::{
+    event::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind},
+    prelude::*,
+    primitive::read_varlen_slice,
+    smf::{self, EventIter, Smf},
+};
+use core::str;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The contents of a Standard Midi File, in a form that can be printed and read back as text, or
+/// serialized with `serde`.
+///
+/// See the [module-level documentation](index.html) for the text format.
+///
+/// This type is only available with the `alloc` feature enabled.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Dump {
+    /// The raw format of the file: `0`, `1` or `2`.
+    pub format: u16,
+    /// The track count declared in the header, which may not match the actual amount of tracks in
+    /// malformed files.
+    pub track_count: u16,
+    /// The raw timing division, as stored in the header.
+    pub division: u16,
+    /// The events of each track.
+    pub tracks: Vec<Vec<DumpEvent>>,
+}
+
+/// A single event within a [`Dump`](struct.Dump.html).
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DumpEvent {
+    /// The absolute time of the event, in ticks since the start of the track.
+    pub tick: u64,
+    /// The event itself.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub kind: DumpKind,
+}
+
+/// The different kinds of events within a [`Dump`](struct.Dump.html).
+///
+/// MIDI events have a `running` flag, indicating that the event omits its status byte and uses
+/// running status instead.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type"))]
+pub enum DumpKind {
+    /// Stop playing a note.
+    NoteOff {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The key to stop playing.
+        key: u8,
+        /// The release velocity.
+        vel: u8,
+        /// Whether the event uses running status.
+        #[cfg_attr(feature = "serde", serde(default))]
+        running: bool,
+    },
+    /// Start playing a note, or stop it if the velocity is zero.
+    NoteOn {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The key to start playing.
+        key: u8,
+        /// The velocity with which to press the key.
+        vel: u8,
+        /// Whether the event uses running status.
+        #[cfg_attr(feature = "serde", serde(default))]
+        running: bool,
+    },
+    /// Modify the velocity of a note after it has been played.
+    Aftertouch {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The key whose velocity changes.
+        key: u8,
+        /// The new velocity.
+        vel: u8,
+        /// Whether the event uses running status.
+        #[cfg_attr(feature = "serde", serde(default))]
+        running: bool,
+    },
+    /// Change a controller value.
+    Controller {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The controller number.
+        controller: u8,
+        /// The new controller value.
+        value: u8,
+        /// Whether the event uses running status.
+        #[cfg_attr(feature = "serde", serde(default))]
+        running: bool,
+    },
+    /// Change the program (instrument) of a channel.
+    ProgramChange {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The new program number.
+        program: u8,
+        /// Whether the event uses running status.
+        #[cfg_attr(feature = "serde", serde(default))]
+        running: bool,
+    },
+    /// Change the velocity of all notes in a channel.
+    ChannelAftertouch {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The new velocity.
+        vel: u8,
+        /// Whether the event uses running status.
+        #[cfg_attr(feature = "serde", serde(default))]
+        running: bool,
+    },
+    /// A pitch bend, with its raw 14-bit value.
+    PitchBend {
+        /// The MIDI channel, from 0 to 15.
+        channel: u8,
+        /// The raw bend value, where `0x2000` is no bend.
+        bend: u16,
+        /// Whether the event uses running status.
+        #[cfg_attr(feature = "serde", serde(default))]
+        running: bool,
+    },
+    /// A SysEx event, excluding the `0xF0` prefix.
+    SysEx {
+        /// The data of the event, including the trailing `0xF7` if present.
+        data: Vec<u8>,
+    },
+    /// An escape sequence.
+    Escape {
+        /// The raw bytes to send.
+        data: Vec<u8>,
+    },
+    /// The end of the track.
+    EndOfTrack,
+    /// A tempo change, in microseconds per beat.
+    Tempo {
+        /// The new tempo.
+        tempo: u32,
+    },
+    /// A time signature change.
+    TimeSignature {
+        /// The top number of the time signature.
+        numerator: u8,
+        /// The bottom number of the time signature, as a power of two.
+        denominator: u8,
+        /// The amount of MIDI clocks per metronome click.
+        clocks_per_click: u8,
+        /// The amount of 32nd notes in a quarter note.
+        notes_per_quarter: u8,
+    },
+    /// A key signature change.
+    KeySignature {
+        /// The amount of sharps, or flats if negative.
+        sharps: i8,
+        /// Whether the key is minor rather than major.
+        minor: bool,
+    },
+    /// A text meta event (types `0x01` to `0x09`) holding valid UTF-8.
+    Text {
+        /// The meta message type byte.
+        meta_type: u8,
+        /// The text itself.
+        text: String,
+    },
+    /// Any other meta event.
+    Meta {
+        /// The meta message type byte.
+        meta_type: u8,
+        /// The payload of the meta message.
+        data: Vec<u8>,
+    },
+    /// The raw bytes of an event, including its delta time.
+    Raw {
+        /// The raw bytes.
+        data: Vec<u8>,
+    },
+}
+
+impl Dump {
+    /// Create a dump out of the raw bytes of a Standard Midi File.
+    ///
+    /// Encoding the dump with [`to_bytes`](#method.to_bytes) yields the same bytes back, as long
+    /// as the file consists only of a header and well-formed tracks.
+    pub fn read(raw: &[u8]) -> Result<Dump> {
+        let (header, tracks) = smf::parse(raw)?;
+        let track_count = tracks.track_count_hint;
+        let header = header.encode(track_count);
+        Ok(Dump {
+            format: u16::from_be_bytes([header[0], header[1]]),
+            track_count,
+            division: u16::from_be_bytes([header[4], header[5]]),
+            tracks: tracks
+                .map(|events| dump_track(events?))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Create a dump out of a parsed `Smf`, using running status wherever the writer would.
+    pub fn from_smf(smf: &Smf) -> Result<Dump> {
+        let mut raw = Vec::new();
+        smf.write(&mut raw)
+            .map_err(|_| err_invalid!("failed to encode smf"))?;
+        Dump::read(&raw)
+    }
+
+    /// Encode the dump back into a Standard Midi File.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"MThd");
+        out.extend_from_slice(&6u32.to_be_bytes());
+        out.extend_from_slice(&self.format.to_be_bytes());
+        out.extend_from_slice(&self.track_count.to_be_bytes());
+        out.extend_from_slice(&self.division.to_be_bytes());
+        let mut buf = Vec::new();
+        for (idx, track) in self.tracks.iter().enumerate() {
+            buf.clear();
+            let mut tick = 0;
+            let mut running_status = None;
+            for (event, ev) in track.iter().enumerate() {
+                ev.encode(&mut tick, &mut running_status, &mut buf)
+                    .map_err(|err| err.locate(out.len() + 8 + buf.len(), Some(idx), Some(event)))?;
+            }
+            let len = u32::try_from(buf.len()).map_err(|_| err_invalid!("track too large"))?;
+            out.extend_from_slice(b"MTrk");
+            out.extend_from_slice(&len.to_be_bytes());
+            out.extend_from_slice(&buf);
+        }
+        Ok(out)
+    }
+
+    /// Read a dump back from its text form.
+    ///
+    /// Errors are located at the byte offset of the offending line within the text.
+    pub fn from_text(text: &str) -> Result<Dump> {
+        let mut dump = None;
+        let mut offset = 0;
+        for line in text.split('\n') {
+            let start = offset;
+            offset += line.len() + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            read_line(line, &mut dump).map_err(|err| err.locate(start, None, None))?;
+        }
+        dump.ok_or_else(|| err_invalid!("missing header line").into())
+    }
+}
+
+impl fmt::Display for Dump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Header, {}, {}, {}",
+            self.format, self.track_count, self.division
+        )?;
+        for (idx, track) in self.tracks.iter().enumerate() {
+            writeln!(f, "Track, {}", idx)?;
+            for ev in track {
+                writeln!(f, "{}, {}", idx, ev)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DumpEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DumpKind::*;
+        let midi = |f: &mut fmt::Formatter, name, channel, data: &[u16], running| {
+            write!(f, "{}, {}, {}", self.tick, name, channel)?;
+            for d in data {
+                write!(f, ", {}", d)?;
+            }
+            if running {
+                write!(f, ", running")?;
+            }
+            Ok(())
+        };
+        match &self.kind {
+            NoteOff {
+                channel,
+                key,
+                vel,
+                running,
+            } => midi(f, "NoteOff", channel, &[*key as u16, *vel as u16], *running),
+            NoteOn {
+                channel,
+                key,
+                vel,
+                running,
+            } => midi(f, "NoteOn", channel, &[*key as u16, *vel as u16], *running),
+            Aftertouch {
+                channel,
+                key,
+                vel,
+                running,
+            } => midi(
+                f,
+                "Aftertouch",
+                channel,
+                &[*key as u16, *vel as u16],
+                *running,
+            ),
+            Controller {
+                channel,
+                controller,
+                value,
+                running,
+            } => midi(
+                f,
+                "Controller",
+                channel,
+                &[*controller as u16, *value as u16],
+                *running,
+            ),
+            ProgramChange {
+                channel,
+                program,
+                running,
+            } => midi(f, "ProgramChange", channel, &[*program as u16], *running),
+            ChannelAftertouch {
+                channel,
+                vel,
+                running,
+            } => midi(f, "ChannelAftertouch", channel, &[*vel as u16], *running),
+            PitchBend {
+                channel,
+                bend,
+                running,
+            } => midi(f, "PitchBend", channel, &[*bend], *running),
+            SysEx { data } => write!(f, "{}, SysEx, {}", self.tick, Hex(data)),
+            Escape { data } => write!(f, "{}, Escape, {}", self.tick, Hex(data)),
+            EndOfTrack => write!(f, "{}, EndOfTrack", self.tick),
+            Tempo { tempo } => write!(f, "{}, Tempo, {}", self.tick, tempo),
+            TimeSignature {
+                numerator,
+                denominator,
+                clocks_per_click,
+                notes_per_quarter,
+            } => write!(
+                f,
+                "{}, TimeSignature, {}, {}, {}, {}",
+                self.tick, numerator, denominator, clocks_per_click, notes_per_quarter
+            ),
+            KeySignature { sharps, minor } => write!(
+                f,
+                "{}, KeySignature, {}, {}",
+                self.tick,
+                sharps,
+                if *minor { "minor" } else { "major" }
+            ),
+            Text { meta_type, text } => {
+                write!(f, "{}, Text, {}, \"", self.tick, meta_type)?;
+                for c in text.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\r' => f.write_str("\\r")?,
+                        '\t' => f.write_str("\\t")?,
+                        c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Meta { meta_type, data } => {
+                write!(f, "{}, Meta, {}, {}", self.tick, meta_type, Hex(data))
+            }
+            Raw { data } => write!(f, "{}, Raw, {}", self.tick, Hex(data)),
+        }
+    }
+}
+
+impl DumpKind {
+    /// Build the readable form of an event out of its raw bytes, without checking whether it
+    /// encodes back into the same bytes.
+    fn new(raw: &[u8], ev: &TrackEvent) -> DumpKind {
+        let running = {
+            let mut raw = raw;
+            let _delta = u28::read_varlen(&mut raw, false);
+            raw.first().map(|&status| status < 0x80).unwrap_or(false)
+        };
+        match ev.kind {
+            TrackEventKind::Midi { channel, message } => {
+                let channel = channel.as_int();
+                match message {
+                    MidiMessage::NoteOff { key, vel } => DumpKind::NoteOff {
+                        channel,
+                        key: key.as_int(),
+                        vel: vel.as_int(),
+                        running,
+                    },
+                    MidiMessage::NoteOn { key, vel } => DumpKind::NoteOn {
+                        channel,
+                        key: key.as_int(),
+                        vel: vel.as_int(),
+                        running,
+                    },
+                    MidiMessage::Aftertouch { key, vel } => DumpKind::Aftertouch {
+                        channel,
+                        key: key.as_int(),
+                        vel: vel.as_int(),
+                        running,
+                    },
+                    MidiMessage::Controller { controller, value } => DumpKind::Controller {
+                        channel,
+                        controller: controller.as_int(),
+                        value: value.as_int(),
+                        running,
+                    },
+                    MidiMessage::ProgramChange { program } => DumpKind::ProgramChange {
+                        channel,
+                        program: program.as_int(),
+                        running,
+                    },
+                    MidiMessage::ChannelAftertouch { vel } => DumpKind::ChannelAftertouch {
+                        channel,
+                        vel: vel.as_int(),
+                        running,
+                    },
+                    MidiMessage::PitchBend { bend } => DumpKind::PitchBend {
+                        channel,
+                        bend: bend.0.as_int(),
+                        running,
+                    },
+                }
+            }
+            TrackEventKind::SysEx(data) => DumpKind::SysEx {
+                data: data.to_vec(),
+            },
+            TrackEventKind::Escape(data) => DumpKind::Escape {
+                data: data.to_vec(),
+            },
+            TrackEventKind::Meta(_) => {
+                //Use the raw meta data, since some meta messages are lossy
+                let meta = {
+                    let mut raw = raw;
+                    let _delta = u28::read_varlen(&mut raw, false);
+                    match raw {
+                        [0xFF, meta_type, rest @ ..] => {
+                            let mut rest = rest;
+                            read_varlen_slice(&mut rest, false)
+                                .ok()
+                                .map(|data| (*meta_type, data))
+                        }
+                        _ => None,
+                    }
+                };
+                match meta {
+                    Some((0x2F, [])) => DumpKind::EndOfTrack,
+                    Some((0x51, &[a, b, c])) => DumpKind::Tempo {
+                        tempo: u32::from_be_bytes([0, a, b, c]),
+                    },
+                    Some((
+                        0x58,
+                        &[numerator, denominator, clocks_per_click, notes_per_quarter],
+                    )) => DumpKind::TimeSignature {
+                        numerator,
+                        denominator,
+                        clocks_per_click,
+                        notes_per_quarter,
+                    },
+                    Some((0x59, &[sharps, minor])) if minor <= 1 => DumpKind::KeySignature {
+                        sharps: sharps as i8,
+                        minor: minor == 1,
+                    },
+                    Some((meta_type @ 0x01..=0x09, data)) if str::from_utf8(data).is_ok() => {
+                        DumpKind::Text {
+                            meta_type,
+                            text: String::from_utf8_lossy(data).into_owned(),
+                        }
+                    }
+                    Some((meta_type, data)) => DumpKind::Meta {
+                        meta_type,
+                        data: data.to_vec(),
+                    },
+                    None => DumpKind::Raw { data: raw.to_vec() },
+                }
+            }
+        }
+    }
+
+    /// Get the status and the message of a MIDI event, along with its running status flag.
+    fn midi(&self) -> Result<Option<(u8, MidiMessage, bool)>> {
+        use self::DumpKind::*;
+        let u7 = |v: u8| u7::try_from(v).ok_or(err_invalid!("midi data byte out of range"));
+        let (channel, message, running) = match *self {
+            NoteOff {
+                channel,
+                key,
+                vel,
+                running,
+            } => (
+                channel,
+                MidiMessage::NoteOff {
+                    key: u7(key)?,
+                    vel: u7(vel)?,
+                },
+                running,
+            ),
+            NoteOn {
+                channel,
+                key,
+                vel,
+                running,
+            } => (
+                channel,
+                MidiMessage::NoteOn {
+                    key: u7(key)?,
+                    vel: u7(vel)?,
+                },
+                running,
+            ),
+            Aftertouch {
+                channel,
+                key,
+                vel,
+                running,
+            } => (
+                channel,
+                MidiMessage::Aftertouch {
+                    key: u7(key)?,
+                    vel: u7(vel)?,
+                },
+                running,
+            ),
+            Controller {
+                channel,
+                controller,
+                value,
+                running,
+            } => (
+                channel,
+                MidiMessage::Controller {
+                    controller: u7(controller)?,
+                    value: u7(value)?,
+                },
+                running,
+            ),
+            ProgramChange {
+                channel,
+                program,
+                running,
+            } => (
+                channel,
+                MidiMessage::ProgramChange {
+                    program: u7(program)?,
+                },
+                running,
+            ),
+            ChannelAftertouch {
+                channel,
+                vel,
+                running,
+            } => (
+                channel,
+                MidiMessage::ChannelAftertouch { vel: u7(vel)? },
+                running,
+            ),
+            PitchBend {
+                channel,
+                bend,
+                running,
+            } => (
+                channel,
+                MidiMessage::PitchBend {
+                    bend: crate::PitchBend(
+                        u14::try_from(bend).ok_or(err_invalid!("pitch bend out of range"))?,
+                    ),
+                },
+                running,
+            ),
+            _ => return Ok(None),
+        };
+        let channel = u4::try_from(channel).ok_or(err_invalid!("midi channel out of range"))?;
+        Ok(Some((
+            message.status_nibble() << 4 | channel.as_int(),
+            message,
+            running,
+        )))
+    }
+}
+
+impl DumpEvent {
+    /// Encode the event, given the absolute tick and running status left by the previous event.
+    fn encode(
+        &self,
+        tick: &mut u64,
+        running_status: &mut Option<u8>,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        if let DumpKind::Raw { data } = &self.kind {
+            let mut raw = &data[..];
+            let ev = TrackEvent::read(&mut raw, running_status, false)
+                .context(err_invalid!("invalid raw event"))?;
+            ensure!(raw.is_empty(), err_invalid!("raw event has trailing bytes"));
+            *tick += ev.delta.as_int() as u64;
+            ensure!(
+                *tick == self.tick,
+                err_invalid!("raw event delta does not match its tick")
+            );
+            out.extend_from_slice(data);
+            return Ok(());
+        }
+        let delta = self
+            .tick
+            .checked_sub(*tick)
+            .ok_or(err_invalid!("event ticks must not decrease"))?;
+        let delta = u32::try_from(delta)
+            .ok()
+            .and_then(u28::try_from)
+            .ok_or(err_invalid!("event delta time too large"))?;
+        *tick = self.tick;
+        let kind = match &self.kind {
+            DumpKind::SysEx { data } => TrackEventKind::SysEx(data),
+            DumpKind::Escape { data } => TrackEventKind::Escape(data),
+            DumpKind::EndOfTrack => TrackEventKind::Meta(MetaMessage::EndOfTrack),
+            DumpKind::Tempo { tempo } => TrackEventKind::Meta(MetaMessage::Tempo(
+                u24::try_from(*tempo).ok_or(err_invalid!("tempo out of range"))?,
+            )),
+            &DumpKind::TimeSignature {
+                numerator,
+                denominator,
+                clocks_per_click,
+                notes_per_quarter,
+            } => TrackEventKind::Meta(MetaMessage::TimeSignature(
+                numerator,
+                denominator,
+                clocks_per_click,
+                notes_per_quarter,
+            )),
+            &DumpKind::KeySignature { sharps, minor } => {
+                TrackEventKind::Meta(MetaMessage::KeySignature(sharps, minor))
+            }
+            DumpKind::Text { meta_type, text } => {
+                TrackEventKind::Meta(MetaMessage::Unknown(*meta_type, text.as_bytes()))
+            }
+            DumpKind::Meta { meta_type, data } => {
+                TrackEventKind::Meta(MetaMessage::Unknown(*meta_type, data))
+            }
+            _ => {
+                let (status, message, running) = self.kind.midi()?.expect("event must be midi");
+                if running {
+                    ensure!(
+                        *running_status == Some(status),
+                        err_invalid!("running status does not match the previous event")
+                    );
+                } else {
+                    //Force the status byte to be written
+                    *running_status = None;
+                }
+                TrackEventKind::Midi {
+                    channel: u4::from_int_lossy(status & 0xF),
+                    message,
+                }
+            }
+        };
+        TrackEvent { delta, kind }
+            .write(running_status, out)
+            .map_err(|_| err_invalid!("failed to encode event"))?;
+        Ok(())
+    }
+}
+
+/// Dump a track, keeping the readable form of each event only if it encodes back exactly.
+fn dump_track(mut events: EventIter) -> Result<Vec<DumpEvent>> {
+    let mut dumped = Vec::new();
+    let mut tick = 0;
+    let mut running_status = None;
+    let mut encoded = Vec::new();
+    loop {
+        let before = events.unread();
+        let ev = match events.next() {
+            Some(ev) => ev?,
+            None => break,
+        };
+        let raw = &before[..before.len() - events.unread().len()];
+        let mut dumped_ev = DumpEvent {
+            tick: tick + ev.delta.as_int() as u64,
+            kind: DumpKind::new(raw, &ev),
+        };
+        encoded.clear();
+        let exact = dumped_ev
+            .encode(&mut tick.clone(), &mut running_status.clone(), &mut encoded)
+            .is_ok()
+            && encoded == raw;
+        if !exact {
+            dumped_ev.kind = DumpKind::Raw { data: raw.to_vec() };
+        }
+        tick = dumped_ev.tick;
+        running_status = events.running_status();
+        dumped.push(dumped_ev);
+    }
+    Ok(dumped)
+}
+
+/// Read a single non-empty line of the text form.
+fn read_line(line: &str, dump: &mut Option<Dump>) -> Result<()> {
+    let fields = split_fields(line)?;
+    match fields[0] {
+        "Header" => {
+            ensure!(dump.is_none(), err_invalid!("duplicate header line"));
+            ensure!(fields.len() == 4, err_invalid!("invalid header line"));
+            *dump = Some(Dump {
+                format: num(fields[1])?,
+                track_count: num(fields[2])?,
+                division: num(fields[3])?,
+                tracks: Vec::new(),
+            });
+        }
+        "Track" => {
+            let dump = dump.as_mut().ok_or(err_invalid!("missing header line"))?;
+            ensure!(fields.len() == 2, err_invalid!("invalid track line"));
+            ensure!(
+                num::<usize>(fields[1])? == dump.tracks.len(),
+                err_invalid!("tracks out of order")
+            );
+            dump.tracks.push(Vec::new());
+        }
+        _ => {
+            let dump = dump.as_mut().ok_or(err_invalid!("missing header line"))?;
+            ensure!(fields.len() >= 3, err_invalid!("invalid event line"));
+            ensure!(
+                Some(num::<usize>(fields[0])?) == dump.tracks.len().checked_sub(1),
+                err_invalid!("event track does not match the current track")
+            );
+            let track = dump
+                .tracks
+                .last_mut()
+                .ok_or(err_invalid!("event outside of a track"))?;
+            track.push(DumpEvent {
+                tick: num(fields[1])?,
+                kind: read_kind(fields[2], &fields[3..])?,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Read the event type and fields of an event line.
+fn read_kind(name: &str, fields: &[&str]) -> Result<DumpKind> {
+    use self::DumpKind::*;
+    let midi_fields = |count: usize| -> Result<bool> {
+        match &fields[count.min(fields.len())..] {
+            _ if fields.len() < count => Err(err_invalid!("missing event fields").into()),
+            [] => Ok(false),
+            ["running"] => Ok(true),
+            _ => Err(err_invalid!("invalid midi event fields").into()),
+        }
+    };
+    let expect = |count: usize| -> Result<()> {
+        ensure!(
+            fields.len() == count,
+            err_invalid!("wrong amount of event fields")
+        );
+        Ok(())
+    };
+    Ok(match name {
+        "NoteOff" | "NoteOn" | "Aftertouch" | "Controller" => {
+            let running = midi_fields(3)?;
+            let channel = num(fields[0])?;
+            let (a, b) = (num(fields[1])?, num(fields[2])?);
+            match name {
+                "NoteOff" => NoteOff {
+                    channel,
+                    key: a,
+                    vel: b,
+                    running,
+                },
+                "NoteOn" => NoteOn {
+                    channel,
+                    key: a,
+                    vel: b,
+                    running,
+                },
+                "Aftertouch" => Aftertouch {
+                    channel,
+                    key: a,
+                    vel: b,
+                    running,
+                },
+                _ => Controller {
+                    channel,
+                    controller: a,
+                    value: b,
+                    running,
+                },
+            }
+        }
+        "ProgramChange" | "ChannelAftertouch" | "PitchBend" => {
+            let running = midi_fields(2)?;
+            let channel = num(fields[0])?;
+            match name {
+                "ProgramChange" => ProgramChange {
+                    channel,
+                    program: num(fields[1])?,
+                    running,
+                },
+                "ChannelAftertouch" => ChannelAftertouch {
+                    channel,
+                    vel: num(fields[1])?,
+                    running,
+                },
+                _ => PitchBend {
+                    channel,
+                    bend: num(fields[1])?,
+                    running,
+                },
+            }
+        }
+        "SysEx" => {
+            expect(1)?;
+            SysEx {
+                data: hex(fields[0])?,
+            }
+        }
+        "Escape" => {
+            expect(1)?;
+            Escape {
+                data: hex(fields[0])?,
+            }
+        }
+        "EndOfTrack" => {
+            expect(0)?;
+            EndOfTrack
+        }
+        "Tempo" => {
+            expect(1)?;
+            Tempo {
+                tempo: num(fields[0])?,
+            }
+        }
+        "TimeSignature" => {
+            expect(4)?;
+            TimeSignature {
+                numerator: num(fields[0])?,
+                denominator: num(fields[1])?,
+                clocks_per_click: num(fields[2])?,
+                notes_per_quarter: num(fields[3])?,
+            }
+        }
+        "KeySignature" => {
+            expect(2)?;
+            KeySignature {
+                sharps: num(fields[0])?,
+                minor: match fields[1] {
+                    "major" => false,
+                    "minor" => true,
+                    _ => bail!(err_invalid!("invalid key signature mode")),
+                },
+            }
+        }
+        "Text" => {
+            expect(2)?;
+            Text {
+                meta_type: num(fields[0])?,
+                text: unquote(fields[1])?,
+            }
+        }
+        "Meta" => {
+            expect(2)?;
+            Meta {
+                meta_type: num(fields[0])?,
+                data: hex(fields[1])?,
+            }
+        }
+        "Raw" => {
+            expect(1)?;
+            Raw {
+                data: hex(fields[0])?,
+            }
+        }
+        _ => bail!(err_invalid!("unknown event type")),
+    })
+}
+
+/// Split a line into its comma-separated fields, keeping quoted strings whole.
+fn split_fields(line: &str) -> Result<Vec<&str>> {
+    let mut fields = Vec::new();
+    let mut rest = line;
+    loop {
+        let trimmed = rest.trim_start();
+        let end = if trimmed.starts_with('"') {
+            //Find the closing quote, skipping escaped characters
+            let mut escaped = false;
+            let close = trimmed
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| {
+                    let found = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    found
+                })
+                .map(|(idx, _)| idx + 1)
+                .ok_or(err_invalid!("unterminated string"))?;
+            let after = trimmed[close..].find(',').unwrap_or(trimmed.len() - close);
+            ensure!(
+                trimmed[close..close + after].trim().is_empty(),
+                err_invalid!("unexpected characters after string")
+            );
+            close + after
+        } else {
+            trimmed.find(',').unwrap_or(trimmed.len())
+        };
+        fields.push(trimmed[..end].trim());
+        match trimmed[end..].strip_prefix(',') {
+            Some(next) => rest = next,
+            None => break,
+        }
+    }
+    Ok(fields)
+}
+
+/// Parse a numeric field.
+fn num<T: str::FromStr>(field: &str) -> Result<T> {
+    field
+        .parse()
+        .map_err(|_| err_invalid!("invalid numeric field").into())
+}
+
+/// Decode a hex-encoded field.
+fn hex(field: &str) -> Result<Vec<u8>> {
+    let pairs = field.as_bytes().chunks_exact(2);
+    ensure!(
+        pairs.remainder().is_empty(),
+        err_invalid!("odd-length hex field")
+    );
+    pairs
+        .map(|pair| {
+            str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| err_invalid!("invalid hex field").into())
+        })
+        .collect()
+}
+
+/// Decode a quoted string field.
+fn unquote(field: &str) -> Result<String> {
+    let inner = field
+        .strip_prefix('"')
+        .and_then(|field| field.strip_suffix('"'))
+        .ok_or(err_invalid!("expected quoted string"))?;
+    let mut text = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        text.push(match chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                let rest = chars.as_str();
+                let code = rest
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.find('}').map(|end| &rest[..end]))
+                    .ok_or(err_invalid!("invalid unicode escape"))?;
+                let c = u32::from_str_radix(code, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or(err_invalid!("invalid unicode escape"))?;
+                chars = rest[code.len() + 2..].chars();
+                c
+            }
+            _ => bail!(err_invalid!("invalid string escape")),
+        });
+    }
+    Ok(text)
+}
+
+/// Formats bytes as contiguous lowercase hex.
+struct Hex<'a>(&'a [u8]);
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
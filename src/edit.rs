@@ -0,0 +1,430 @@
+//! Merging several files together and cutting files into time ranges.
+#![cfg(feature = "alloc")]
+
+use crate::{
+    event::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind},
+    prelude::*,
+    primitive::{clamp_delta, Format, Timing},
+    smf::{Header, Smf, Track},
+    transform::{ChangePpq, RemapChannels, Transform},
+};
+use core::ops::{Bound, RangeBounds};
+
+/// The percussion channel in General MIDI, which is never remapped when merging.
+const DRUM_CHANNEL: u8 = 9;
+
+impl<'a> Smf<'a> {
+    /// Combine several files into a single `Format::Parallel` file, playing them all at once.
+    ///
+    /// - The resulting resolution is the highest ticks per beat among `Timing::Metrical` inputs,
+    ///   and all metrical inputs are rescaled to it.
+    ///   If the first input uses `Timing::Timecode`, its timing is used instead and no rescaling
+    ///   is done.
+    /// - The conductor events (tempo, time signature, key signature, SMPTE offset and markers) of
+    ///   the first track of each file are gathered into a single conductor track at the start of
+    ///   the result.
+    ///   Duplicate events are dropped, and if two files set a different tempo, time signature or
+    ///   key signature at the same tick, the earliest file wins.
+    ///   First tracks that are left with no channel messages are dropped altogether.
+    /// - If a file uses a MIDI channel that a previous file already used, it is moved to a free
+    ///   channel, if there is any.
+    ///   The percussion channel (channel 10, or `9` when zero-based) is never remapped.
+    pub fn merge(files: &[Smf<'a>]) -> Smf<'a> {
+        let timing = match files.first().map(|smf| smf.header.timing) {
+            Some(Timing::Timecode(fps, subframes)) => Timing::Timecode(fps, subframes),
+            _ => Timing::Metrical(
+                files
+                    .iter()
+                    .filter_map(|smf| match smf.header.timing {
+                        Timing::Metrical(ppq) => Some(ppq),
+                        Timing::Timecode(..) => None,
+                    })
+                    .max()
+                    .unwrap_or_else(|| 480.into()),
+            ),
+        };
+        let mut merged = Smf::new(Header::new(Format::Parallel, timing));
+        let mut conductor = Vec::new();
+        let mut used_channels = [false; 16];
+        for smf in files {
+            let mut smf = smf.clone();
+            if let (Timing::Metrical(from), Timing::Metrical(to)) = (smf.header.timing, timing) {
+                ChangePpq::new(from, to).apply(&mut smf);
+            }
+            channel_remap(&smf, &mut used_channels).apply(&mut smf);
+            for (i, track) in smf.tracks.into_iter().enumerate() {
+                if i != 0 {
+                    merged.tracks.push(track);
+                    continue;
+                }
+                //Pull conductor events out of the first track
+                let mut rest = Vec::with_capacity(track.len());
+                let (mut abs, mut carry) = (0, 0);
+                for ev in track {
+                    abs += ev.delta.as_int() as u64;
+                    carry += ev.delta.as_int() as u64;
+                    if let TrackEventKind::Meta(meta) = ev.kind {
+                        if is_conductor_meta(&meta) {
+                            conductor.push((abs, meta));
+                            continue;
+                        }
+                    }
+                    rest.push(TrackEvent {
+                        delta: clamp_delta(carry),
+                        kind: ev.kind,
+                    });
+                    carry = 0;
+                }
+                let has_channel_messages = rest
+                    .iter()
+                    .any(|ev| matches!(ev.kind, TrackEventKind::Midi { .. }));
+                if has_channel_messages {
+                    merged.tracks.push(rest);
+                }
+            }
+        }
+        merged.tracks.insert(0, conductor_track(conductor));
+        merged
+    }
+
+    /// Cut this file in two at the given tick.
+    ///
+    /// Equivalent to `(self.slice(..tick), self.slice(tick..))`.
+    /// See [`slice`](#method.slice) for details on how each piece is made standalone.
+    #[inline]
+    pub fn split_at(&self, tick: u64) -> (Smf<'a>, Smf<'a>) {
+        (self.slice(..tick), self.slice(tick..))
+    }
+
+    /// Extract the events within the given tick range into a new standalone file.
+    ///
+    /// Event times are shifted so that the start of the range is at tick 0.
+    /// To make sure the piece plays correctly on its own, the following is done on each track:
+    ///
+    /// - The tempo, time signature and key signature active at the start of the range are
+    ///   re-emitted at tick 0, along with the program, controller and pitch bend state of every
+    ///   channel.
+    /// - Note-offs for notes that started before the range are dropped.
+    /// - Notes still playing at the end of the range are ended there.
+    /// - Every track ends with a single `EndOfTrack` event.
+    pub fn slice(&self, range: impl RangeBounds<u64>) -> Smf<'a> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => Some(end.saturating_add(1)),
+            Bound::Excluded(&end) => Some(end),
+            Bound::Unbounded => None,
+        };
+        Smf {
+            header: self.header,
+            tracks: self
+                .tracks
+                .iter()
+                .map(|track| slice_track(track, start, end))
+                .collect(),
+        }
+    }
+}
+
+/// Whether a meta message belongs in the conductor track of a parallel file.
+fn is_conductor_meta(meta: &MetaMessage) -> bool {
+    matches!(
+        meta,
+        MetaMessage::Tempo(..)
+            | MetaMessage::TimeSignature(..)
+            | MetaMessage::KeySignature(..)
+            | MetaMessage::SmpteOffset(..)
+            | MetaMessage::Marker(..)
+    )
+}
+
+/// Figure out a channel mapping for a file so that it does not collide with the channels already
+/// in use, and mark its channels as used.
+fn channel_remap(smf: &Smf, used: &mut [bool; 16]) -> RemapChannels {
+    let mut present = [false; 16];
+    for ev in smf.tracks.iter().flatten() {
+        if let TrackEventKind::Midi { channel, .. } = ev.kind {
+            present[channel.as_int() as usize] = true;
+        }
+    }
+    let mut remap = RemapChannels::identity();
+    //Channels claimed by this same file cannot be used as remap targets either
+    let mut taken = *used;
+    for (ch, &is_present) in present.iter().enumerate() {
+        taken[ch] |= is_present;
+    }
+    for ch in 0..16 {
+        if !present[ch] {
+            continue;
+        }
+        if used[ch] && ch != DRUM_CHANNEL as usize {
+            let free = (0..16).find(|&free| !taken[free] && free != DRUM_CHANNEL as usize);
+            if let Some(free) = free {
+                taken[free] = true;
+                remap = remap.map(u4::new(ch as u8), u4::new(free as u8));
+                used[free] = true;
+                continue;
+            }
+        }
+        used[ch] = true;
+    }
+    remap
+}
+
+/// Build a conductor track out of absolute-timed meta events, dropping duplicates and
+/// conflicting events.
+fn conductor_track<'a>(mut events: Vec<(u64, MetaMessage<'a>)>) -> Track<'a> {
+    //Stable sort, so that at the same tick events from earlier files come first
+    events.sort_by_key(|&(tick, _)| tick);
+    let mut track: Track<'a> = Vec::with_capacity(events.len() + 1);
+    let mut prev = 0;
+    let mut tick_start = 0;
+    for (tick, meta) in events {
+        if tick != prev || track.is_empty() {
+            tick_start = track.len();
+        }
+        let conflicts = track[tick_start..].iter().any(|ev| match ev.kind {
+            TrackEventKind::Meta(other) => mem::discriminant(&other) == mem::discriminant(&meta),
+            _ => false,
+        });
+        let is_exclusive = matches!(
+            meta,
+            MetaMessage::Tempo(..) | MetaMessage::TimeSignature(..) | MetaMessage::KeySignature(..)
+        );
+        let is_duplicate = track[tick_start..]
+            .iter()
+            .any(|ev| ev.kind == TrackEventKind::Meta(meta));
+        if is_duplicate || (is_exclusive && conflicts) {
+            continue;
+        }
+        track.push(TrackEvent {
+            delta: clamp_delta(tick - prev),
+            kind: TrackEventKind::Meta(meta),
+        });
+        prev = tick;
+    }
+    track.push(TrackEvent {
+        delta: 0.into(),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    track
+}
+
+/// The state of a track at a given instant, required to start playback at that instant.
+struct TrackState<'a> {
+    tempo: Option<MetaMessage<'a>>,
+    time_signature: Option<MetaMessage<'a>>,
+    key_signature: Option<MetaMessage<'a>>,
+    program: [Option<u7>; 16],
+    controllers: [[Option<u7>; 128]; 16],
+    /// Whether the last parameter selected on each channel was an NRPN rather than an RPN.
+    nrpn_selected: [bool; 16],
+    pitch_bend: [Option<MidiMessage>; 16],
+}
+//Controllers that are restored in a specific order
+const BANK_SELECT_MSB: u8 = 0;
+const BANK_SELECT_LSB: u8 = 32;
+const DATA_ENTRY_MSB: u8 = 6;
+const DATA_ENTRY_LSB: u8 = 38;
+const DATA_INCREMENT: u8 = 96;
+const DATA_DECREMENT: u8 = 97;
+const NRPN_LSB: u8 = 98;
+const NRPN_MSB: u8 = 99;
+const RPN_LSB: u8 = 100;
+const RPN_MSB: u8 = 101;
+const ORDERED_CONTROLLERS: [u8; 8] = [
+    BANK_SELECT_MSB,
+    BANK_SELECT_LSB,
+    DATA_ENTRY_MSB,
+    DATA_ENTRY_LSB,
+    NRPN_LSB,
+    NRPN_MSB,
+    RPN_LSB,
+    RPN_MSB,
+];
+
+impl<'a> TrackState<'a> {
+    fn new() -> TrackState<'a> {
+        TrackState {
+            tempo: None,
+            time_signature: None,
+            key_signature: None,
+            program: [None; 16],
+            controllers: [[None; 128]; 16],
+            nrpn_selected: [false; 16],
+            pitch_bend: [None; 16],
+        }
+    }
+
+    fn update(&mut self, kind: &TrackEventKind<'a>) {
+        match *kind {
+            TrackEventKind::Meta(meta) => match meta {
+                MetaMessage::Tempo(..) => self.tempo = Some(meta),
+                MetaMessage::TimeSignature(..) => self.time_signature = Some(meta),
+                MetaMessage::KeySignature(..) => self.key_signature = Some(meta),
+                _ => {}
+            },
+            TrackEventKind::Midi { channel, message } => {
+                let ch = channel.as_int() as usize;
+                match message {
+                    MidiMessage::ProgramChange { program } => self.program[ch] = Some(program),
+                    MidiMessage::Controller { controller, value } => {
+                        let controller = controller.as_int();
+                        match controller {
+                            //Relative and channel mode messages are not part of the state
+                            DATA_INCREMENT | DATA_DECREMENT | 120..=127 => return,
+                            NRPN_LSB | NRPN_MSB => self.nrpn_selected[ch] = true,
+                            RPN_LSB | RPN_MSB => self.nrpn_selected[ch] = false,
+                            _ => {}
+                        }
+                        self.controllers[ch][controller as usize] = Some(value)
+                    }
+                    MidiMessage::PitchBend { .. } => self.pitch_bend[ch] = Some(message),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Emit the events required to recreate this state, all at delta 0.
+    fn emit(&self, out: &mut Track<'a>) {
+        let mut push = |kind| {
+            out.push(TrackEvent {
+                delta: 0.into(),
+                kind,
+            })
+        };
+        for meta in [self.tempo, self.time_signature, self.key_signature]
+            .iter()
+            .flatten()
+        {
+            push(TrackEventKind::Meta(*meta));
+        }
+        for ch in 0..16 {
+            let channel = u4::new(ch as u8);
+            let controller = |controller: u8| {
+                self.controllers[ch][controller as usize].map(|value| MidiMessage::Controller {
+                    controller: u7::new(controller),
+                    value,
+                })
+            };
+            //The bank must be selected before the program, and the parameter before its data
+            let bank = [BANK_SELECT_MSB, BANK_SELECT_LSB];
+            let parameter = if self.nrpn_selected[ch] {
+                [
+                    RPN_MSB,
+                    RPN_LSB,
+                    NRPN_MSB,
+                    NRPN_LSB,
+                    DATA_ENTRY_MSB,
+                    DATA_ENTRY_LSB,
+                ]
+            } else {
+                [
+                    NRPN_MSB,
+                    NRPN_LSB,
+                    RPN_MSB,
+                    RPN_LSB,
+                    DATA_ENTRY_MSB,
+                    DATA_ENTRY_LSB,
+                ]
+            };
+            let program = self.program[ch].map(|program| MidiMessage::ProgramChange { program });
+            let others = (0..120).filter(|cc| !ORDERED_CONTROLLERS.contains(cc));
+            let messages = bank
+                .iter()
+                .map(|&cc| controller(cc))
+                .chain(Some(program))
+                .chain(parameter.iter().map(|&cc| controller(cc)))
+                .chain(others.map(controller))
+                .chain(Some(self.pitch_bend[ch]))
+                .flatten();
+            for message in messages {
+                push(TrackEventKind::Midi { channel, message });
+            }
+        }
+    }
+}
+
+/// Extract the `start .. end` tick range of a track.
+fn slice_track<'a>(track: &Track<'a>, start: u64, end: Option<u64>) -> Track<'a> {
+    let mut out = Vec::new();
+    let mut state = TrackState::new();
+    let mut held = [[0u32; 128]; 16];
+    let mut abs = 0;
+    let mut prev = start;
+    let mut emitted_state = false;
+    let mut last = start;
+    for ev in track {
+        abs += ev.delta.as_int() as u64;
+        if abs < start {
+            state.update(&ev.kind);
+            continue;
+        }
+        if end.map(|end| abs >= end).unwrap_or(false) {
+            break;
+        }
+        if !emitted_state {
+            if start > 0 {
+                state.emit(&mut out);
+            }
+            emitted_state = true;
+        }
+        last = abs;
+        match ev.kind {
+            TrackEventKind::Meta(MetaMessage::EndOfTrack) => continue,
+            TrackEventKind::Midi { channel, message } => {
+                let held = &mut held[channel.as_int() as usize];
+                match message {
+                    MidiMessage::NoteOn { key, vel } if vel > 0 => held[key.as_int() as usize] += 1,
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                        let count = &mut held[key.as_int() as usize];
+                        if *count == 0 {
+                            //Note started before the slice
+                            continue;
+                        }
+                        *count -= 1;
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        out.push(TrackEvent {
+            delta: clamp_delta(abs - prev),
+            kind: ev.kind,
+        });
+        prev = abs;
+    }
+    if !emitted_state && start > 0 {
+        state.emit(&mut out);
+    }
+    //End any notes still playing and close the track
+    let end_tick = end.unwrap_or(last).max(prev);
+    let mut delta = clamp_delta(end_tick - prev);
+    for (ch, keys) in held.iter().enumerate() {
+        for (key, &count) in keys.iter().enumerate() {
+            for _ in 0..count {
+                out.push(TrackEvent {
+                    delta: mem::replace(&mut delta, 0.into()),
+                    kind: TrackEventKind::Midi {
+                        channel: u4::new(ch as u8),
+                        message: MidiMessage::NoteOff {
+                            key: u7::new(key as u8),
+                            vel: 0.into(),
+                        },
+                    },
+                });
+            }
+        }
+    }
+    out.push(TrackEvent {
+        delta,
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    out
+}
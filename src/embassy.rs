@@ -0,0 +1,212 @@
+//! Async USB MIDI for `embassy-usb`.
+//!
+//! The [`MidiClass`](struct.MidiClass.html) in this module exposes the same MIDI Streaming
+//! descriptors as the `usb-device` [`MidiClass`](../class/struct.MidiClass.html), so both
+//! backends enumerate identically, and both encode events into USB MIDI packets the same way.
+
+#![cfg(feature = "embassy")]
+
+use crate::{
+    descriptor::{
+        self, EndpointJacks, CS_ENDPOINT, CS_INTERFACE, MIDI_PROTOCOL_NONE, USB_CLASS_AUDIO,
+        USB_SUBCLASS_AUDIOCONTROL, USB_SUBCLASS_MIDISTREAMING,
+    },
+    live::{LiveEvent, SystemCommon},
+    packet::{packetize, UsbMidiPacket},
+    prelude::*,
+};
+use embassy_usb::{
+    driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut},
+    Builder,
+};
+
+/// The largest supported max packet size, which is the largest one allowed for full-speed bulk
+/// endpoints.
+const MAX_PACKET_SIZE: usize = 64;
+
+/// An async USB MIDI class for `embassy-usb`, with one bulk endpoint in each direction.
+///
+/// Each USB MIDI event packet carries at most 3 bytes, so SysEx messages span several packets.
+/// [`send`](#method.send) splits them up automatically, but [`receive`](#method.receive) only
+/// yields complete messages and skips SysEx fragments.
+/// Use [`receive_raw`](#method.receive_raw) to reassemble long SysEx messages manually.
+///
+/// This type is only available with the `embassy` feature enabled.
+pub struct MidiClass<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+    rx: [u8; MAX_PACKET_SIZE],
+    rx_pos: usize,
+    rx_len: usize,
+}
+impl<'d, D: Driver<'d>> MidiClass<'d, D> {
+    /// Add the MIDI function to the given device builder.
+    ///
+    /// For full-speed devices, `max_packet_size` has to be one of 8, 16, 32 or 64.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_packet_size` is larger than 64 or not a multiple of 4.
+    //`is_multiple_of` needs Rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn new(builder: &mut Builder<'d, D>, max_packet_size: u16) -> MidiClass<'d, D> {
+        assert!(
+            max_packet_size as usize <= MAX_PACKET_SIZE && max_packet_size % 4 == 0,
+            "unsupported usb midi max packet size"
+        );
+        let mut func = builder.function(
+            USB_CLASS_AUDIO,
+            USB_SUBCLASS_AUDIOCONTROL,
+            MIDI_PROTOCOL_NONE,
+        );
+
+        // B.3.1 Standard AC Interface Descriptor
+        let mut iface = func.interface();
+        let streaming_if = u8::from(iface.interface_number()) + 1;
+        let mut alt = iface.alt_setting(
+            USB_CLASS_AUDIO,
+            USB_SUBCLASS_AUDIOCONTROL,
+            MIDI_PROTOCOL_NONE,
+            None,
+        );
+        // B.3.2 Class-specific AC Interface Descriptor
+        alt.descriptor(
+            CS_INTERFACE,
+            &descriptor::audio_control_header(streaming_if),
+        );
+
+        // B.4.1 Standard MS Interface Descriptor
+        let mut iface = func.interface();
+        let mut alt = iface.alt_setting(
+            USB_CLASS_AUDIO,
+            USB_SUBCLASS_MIDISTREAMING,
+            MIDI_PROTOCOL_NONE,
+            None,
+        );
+        // B.4.2 Class-specific MS Interface Descriptor, B.4.3 MIDI IN and B.4.4 MIDI OUT Jack
+        // Descriptors
+        let _ = descriptor::write_streaming_interface(
+            1,
+            &[],
+            |_| 0,
+            |_| 0,
+            |kind, data| {
+                alt.descriptor(kind, data);
+                Ok::<(), core::convert::Infallible>(())
+            },
+        );
+        // B.5 Bulk OUT and B.6 Bulk IN Endpoint Descriptors
+        let read_ep = alt.endpoint_bulk_out(max_packet_size);
+        alt.descriptor(CS_ENDPOINT, EndpointJacks::out_endpoint(1).as_bytes());
+        let write_ep = alt.endpoint_bulk_in(max_packet_size);
+        alt.descriptor(CS_ENDPOINT, EndpointJacks::in_endpoint(1).as_bytes());
+
+        MidiClass {
+            read_ep,
+            write_ep,
+            rx: [0; MAX_PACKET_SIZE],
+            rx_pos: 0,
+            rx_len: 0,
+        }
+    }
+
+    /// Gets the maximum packet size in bytes.
+    #[inline]
+    pub fn max_packet_size(&self) -> u16 {
+        // The size is the same for both endpoints.
+        self.read_ep.info().max_packet_size
+    }
+
+    /// Waits for the USB host to enable this interface.
+    pub async fn wait_connection(&mut self) {
+        self.read_ep.wait_enabled().await;
+    }
+
+    /// Send a single event on the cable of the given packet.
+    ///
+    /// The code index number of the packet is ignored and derived from the event instead, and
+    /// SysEx messages are split into as many USB packets as necessary.
+    #[inline]
+    pub async fn send(&mut self, packet: &UsbMidiPacket<'_>) -> StdResult<(), EndpointError> {
+        self.send_event(packet.cable_number, &packet.event).await
+    }
+
+    /// Send a single event on the given cable.
+    ///
+    /// Undefined System Common messages with more than 2 data bytes cannot be sent over USB and
+    /// are silently dropped.
+    pub async fn send_event(
+        &mut self,
+        cable: u4,
+        event: &LiveEvent<'_>,
+    ) -> StdResult<(), EndpointError> {
+        let max_len = self.write_ep.info().max_packet_size as usize;
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let mut len = 0;
+        for packet in packetize(cable, event) {
+            if len + packet.len() > max_len {
+                self.write_ep.write(&buf[..len]).await?;
+                len = 0;
+            }
+            buf[len..len + packet.len()].copy_from_slice(&packet);
+            len += packet.len();
+        }
+        if len > 0 {
+            self.write_ep.write(&buf[..len]).await?;
+        }
+        if len == max_len {
+            //A full transfer is not processed by the host until a short packet arrives
+            self.write_ep.write(&[]).await?;
+        }
+        Ok(())
+    }
+
+    /// Receive the next USB MIDI event packet as-is, waiting for the host if necessary.
+    pub async fn receive_raw(&mut self) -> StdResult<[u8; 4], EndpointError> {
+        while self.rx_pos + 4 > self.rx_len {
+            self.rx_len = self.read_ep.read(&mut self.rx).await?;
+            self.rx_pos = 0;
+        }
+        let mut packet = [0; 4];
+        packet.copy_from_slice(&self.rx[self.rx_pos..self.rx_pos + 4]);
+        self.rx_pos += 4;
+        Ok(packet)
+    }
+
+    /// Receive the next complete event, waiting for the host if necessary.
+    ///
+    /// Packets that do not hold a complete message, such as fragments of long SysEx messages or
+    /// packets with a reserved code index number, are skipped.
+    pub async fn receive(&mut self) -> StdResult<UsbMidiPacket<'_>, EndpointError> {
+        //The buffer may be refilled within the loop, so the payload is only borrowed after it
+        let (start, mut packet) = loop {
+            if self.rx_pos + 4 > self.rx_len {
+                self.rx_len = self.read_ep.read(&mut self.rx).await?;
+                self.rx_pos = 0;
+                continue;
+            }
+            let start = self.rx_pos;
+            self.rx_pos += 4;
+            if let Ok(packet) = UsbMidiPacket::parse(&self.rx[start..start + 4]) {
+                let packet = UsbMidiPacket {
+                    event: packet.event.to_static(),
+                    ..packet
+                };
+                break (start, packet);
+            }
+        };
+        //Payloads are made up of all data bytes after the status byte
+        let len = UsbMidiPacket::packet_length(packet.code_index_number);
+        let data = u7::slice_from_int(&self.rx[start + 2..=start + len]);
+        packet.event = match packet.event {
+            LiveEvent::Common(SystemCommon::SysEx(_)) => {
+                LiveEvent::Common(SystemCommon::SysEx(data))
+            }
+            LiveEvent::Common(SystemCommon::Undefined(status, _)) => {
+                LiveEvent::Common(SystemCommon::Undefined(status, data))
+            }
+            event => event,
+        };
+        Ok(packet)
+    }
+}
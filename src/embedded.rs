@@ -1,2 +1,305 @@
-use crate::class;
-use crate::packet;
+//! Support for sending and receiving MIDI on embedded devices.
+//!
+//! The [`SerialMidi`](struct.SerialMidi.html) transport implements classic 5-pin DIN MIDI on top of
+//! any UART implementing the `embedded-hal` serial traits.
+
+#![cfg(feature = "embedded")]
+
+use crate::{
+    live::LiveEvent,
+    prelude::*,
+    stream::{DefaultBuffer, MidiStream},
+};
+use embedded_hal::serial;
+
+/// The baud rate of DIN MIDI, which the UART must be configured with (8 data bits, no parity,
+/// 1 stop bit).
+pub const BAUD_RATE: u32 = 31_250;
+
+/// The reasons why an event could not be queued for transmission.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum SendError {
+    /// There is not enough room in the transmit queue for the whole event.
+    ///
+    /// Nothing is queued, so the event can be retried once
+    /// [`poll_write`](struct.SerialMidi.html#method.poll_write) has made room.
+    QueueFull,
+}
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::QueueFull => f.write_str("midi transmit queue full"),
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl std::error::Error for SendError {}
+
+/// A MIDI transport over a serial port, as used by 5-pin DIN connectors.
+///
+/// Received bytes are fed through a [`MidiStream`](../stream/struct.MidiStream.html), so messages
+/// may be split across reads and interleaved with realtime bytes.
+///
+/// Outgoing events are encoded into a transmit queue of `N` bytes, which is drained one byte at a
+/// time with [`poll_write`](#method.poll_write), usually from the UART interrupt or main loop.
+/// System Realtime messages (such as the clock) are held in a separate queue and jump ahead of any
+/// queued bytes, even in the middle of a message, as allowed by the MIDI spec.
+///
+/// Running status is used for outgoing channel messages unless disabled with
+/// [`set_running_status`](#method.set_running_status).
+///
+/// Only the traits required by each method are needed, so a receive-only or transmit-only serial
+/// port can be wrapped too.
+///
+/// This type is only available with the `embedded` feature enabled.
+#[derive(Debug)]
+pub struct SerialMidi<S, const N: usize = 64> {
+    serial: S,
+    stream: MidiStream<DefaultBuffer>,
+    tx: ByteQueue<N>,
+    realtime: ByteQueue<8>,
+    running_status: Option<u8>,
+    use_running_status: bool,
+}
+impl<S, const N: usize> SerialMidi<S, N> {
+    /// Wrap a serial port, which must already be configured for
+    /// [`BAUD_RATE`](constant.BAUD_RATE.html).
+    pub fn new(serial: S) -> SerialMidi<S, N> {
+        SerialMidi {
+            serial,
+            stream: MidiStream::new(),
+            tx: ByteQueue::new(),
+            realtime: ByteQueue::new(),
+            running_status: None,
+            use_running_status: true,
+        }
+    }
+
+    /// Whether outgoing channel messages omit repeated status bytes.
+    #[inline]
+    pub fn running_status(&self) -> bool {
+        self.use_running_status
+    }
+
+    /// Enable or disable running status for outgoing channel messages.
+    #[inline]
+    pub fn set_running_status(&mut self, enabled: bool) {
+        self.use_running_status = enabled;
+        self.running_status = None;
+    }
+
+    /// Make the next outgoing channel message include its status byte, even if running status is
+    /// enabled.
+    ///
+    /// Receivers that were connected mid-stream only catch up on the next status byte, so it is a
+    /// good idea to call this periodically.
+    #[inline]
+    pub fn refresh_running_status(&mut self) {
+        self.running_status = None;
+    }
+
+    /// Queue an event for transmission.
+    ///
+    /// The event is either queued entirely or not at all.
+    /// Realtime events are queued ahead of any other pending bytes.
+    pub fn send(&mut self, event: &LiveEvent) -> StdResult<(), SendError> {
+        if let LiveEvent::Realtime(realtime) = event {
+            return self
+                .realtime
+                .push(realtime.encode())
+                .map_err(|()| SendError::QueueFull);
+        }
+        let mut running_status = if self.use_running_status {
+            self.running_status
+        } else {
+            None
+        };
+        let mut writer = QueueWriter {
+            start: self.tx.len,
+            queue: &mut self.tx,
+        };
+        match event.write_with_running_status(&mut running_status, &mut writer) {
+            Ok(()) => {
+                self.running_status = running_status;
+                Ok(())
+            }
+            Err(_) => {
+                writer.rollback();
+                Err(SendError::QueueFull)
+            }
+        }
+    }
+
+    /// Queue raw bytes for transmission, such as the DIN output of a
+    /// [`Router`](../router/struct.Router.html).
+    ///
+    /// The bytes are either queued entirely or not at all.
+    /// A single System Realtime byte is queued ahead of any other pending bytes, like in
+    /// [`send`](#method.send).
+    /// Since the bytes may change the status of the receiver, the next channel message queued
+    /// through `send` will include its status byte.
+    pub fn send_bytes(&mut self, bytes: &[u8]) -> StdResult<(), SendError> {
+        if let [byte @ 0xF8..=0xFF] = *bytes {
+            return self.realtime.push(byte).map_err(|()| SendError::QueueFull);
+        }
+        let mut writer = QueueWriter {
+            start: self.tx.len,
+            queue: &mut self.tx,
+        };
+        writer.write(bytes).map_err(|()| SendError::QueueFull)?;
+        self.running_status = None;
+        Ok(())
+    }
+
+    /// How many bytes are waiting to be transmitted, including realtime bytes.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.tx.len + self.realtime.len
+    }
+
+    /// Discard any bytes waiting to be transmitted, as well as any partially received message.
+    pub fn clear(&mut self) {
+        self.tx.clear();
+        self.realtime.clear();
+        self.stream = MidiStream::new();
+        self.running_status = None;
+    }
+
+    /// Get a reference to the underlying serial port.
+    #[inline]
+    pub fn serial(&self) -> &S {
+        &self.serial
+    }
+
+    /// Get a mutable reference to the underlying serial port.
+    #[inline]
+    pub fn serial_mut(&mut self) -> &mut S {
+        &mut self.serial
+    }
+
+    /// Release the underlying serial port.
+    ///
+    /// Any pending bytes are lost.
+    #[inline]
+    pub fn release(self) -> S {
+        self.serial
+    }
+}
+impl<S: serial::Read<u8>, const N: usize> SerialMidi<S, N> {
+    /// Read all available bytes from the serial port, calling `handle_ev` for every completed
+    /// message.
+    ///
+    /// Returns once the serial port has no more bytes to offer.
+    /// On a read error (such as a framing or overrun error) any partially received message is
+    /// discarded and the error is returned, and reading can be resumed by calling this method
+    /// again.
+    pub fn poll_read(&mut self, mut handle_ev: impl FnMut(LiveEvent)) -> StdResult<(), S::Error> {
+        loop {
+            match self.serial.read() {
+                Ok(byte) => self.stream.feed(&[byte], &mut handle_ev),
+                Err(nb::Error::WouldBlock) => return Ok(()),
+                Err(nb::Error::Other(err)) => {
+                    self.stream = MidiStream::new();
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+impl<S: serial::Write<u8>, const N: usize> SerialMidi<S, N> {
+    /// Transmit as many pending bytes as the serial port accepts, realtime bytes first.
+    ///
+    /// Returns `Ok(())` once all pending bytes have been handed over to the serial port, and
+    /// `WouldBlock` if some are still pending.
+    pub fn poll_write(&mut self) -> nb::Result<(), S::Error> {
+        loop {
+            if let Some(byte) = self.realtime.peek() {
+                self.serial.write(byte)?;
+                self.realtime.pop();
+            } else if let Some(byte) = self.tx.peek() {
+                self.serial.write(byte)?;
+                self.tx.pop();
+            } else {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// A fixed-size FIFO ring buffer of bytes.
+#[derive(Clone, Debug)]
+struct ByteQueue<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+impl<const N: usize> ByteQueue<N> {
+    #[inline]
+    fn new() -> ByteQueue<N> {
+        ByteQueue {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, byte: u8) -> StdResult<(), ()> {
+        if self.len >= N {
+            return Err(());
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<u8> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(self.buf[self.head])
+        }
+    }
+
+    #[inline]
+    fn pop(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+/// Writes events into a byte queue, so that a partially written event can be rolled back.
+struct QueueWriter<'a, const N: usize> {
+    queue: &'a mut ByteQueue<N>,
+    start: usize,
+}
+impl<'a, const N: usize> QueueWriter<'a, N> {
+    #[inline]
+    fn rollback(self) {
+        self.queue.len = self.start;
+    }
+}
+impl<'a, const N: usize> Write for QueueWriter<'a, N> {
+    type Error = ();
+    type Seekable = crate::io::NotSeekable<Self>;
+
+    fn write(&mut self, buf: &[u8]) -> StdResult<(), ()> {
+        ensure!(N - self.queue.len >= buf.len(), ());
+        for &byte in buf {
+            self.queue.push(byte)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn invalid_input(_msg: &'static str) {}
+}
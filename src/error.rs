@@ -1,8 +1,8 @@
-use core::fmt;
+use core::{convert::TryFrom, fmt};
 
 #[cfg(all(debug_assertions, feature = "alloc"))]
 mod error_impl {
-    use super::{Error, ErrorExt, ErrorKind};
+    use super::{Error, ErrorExt, ErrorKind, Position};
 
     pub type ErrorInner = alloc::boxed::Box<Chained>;
 
@@ -23,6 +23,7 @@ mod error_impl {
         #[inline]
         fn chain_ctx(self, ctx: &'static ErrorKind) -> Error {
             Error {
+                pos: self.pos,
                 inner: Chained {
                     this: ctx,
                     src: Some(self),
@@ -40,6 +41,7 @@ mod error_impl {
                     src: None,
                 }
                 .into(),
+                pos: Position::UNKNOWN,
             }
         }
     }
@@ -47,9 +49,9 @@ mod error_impl {
 
 #[cfg(not(all(debug_assertions, feature = "alloc")))]
 mod error_impl {
-    use super::{Error, ErrorExt, ErrorKind};
+    use super::{Error, ErrorExt, ErrorKind, Position};
 
-    /// In release mode errors are just a thin pointer.
+    /// In release mode errors are just a thin pointer (plus their position).
     pub type ErrorInner = &'static ErrorKind;
     impl ErrorExt for Error {
         #[inline]
@@ -62,13 +64,19 @@ mod error_impl {
         }
         #[inline]
         fn chain_ctx(self, ctx: &'static ErrorKind) -> Error {
-            Error { inner: ctx }
+            Error {
+                inner: ctx,
+                pos: self.pos,
+            }
         }
     }
     impl From<&'static ErrorKind> for Error {
         #[inline]
         fn from(inner: &'static ErrorKind) -> Error {
-            Error { inner }
+            Error {
+                inner,
+                pos: Position::UNKNOWN,
+            }
         }
     }
 }
@@ -76,9 +84,13 @@ mod error_impl {
 /// Represents an error while parsing an SMF file or MIDI stream.
 ///
 /// This type wraps an `ErrorKind` and includes backtrace and error chain data in debug mode.
-/// In release mode it is a newtype wrapper around `ErrorKind`, so the `Error::source` method
+/// In release mode it is a thin wrapper around `ErrorKind`, so the `Error::source` method
 /// always returns `None`.
 ///
+/// Parse errors also record where in the input they happened: the byte offset, the chunk index
+/// and the event index, whenever these are known.
+/// This information does not require allocation, so it is available in `no_std` environments too.
+///
 /// If the `std` feature is enabled, this type implements `std::error::Error`.
 /// Otherwise, only `Display` and `Debug` are implemented (the `source` method on the `Error` type
 /// itself is still available, though).
@@ -88,6 +100,23 @@ mod error_impl {
 #[derive(Clone)]
 pub struct Error {
     inner: self::error_impl::ErrorInner,
+    pos: Position,
+}
+
+/// Where an error happened, using `u32::MAX`/`usize::MAX` to signal unknown fields in order to
+/// keep errors small.
+#[derive(Copy, Clone, Debug)]
+struct Position {
+    offset: usize,
+    chunk: u32,
+    event: u32,
+}
+impl Position {
+    const UNKNOWN: Position = Position {
+        offset: usize::MAX,
+        chunk: u32::MAX,
+        event: u32::MAX,
+    };
 }
 impl Error {
     /// Create a new error with the given `ErrorKind`.
@@ -112,16 +141,82 @@ impl Error {
     pub fn source(&self) -> Option<&Error> {
         ErrorExt::source(self)
     }
+
+    /// The absolute byte offset at which the error happened, if known.
+    ///
+    /// For errors within a chunk or event, this is the offset of the start of the chunk or event
+    /// that failed to parse.
+    /// Offsets are counted from the start of the slice given to the parsing function.
+    #[inline]
+    pub fn offset(&self) -> Option<usize> {
+        Some(self.pos.offset).filter(|&o| o != usize::MAX)
+    }
+
+    /// The index of the chunk in which the error happened, if known.
+    ///
+    /// Chunks are counted from the start of the file, including the header chunk and any unknown
+    /// chunks.
+    #[inline]
+    pub fn chunk(&self) -> Option<usize> {
+        Some(self.pos.chunk)
+            .filter(|&c| c != u32::MAX)
+            .map(|c| c as usize)
+    }
+
+    /// The index of the event within its track at which the error happened, if known.
+    #[inline]
+    pub fn event(&self) -> Option<usize> {
+        Some(self.pos.event)
+            .filter(|&e| e != u32::MAX)
+            .map(|e| e as usize)
+    }
+
+    /// Fill in any unknown position fields.
+    /// Fields that are already known are left as-is, since the innermost location is the most
+    /// precise one.
+    pub(crate) fn locate(
+        mut self,
+        offset: usize,
+        chunk: Option<usize>,
+        event: Option<usize>,
+    ) -> Error {
+        if self.pos.offset == usize::MAX {
+            self.pos.offset = offset;
+        }
+        if let (u32::MAX, Some(chunk)) = (self.pos.chunk, chunk) {
+            self.pos.chunk = u32::try_from(chunk).unwrap_or(u32::MAX);
+        }
+        if let (u32::MAX, Some(event)) = (self.pos.event, event) {
+            self.pos.event = u32::try_from(event).unwrap_or(u32::MAX);
+        }
+        self
+    }
+
+    fn fmt_position(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(offset) = self.offset() {
+            write!(f, " (at byte {}", offset)?;
+            if let Some(chunk) = self.chunk() {
+                write!(f, ", chunk {}", chunk)?;
+            }
+            if let Some(event) = self.event() {
+                write!(f, ", event {}", event)?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
 }
 impl fmt::Display for Error {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&self.kind(), f)
+        fmt::Display::fmt(&self.kind(), f)?;
+        self.fmt_position(f)
     }
 }
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.kind())?;
+        self.fmt_position(f)?;
         let mut maybe_src = self.source();
         while let Some(src) = maybe_src {
             writeln!(f)?;
@@ -152,7 +247,7 @@ trait ErrorExt {
 /// violated are not very useful.
 /// For this reason, errors are broadly categorized into 2 classes, and specific error info is
 /// provided as a non-normative string literal.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
 pub enum ErrorKind {
     /// Fatal errors while reading the file. It is likely that the file is not a MIDI file or
     /// is severely corrupted.
@@ -11,22 +11,27 @@ use crate::{
 /// Consists of a delta time (in MIDI ticks relative to the previous event) and the actual track
 /// event.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrackEvent<'a> {
     /// How many MIDI ticks after the previous event should this event fire.
     pub delta: u28,
     /// The type of event along with event-specific data.
+    #[cfg_attr(feature = "serde", serde(borrow))]
     pub kind: TrackEventKind<'a>,
 }
 impl<'a> TrackEvent<'a> {
     /// Advances the slice and updates `running_status`.
     ///
     /// In case of failure the slice might be left in the middle of an event!
+    /// Malformed events are only rejected if `strict` is set.
     pub(crate) fn read(
         raw: &mut &'a [u8],
         running_status: &mut Option<u8>,
+        strict: bool,
     ) -> Result<TrackEvent<'a>> {
-        let delta = u28::read_u7(raw).context(err_invalid!("failed to read event deltatime"))?;
-        let kind = TrackEventKind::read(raw, running_status)
+        let delta = u28::read_varlen(raw, strict)
+            .context(err_invalid!("failed to read event deltatime"))?;
+        let kind = TrackEventKind::read(raw, running_status, strict)
             .context(err_invalid!("failed to parse event"))?;
         Ok(TrackEvent { delta, kind })
     }
@@ -34,10 +39,12 @@ impl<'a> TrackEvent<'a> {
     pub(crate) fn read_bytemap(
         raw: &mut &'a [u8],
         running_status: &mut Option<u8>,
+        strict: bool,
     ) -> Result<(&'a [u8], TrackEvent<'a>)> {
-        let delta = u28::read_u7(raw).context(err_invalid!("failed to read event deltatime"))?;
+        let delta = u28::read_varlen(raw, strict)
+            .context(err_invalid!("failed to read event deltatime"))?;
         let old_raw = *raw;
-        let kind = TrackEventKind::read(raw, running_status)
+        let kind = TrackEventKind::read(raw, running_status, strict)
             .context(err_invalid!("failed to parse event"))?;
         Ok((
             &old_raw[..old_raw.len() - raw.len()],
@@ -59,6 +66,8 @@ impl<'a> TrackEvent<'a> {
     /// lifetime that can be stored and moved everywhere, solving borrow checker issues.
     ///
     /// WARNING: Any bytestrings in the input will be replaced by empty bytestrings.
+    ///
+    /// See [`OwnedTrackEvent`](struct.OwnedTrackEvent.html) for a lossless alternative.
     pub fn to_static(&self) -> TrackEvent<'static> {
         TrackEvent {
             delta: self.delta,
@@ -72,6 +81,7 @@ impl<'a> TrackEvent<'a> {
 /// It notably does *not* include the timing of the event; the `TrackEvent` struct is responsible
 /// for this.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TrackEventKind<'a> {
     /// A message associated to a MIDI channel carrying musical data.
     ///
@@ -88,15 +98,22 @@ pub enum TrackEventKind<'a> {
     ///
     /// Usually SysEx events end with an `0xF7` byte, but SysEx events that are split into several
     /// small packets may only contain the `0xF7` byte in the last packet fragment.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     SysEx(&'a [u8]),
     /// An escape sequence, intended to send arbitrary data to the MIDI synthesizer.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     Escape(&'a [u8]),
     /// A meta-message, giving extra information for correct playback, like tempo, song name,
     /// lyrics, etc...
+    #[cfg_attr(feature = "serde", serde(borrow))]
     Meta(MetaMessage<'a>),
 }
 impl<'a> TrackEventKind<'a> {
-    fn read(raw: &mut &'a [u8], running_status: &mut Option<u8>) -> Result<TrackEventKind<'a>> {
+    fn read(
+        raw: &mut &'a [u8],
+        running_status: &mut Option<u8>,
+        strict: bool,
+    ) -> Result<TrackEventKind<'a>> {
         //Read status
         let mut status = *raw.get(0).ok_or(err_invalid!("failed to read status"))?;
         if status < 0x80 {
@@ -120,19 +137,22 @@ impl<'a> TrackEventKind<'a> {
             0xFF => {
                 *running_status = None;
                 TrackEventKind::Meta(
-                    MetaMessage::read(raw).context(err_invalid!("failed to read meta event"))?,
+                    MetaMessage::read(raw, strict)
+                        .context(err_invalid!("failed to read meta event"))?,
                 )
             }
             0xF0 => {
                 *running_status = None;
                 TrackEventKind::SysEx(
-                    read_varlen_slice(raw).context(err_invalid!("failed to read sysex event"))?,
+                    read_varlen_slice(raw, strict)
+                        .context(err_invalid!("failed to read sysex event"))?,
                 )
             }
             0xF7 => {
                 *running_status = None;
                 TrackEventKind::Escape(
-                    read_varlen_slice(raw).context(err_invalid!("failed to read escape event"))?,
+                    read_varlen_slice(raw, strict)
+                        .context(err_invalid!("failed to read escape event"))?,
                 )
             }
             0xF1..=0xF6 => bail!(err_invalid!(
@@ -225,12 +245,63 @@ impl<'a> TrackEventKind<'a> {
     }
 }
 
+/// Get the payload of an event, if it has any.
+pub(crate) fn kind_payload<'a>(kind: &TrackEventKind<'a>) -> &'a [u8] {
+    use self::MetaMessage::*;
+    match *kind {
+        TrackEventKind::Midi { .. } => &[],
+        TrackEventKind::SysEx(data) | TrackEventKind::Escape(data) => data,
+        TrackEventKind::Meta(meta) => match meta {
+            Text(data)
+            | Copyright(data)
+            | TrackName(data)
+            | InstrumentName(data)
+            | Lyric(data)
+            | Marker(data)
+            | CuePoint(data)
+            | ProgramName(data)
+            | DeviceName(data)
+            | SequencerSpecific(data)
+            | Unknown(_, data) => data,
+            _ => &[],
+        },
+    }
+}
+
+/// Put the given payload back into an event stripped by `to_static`.
+pub(crate) fn kind_with_payload<'a>(
+    kind: TrackEventKind<'static>,
+    data: &'a [u8],
+) -> TrackEventKind<'a> {
+    use self::MetaMessage::*;
+    match kind {
+        TrackEventKind::Midi { channel, message } => TrackEventKind::Midi { channel, message },
+        TrackEventKind::SysEx(_) => TrackEventKind::SysEx(data),
+        TrackEventKind::Escape(_) => TrackEventKind::Escape(data),
+        TrackEventKind::Meta(meta) => TrackEventKind::Meta(match meta {
+            Text(_) => Text(data),
+            Copyright(_) => Copyright(data),
+            TrackName(_) => TrackName(data),
+            InstrumentName(_) => InstrumentName(data),
+            Lyric(_) => Lyric(data),
+            Marker(_) => Marker(data),
+            CuePoint(_) => CuePoint(data),
+            ProgramName(_) => ProgramName(data),
+            DeviceName(_) => DeviceName(data),
+            SequencerSpecific(_) => SequencerSpecific(data),
+            Unknown(v, _) => Unknown(v, data),
+            meta => meta,
+        }),
+    }
+}
+
 /// Represents a MIDI message, usually associated to a MIDI channel.
 ///
 /// If you wish to parse a MIDI message from a slice of raw MIDI bytes, use the
 /// [`LiveEvent::parse`](live/enum.LiveEvent.html#method.parse) method instead and ignore all
 /// variants except for [`LiveEvent::Midi`](live/enum.LiveEvent.html#variant.Midi).
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MidiMessage {
     /// Stop playing a note.
     NoteOff {
@@ -372,13 +443,16 @@ impl MidiMessage {
             MidiMessage::NoteOn { key, vel } => out.write(&[key.as_int(), vel.as_int()])?,
             MidiMessage::Aftertouch { key, vel } => out.write(&[key.as_int(), vel.as_int()])?,
             MidiMessage::Controller { controller, value } => {
+                out.write(&[controller.as_int(), value.as_int()])?
+            }
+            MidiMessage::ProgramChange { program } => out.write(&[program.as_int()])?,
+            MidiMessage::ChannelAftertouch { vel } => out.write(&[vel.as_int()])?,
             MidiMessage::PitchBend { bend } => {
                 let raw = bend.0.as_int();
                 out.write(&[(raw & 0x7F) as u8, (raw >> 7) as u8])?
             }
         }
         Ok(())
-        }
     }
 }
 
@@ -388,6 +462,7 @@ impl MidiMessage {
 /// A value of `0x2000` indicates no bend.
 /// A value of `0x3FFF` indicates full bend upwards.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PitchBend(pub u14);
 impl PitchBend {
     /// The minimum value of `0x0000`, indicating full bend downwards.
@@ -454,27 +529,37 @@ impl PitchBend {
 /// A "meta message", as defined by the SMF spec.
 /// These events carry metadata about the track, such as tempo, time signature, copyright, etc...
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MetaMessage<'a> {
     /// For `Format::Sequential` MIDI file types, `TrackNumber` can be empty, and defaults to
     /// the track index.
     TrackNumber(Option<u16>),
     /// Arbitrary text associated to an instant.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     Text(&'a [u8]),
     /// A copyright notice.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     Copyright(&'a [u8]),
     /// Information about the name of the track.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     TrackName(&'a [u8]),
     /// Information about the name of the current instrument.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     InstrumentName(&'a [u8]),
     /// Arbitrary lyric information associated to an instant.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     Lyric(&'a [u8]),
     /// Arbitrary marker text associated to an instant.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     Marker(&'a [u8]),
     /// Arbitrary cue point text associated to an instant.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     CuePoint(&'a [u8]),
     /// Information about the name of the current program.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     ProgramName(&'a [u8]),
     /// Name of the device that this file was intended to be played with.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     DeviceName(&'a [u8]),
     /// Number of the MIDI channel that this file was intended to be played with.
     MidiChannel(u4),
@@ -501,12 +586,17 @@ pub enum MetaMessage<'a> {
     KeySignature(i8, bool),
     /// Arbitrary data intended for the sequencer.
     /// This data is never sent to a device.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
     SequencerSpecific(&'a [u8]),
     /// An unknown or malformed meta-message.
     ///
     /// The first `u8` is the raw meta-message identifier byte.
     /// The slice is the actual payload of the meta-message.
-    Unknown(u8, &'a [u8]),
+    Unknown(
+        u8,
+        #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::bytes"))]
+        &'a [u8],
+    ),
 }
 impl<'a> MetaMessage<'a> {
     /// Remove any lifetimed data from this event to create a `MidiMessage` with `'static` lifetime
@@ -539,10 +629,10 @@ impl<'a> MetaMessage<'a> {
     }
 
     #[allow(clippy::len_zero)]
-    fn read(raw: &mut &'a [u8]) -> Result<MetaMessage<'a>> {
+    fn read(raw: &mut &'a [u8], strict: bool) -> Result<MetaMessage<'a>> {
         let type_byte = u8::read(raw).context(err_invalid!("failed to read meta message type"))?;
-        let mut data =
-            read_varlen_slice(raw).context(err_invalid!("failed to read meta message data"))?;
+        let mut data = read_varlen_slice(raw, strict)
+            .context(err_invalid!("failed to read meta message data"))?;
         Ok(match type_byte {
             0x00 => MetaMessage::TrackNumber({
                 if data.len() >= 2 {
@@ -560,12 +650,10 @@ impl<'a> MetaMessage<'a> {
             0x07 => MetaMessage::CuePoint(data),
             0x08 => MetaMessage::ProgramName(data),
             0x09 => MetaMessage::DeviceName(data),
-            0x06 => MetaMessage::Marker(data),
-            0x07 => MetaMessage::CuePoint(data),
-            0x08 => MetaMessage::ProgramName(data),
-            0x09 => MetaMessage::DeviceName(data),
-            0x20 if data.len() >= 1 => MetaMessage::MidiChannel(u4::read(&mut data)?),
-            0x21 if data.len() >= 1 => MetaMessage::MidiPort(u7::read(&mut data)?),
+            0x20 if data.len() >= 1 => {
+                MetaMessage::MidiChannel(u4::read_strict(&mut data, strict)?)
+            }
+            0x21 if data.len() >= 1 => MetaMessage::MidiPort(u7::read_strict(&mut data, strict)?),
             0x2F => MetaMessage::EndOfTrack,
             0x51 if data.len() >= 3 => MetaMessage::Tempo(u24::read(&mut data)?),
             0x54 if data.len() >= 5 => MetaMessage::SmpteOffset(
@@ -42,8 +42,50 @@ pub trait Seek: Write {
     fn write_at(&mut self, buf: &[u8], pos: u64) -> WriteResult<Self>;
 }
 
-#[derive(Copy, Clone, Debug)]
-enum Never {}
+/// A positional `Read`-like trait available even in `no_std` environments, and with per-type
+/// errors.
+///
+/// Reads take an absolute position instead of advancing an internal cursor, so several readers
+/// (for example one per track) can share a single source.
+pub trait ReadAt {
+    /// The error type specific to the reader.
+    type Error;
+
+    /// Read bytes starting at the absolute position `pos` into `buf`, returning how many bytes
+    /// were read.
+    ///
+    /// Reading less bytes than requested is allowed, and reading 0 bytes signals the end of the
+    /// source.
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> StdResult<usize, Self::Error>;
+}
+
+impl<R: ReadAt> ReadAt for &mut R {
+    type Error = R::Error;
+    #[inline]
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> StdResult<usize, R::Error> {
+        R::read_at(self, pos, buf)
+    }
+}
+
+impl ReadAt for &[u8] {
+    type Error = Never;
+    #[inline]
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> StdResult<usize, Never> {
+        let src = usize::try_from(pos)
+            .ok()
+            .and_then(|pos| self.get(pos..))
+            .unwrap_or(&[]);
+        let len = buf.len().min(src.len());
+        buf[..len].copy_from_slice(&src[..len]);
+        Ok(len)
+    }
+}
+
+/// An error that can never happen.
+///
+/// Used as the error type of infallible readers, such as in-memory slices.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Never {}
 
 /// The type used for the [`Seekable`](trait.Write.html#associatedtype.Seekable) associated type on
 /// non-seekable writers.
@@ -334,9 +376,10 @@ impl<T: io::Write> Write for IoWrap<T> {
 }
 
 /// Bridge between a `midly::io::{Write, Seek}` type and a `std::io::{Write, Seek}` type.
+/// Also bridges `midly::io::ReadAt` and `std::io::{Read, Seek}`.
 ///
-/// Always available, but only implements `midly::io::{Write, Seek}` when the `std` feature is
-/// enabled.
+/// Always available, but only implements `midly::io::{Write, Seek, ReadAt}` when the `std` feature
+/// is enabled.
 #[derive(Debug, Clone, Default)]
 pub struct SeekableWrap<T>(pub T);
 #[cfg(feature = "std")]
@@ -357,6 +400,15 @@ impl<T: io::Write + io::Seek> Write for SeekableWrap<T> {
     }
 }
 #[cfg(feature = "std")]
+impl<T: io::Read + io::Seek> ReadAt for SeekableWrap<T> {
+    type Error = io::Error;
+    #[inline]
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        io::Seek::seek(&mut self.0, io::SeekFrom::Start(pos))?;
+        io::Read::read(&mut self.0, buf)
+    }
+}
+#[cfg(feature = "std")]
 impl<T: io::Write + io::Seek> Seek for SeekableWrap<T> {
     #[inline]
     fn tell(&mut self) -> io::Result<u64> {
@@ -162,6 +162,50 @@
 //!   By enabling the `strict` feature the parser will reject uncompliant data and do
 //!   additional checking, throwing errors of the kind
 //!   [`ErrorKind::Malformed`](enum.ErrorKind.html#variant.Malformed) when such a situation arises.
+//!
+//!   Since features are shared by every crate in a dependency graph, this feature only sets the
+//!   default strictness.
+//!   Strictness can also be chosen per call by passing [`ParseOptions`](struct.ParseOptions.html)
+//!   to [`parse_with`](fn.parse_with.html) or [`Smf::parse_with`](struct.Smf.html#method.parse_with).
+//!
+//! - `serde`
+//!
+//!   Implement the `serde` traits for the [`dump`](dump/index.html) types, so that MIDI files can
+//!   be stored as JSON or any other `serde` format.
+//!
+//!   The traits are also implemented for the header, event and restricted integer types.
+//!   Integers out of range are rejected when deserializing rather than truncated, and borrowed
+//!   byte slices such as SysEx data are deserialized without copying, which requires a binary
+//!   format like `bincode`.
+//!   The owned event types such as [`OwnedTrackEvent`](struct.OwnedTrackEvent.html) copy their
+//!   payloads instead, so they can be read back from any format, including JSON.
+//!   This feature works without `std`.
+//!
+//! - `embedded` (enabled by default)
+//!
+//!   Support for MIDI transports on embedded devices, such as USB MIDI and DIN MIDI over a UART
+//!   (see the [`embedded`](embedded/index.html) module).
+//!
+//!   No particular architecture or microcontroller is assumed: any `usb-device` bus implementation
+//!   and any `embedded-hal` UART will do, and the `sim` feature provides a bus for host machines.
+//!   On targets without atomic compare-and-swap, such as the `riscv32imc` ESP32-C3, the
+//!   application has to provide a [`critical-section`](https://docs.rs/critical-section)
+//!   implementation for the USB MIDI queues.
+//!
+//!   Disabling this feature removes the dependencies on `usb-device`, `embedded-hal`, `nb` and
+//!   `heapless`.
+//!
+//! - `embassy`
+//!
+//!   An async USB MIDI class for `embassy-usb` (see the [`embassy`](embassy/index.html) module).
+//!   It enumerates with the same descriptors as the `usb-device` class of the `embedded` feature.
+//!   This feature works without `std`.
+//!
+//! - `sim`
+//!
+//!   A simulated USB bus and host (see the [`sim`](sim/index.html) module), to test USB classes on
+//!   a regular computer without any hardware.
+//!   Depends on the `embedded` and `std` features.
 
 #![cfg_attr(not(any(test, feature = "std")), no_std)]
 #![warn(missing_docs)]
@@ -196,12 +240,12 @@ mod prelude {
     #[cfg(feature = "std")]
     pub(crate) use crate::io::IoWrap;
     pub(crate) use crate::{
-        error::{ErrorKind, Result, ResultExt, StdResult},
+        error::{Error, ErrorKind, Result, ResultExt, StdResult},
         io::{Seek, Write, WriteCounter, WriteResult},
         primitive::{u14, u24, u28, u4, u7, IntRead, IntReadBottom7, SplitChecked},
     };
     #[cfg(feature = "alloc")]
-    pub(crate) use alloc::{boxed::Box, vec, vec::Vec};
+    pub(crate) use alloc::{boxed::Box, string::String, vec, vec::Vec};
     pub(crate) use core::{convert::TryFrom, fmt, marker::PhantomData, mem};
     #[cfg(feature = "std")]
     pub(crate) use std::{fs::File, io, path::Path};
@@ -217,36 +261,63 @@ mod prelude {
 mod arena;
 mod buffer;
 pub mod class;
+mod descriptor;
+pub mod dump;
+mod edit;
+pub mod embassy;
 pub mod embedded;
 mod event;
 pub mod io;
 pub mod live;
+pub mod notes;
+mod owned;
 pub mod packet;
 mod primitive;
+mod reader;
+pub mod recover;
 mod riff;
+mod rmid;
+pub mod router;
+pub mod sensing;
+mod serde_impl;
+pub mod sim;
 mod smf;
 pub mod stream;
+pub mod transform;
 pub mod usb;
+pub mod validate;
+mod writer;
+mod xmf;
 
 #[cfg(feature = "std")]
 pub use crate::smf::write_std;
 #[cfg(feature = "embedded")]
 pub use crate::{
-    class::MidiClass,
+    class::{
+        Element, ElementCap, ElementCaps, MidiClass, MidiClassBuilder, MidiQueues, MidiReceiver,
+        MidiSender,
+    },
+    embedded::SerialMidi,
     packet::{UsbMidiPacket, CIN},
-    usb::MidiDevice,
+    usb::UsbMidiEvent,
 };
 
 #[cfg(feature = "alloc")]
 pub use crate::{
     arena::Arena,
+    owned::{OwnedSmf, OwnedTrackEvent},
+    rmid::{RiffChunk, Rmid, SoundBank},
     smf::{BytemappedTrack, Smf, SmfBytemap, Track},
+    validate::{validate, Report},
+    xmf::{ResourceFormat, Xmf, XmfContent, XmfNode},
 };
 pub use crate::{
     error::{Error, ErrorKind, Result},
     event::{MetaMessage, MidiMessage, PitchBend, TrackEvent, TrackEventKind},
     primitive::{Format, Fps, SmpteTime, Timing},
-    smf::{parse, write, EventBytemapIter, EventIter, Header, TrackIter},
+    reader::{InlineTrackEvent, ReadError, SmfReader, TrackCursor},
+    smf::{parse, parse_with, write, EventBytemapIter, EventIter, Header, ParseOptions, TrackIter},
+    writer::SmfWriter,
 };
 
 /// Exotically-sized integers used by the MIDI standard.
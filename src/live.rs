@@ -18,11 +18,15 @@ use crate::{event::MidiMessage, prelude::*};
 #[cfg(feature = "alloc")]
 use crate::{event::TrackEventKind, Arena};
 
+#[cfg(feature = "alloc")]
+pub use crate::owned::OwnedLiveEvent;
+
 /// A live event produced by an OS API or generated on-the-fly, in contrast with "dead"
 /// [`TrackEvent`](../struct.TrackEvent.html)s stored in a `.mid` file.
 ///
 /// See the [`live`](index.html) module for more information.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LiveEvent<'a> {
     /// A MIDI message associated with a channel, carrying musical data.
     ///
@@ -36,6 +40,7 @@ pub enum LiveEvent<'a> {
     /// A System Common message, as defined by the MIDI spec, including System Exclusive events.
     ///
     /// Status byte in the range `0xF0 ..= 0xF7`.
+    #[cfg_attr(feature = "serde", serde(borrow))]
     Common(SystemCommon<'a>),
     /// A one-byte System Realtime message.
     ///
@@ -55,9 +60,9 @@ impl<'a> LiveEvent<'a> {
     pub fn parse(mut raw: &'a [u8]) -> Result<LiveEvent<'a>> {
         let status = raw
             .split_checked(1)
-            .ok_or_else(|| err_invalid!("no status byte"))?[0];
+            .ok_or_else(|| Error::from(err_invalid!("no status byte")).locate(0, None, None))?[0];
         let data = u7::slice_from_int(raw);
-        Self::read(status, data)
+        Self::read(status, data).map_err(|err| err.locate(0, None, None))
     }
 
     pub(crate) fn read(status: u8, data: &[u7]) -> Result<LiveEvent> {
@@ -147,6 +152,8 @@ impl<'a> LiveEvent<'a> {
     ///
     /// WARNING: Any bytestrings, including SysEx dumps, will be
     /// replaced by empty bytestrings.
+    ///
+    /// See [`OwnedLiveEvent`](struct.OwnedLiveEvent.html) for a lossless alternative.
     pub fn to_static(&self) -> LiveEvent<'static> {
         use self::LiveEvent::*;
         match *self {
@@ -210,11 +217,13 @@ impl<'a> LiveEvent<'a> {
 
 /// A "system common event", as defined by the MIDI spec.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SystemCommon<'a> {
     /// A system-exclusive event.
     ///
     /// System Exclusive events start with a `0xF0` byte and finish with a `0xF7` byte, but this
     /// slice does not include either: it only includes data bytes in the `0x00..=0x7F` range.
+    #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::u7_slice"))]
     SysEx(&'a [u7]),
     /// A MIDI Time Code Quarter Frame message, carrying a tag type and a 4-bit tag value.
     MidiTimeCodeQuarterFrame(MtcQuarterFrameMessage, u4),
@@ -226,11 +235,15 @@ pub enum SystemCommon<'a> {
     /// Request the device to tune itself.
     TuneRequest,
     /// An undefined System Common message, with arbitrary data bytes.
-    Undefined(u8, &'a [u7]),
+    Undefined(
+        u8,
+        #[cfg_attr(feature = "serde", serde(borrow, with = "crate::serde_impl::u7_slice"))]
+        &'a [u7],
+    ),
 }
 impl<'a> SystemCommon<'a> {
     #[allow(clippy::len_zero)]
-    fn read(status: u8, data: &'a [u7]) -> Result<SystemCommon<'a>> {
+    pub(crate) fn read(status: u8, data: &'a [u7]) -> Result<SystemCommon<'a>> {
         let ev = match status {
             0xF0 => {
                 //SysEx
@@ -311,6 +324,7 @@ impl<'a> SystemCommon<'a> {
 
 /// The different kinds of info a Midi Time Code Quarter Frame message can carry.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MtcQuarterFrameMessage {
     /// The low nibble of the frame count.
     FramesLow,
@@ -363,6 +377,7 @@ impl MtcQuarterFrameMessage {
 /// They are usually time-sensitive, get top priority and can even be transmitted in between other
 /// messages.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SystemRealtime {
     /// If sent, they should be sent 24 times per quarter note.
     TimingClock,
@@ -0,0 +1,233 @@
+//! Conversion between tracks of raw events and lists of notes with a duration.
+//!
+//! MIDI tracks only contain instantaneous `NoteOn` and `NoteOff` events.
+//! The [`notes`](fn.notes.html) function pairs them up into [`Note`](struct.Note.html)s, which
+//! carry their own start and duration, and [`to_track`](fn.to_track.html) does the inverse
+//! conversion:
+//!
+//! ```rust
+//! use midly_usb::{Smf, notes::{self, PairingOptions}};
+//!
+//! let smf = Smf::parse(include_bytes!("../test-asset/Clementi.mid")).unwrap();
+//! for note in notes::notes(&smf.tracks[1], PairingOptions::default()) {
+//!     println!("key {} at tick {} for {} ticks", note.key, note.start_tick, note.duration);
+//! }
+//! ```
+//!
+//! This module is only available with the `alloc` feature enabled.
+#![cfg(feature = "alloc")]
+
+use crate::{
+    event::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind},
+    prelude::*,
+    primitive::clamp_delta,
+    smf::Track,
+};
+use alloc::collections::{BTreeMap, VecDeque};
+
+/// A single note, with an absolute start time and a duration.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Note {
+    /// The MIDI channel the note plays on.
+    pub channel: u4,
+    /// The key of the note.
+    pub key: u7,
+    /// The velocity of the `NoteOn` that starts the note. Always nonzero.
+    pub velocity: u7,
+    /// The velocity of the `NoteOff` that ends the note.
+    ///
+    /// `None` if the note was ended by a `NoteOn` with zero velocity, or if it was never ended
+    /// (see [`DanglingPolicy`](enum.DanglingPolicy.html)).
+    pub release_velocity: Option<u7>,
+    /// The absolute tick at which the note starts.
+    pub start_tick: u64,
+    /// The length of the note in ticks.
+    pub duration: u64,
+}
+impl Note {
+    /// The absolute tick at which the note ends.
+    #[inline]
+    pub fn end_tick(&self) -> u64 {
+        self.start_tick + self.duration
+    }
+}
+
+/// Which note is ended when a note-off arrives while several notes with the same key and channel
+/// are playing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum OverlapPolicy {
+    /// End the note that started first.
+    Fifo,
+    /// End the note that started last.
+    Lifo,
+}
+
+/// What to do with notes that are never ended by a note-off.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum DanglingPolicy {
+    /// Drop the note altogether.
+    Drop,
+    /// End the note at the `EndOfTrack` event (or at the last event if there is none).
+    CloseAtEnd,
+}
+
+/// Options controlling how note-ons and note-offs are paired into notes.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct PairingOptions {
+    /// How to pair overlapping notes with the same key and channel.
+    pub overlap: OverlapPolicy,
+    /// What to do with notes that are never ended.
+    pub dangling: DanglingPolicy,
+}
+impl Default for PairingOptions {
+    /// First-in-first-out pairing, closing dangling notes at the end of the track.
+    #[inline]
+    fn default() -> PairingOptions {
+        PairingOptions {
+            overlap: OverlapPolicy::Fifo,
+            dangling: DanglingPolicy::CloseAtEnd,
+        }
+    }
+}
+
+/// Extract the notes in a track, sorted by start time.
+///
+/// Note-offs that do not match any playing note are ignored.
+pub fn notes(track: &[TrackEvent], options: PairingOptions) -> Vec<Note> {
+    let mut times = Vec::with_capacity(track.len());
+    let mut abs = 0;
+    for ev in track {
+        abs += ev.delta.as_int() as u64;
+        times.push(abs);
+    }
+    let end = track
+        .iter()
+        .position(|ev| ev.kind == TrackEventKind::Meta(MetaMessage::EndOfTrack))
+        .map(|idx| times[idx])
+        .unwrap_or(abs);
+    pair(track, options.overlap)
+        .into_iter()
+        .filter_map(|pair| {
+            let start_tick = times[pair.on];
+            let velocity = match track[pair.on].kind {
+                TrackEventKind::Midi {
+                    message: MidiMessage::NoteOn { vel, .. },
+                    ..
+                } => vel,
+                _ => unreachable!(),
+            };
+            let (end_tick, release_velocity) = match pair.off {
+                Some(off) => match track[off].kind {
+                    TrackEventKind::Midi {
+                        message: MidiMessage::NoteOff { vel, .. },
+                        ..
+                    } => (times[off], Some(vel)),
+                    _ => (times[off], None),
+                },
+                None => match options.dangling {
+                    DanglingPolicy::Drop => return None,
+                    DanglingPolicy::CloseAtEnd => (end.max(start_tick), None),
+                },
+            };
+            Some(Note {
+                channel: pair.channel,
+                key: pair.key,
+                velocity,
+                release_velocity,
+                start_tick,
+                duration: end_tick - start_tick,
+            })
+        })
+        .collect()
+}
+
+/// Build a track out of a list of notes, in any order.
+///
+/// Notes with a `release_velocity` are ended with a `NoteOff`, while notes without one are ended
+/// with a zero-velocity `NoteOn`.
+/// At any given tick, note-offs are placed before note-ons, except for the note-offs of notes with
+/// a zero duration, which directly follow their own note-on.
+/// An `EndOfTrack` event is placed at the end of the last note.
+pub fn to_track(notes: &[Note]) -> Track<'static> {
+    let mut events = Vec::with_capacity(notes.len() * 2);
+    for (idx, note) in notes.iter().enumerate() {
+        let off = match note.release_velocity {
+            Some(vel) => MidiMessage::NoteOff { key: note.key, vel },
+            None => MidiMessage::NoteOn {
+                key: note.key,
+                vel: 0.into(),
+            },
+        };
+        let on = MidiMessage::NoteOn {
+            key: note.key,
+            vel: note.velocity,
+        };
+        events.push((note.start_tick, 1, idx, note.channel, on));
+        if note.duration == 0 {
+            //Ending the note before it starts would leave it stuck
+            events.push((note.start_tick, 1, idx, note.channel, off));
+        } else {
+            events.push((note.end_tick(), 0, idx, note.channel, off));
+        }
+    }
+    //The sort is stable, so the note-off of an empty note stays right after its note-on
+    events.sort_by_key(|&(tick, class, idx, ..)| (tick, class, idx));
+    let mut track = Vec::with_capacity(events.len() + 1);
+    let mut prev = 0;
+    for (tick, _class, _idx, channel, message) in events {
+        track.push(TrackEvent {
+            delta: clamp_delta(tick - prev),
+            kind: TrackEventKind::Midi { channel, message },
+        });
+        prev = tick;
+    }
+    track.push(TrackEvent {
+        delta: 0.into(),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    track
+}
+
+/// A note-on event index paired with its note-off event index, if any.
+pub(crate) struct NotePair {
+    pub channel: u4,
+    pub key: u7,
+    pub on: usize,
+    pub off: Option<usize>,
+}
+
+/// Pair note-ons with note-offs by event index, sorted by note-on.
+pub(crate) fn pair(track: &[TrackEvent], overlap: OverlapPolicy) -> Vec<NotePair> {
+    let mut pairs: Vec<NotePair> = Vec::new();
+    //Indices into `pairs` of the notes still sounding, for every channel and key
+    let mut open: BTreeMap<(u4, u7), VecDeque<usize>> = BTreeMap::new();
+    for (i, ev) in track.iter().enumerate() {
+        if let TrackEventKind::Midi { channel, message } = ev.kind {
+            match message {
+                MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                    open.entry((channel, key))
+                        .or_default()
+                        .push_back(pairs.len());
+                    pairs.push(NotePair {
+                        channel,
+                        key,
+                        on: i,
+                        off: None,
+                    });
+                }
+                MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                    let sounding = open.get_mut(&(channel, key));
+                    let closed = sounding.and_then(|sounding| match overlap {
+                        OverlapPolicy::Fifo => sounding.pop_front(),
+                        OverlapPolicy::Lifo => sounding.pop_back(),
+                    });
+                    if let Some(idx) = closed {
+                        pairs[idx].off = Some(i);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    pairs
+}
@@ -0,0 +1,279 @@
+//! Lifetime-free versions of the event and file types, which own their byte payloads.
+
+#![cfg(feature = "alloc")]
+
+use crate::{
+    event::{kind_payload, kind_with_payload, TrackEvent, TrackEventKind},
+    live::{LiveEvent, SystemCommon},
+    prelude::*,
+    smf::{Header, ParseOptions, Smf},
+};
+
+/// A [`TrackEvent`](struct.TrackEvent.html) that owns its payload, and therefore has no lifetime.
+///
+/// Unlike [`TrackEvent::to_static`](struct.TrackEvent.html#method.to_static), converting into an
+/// owned event does not lose any data: SysEx dumps, escape sequences and meta-message payloads are
+/// copied into a boxed slice.
+/// Owned events can be sent across threads and channels freely, and can be borrowed back as a
+/// regular `TrackEvent` without allocating through [`as_event`](#method.as_event).
+///
+/// This type is only available with the `alloc` feature enabled.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct OwnedTrackEvent {
+    /// How many MIDI ticks after the previous event should this event fire.
+    pub delta: u28,
+    /// The event with its payload (if any) replaced by an empty slice.
+    kind: TrackEventKind<'static>,
+    payload: Box<[u8]>,
+}
+impl OwnedTrackEvent {
+    /// Copy the given event and its payload into an owned event.
+    pub fn new(event: &TrackEvent) -> OwnedTrackEvent {
+        OwnedTrackEvent {
+            delta: event.delta,
+            kind: event.kind.to_static(),
+            payload: kind_payload(&event.kind).into(),
+        }
+    }
+
+    /// Borrow this event as a regular `TrackEvent`.
+    #[inline]
+    pub fn as_event(&self) -> TrackEvent<'_> {
+        TrackEvent {
+            delta: self.delta,
+            kind: self.kind(),
+        }
+    }
+
+    /// Borrow the type of this event along with its data.
+    #[inline]
+    pub fn kind(&self) -> TrackEventKind<'_> {
+        kind_with_payload(self.kind, &self.payload)
+    }
+
+    /// Replace the type of this event along with its data, copying the payload if any.
+    pub fn set_kind(&mut self, kind: TrackEventKind) {
+        self.kind = kind.to_static();
+        self.payload = kind_payload(&kind).into();
+    }
+
+    /// The payload of this event, or an empty slice if the event has no payload.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+impl<'a> From<TrackEvent<'a>> for OwnedTrackEvent {
+    #[inline]
+    fn from(event: TrackEvent<'a>) -> OwnedTrackEvent {
+        OwnedTrackEvent::new(&event)
+    }
+}
+impl<'a, 'b> From<&'b TrackEvent<'a>> for OwnedTrackEvent {
+    #[inline]
+    fn from(event: &'b TrackEvent<'a>) -> OwnedTrackEvent {
+        OwnedTrackEvent::new(event)
+    }
+}
+impl<'a> From<&'a OwnedTrackEvent> for TrackEvent<'a> {
+    #[inline]
+    fn from(event: &'a OwnedTrackEvent) -> TrackEvent<'a> {
+        event.as_event()
+    }
+}
+
+/// A [`LiveEvent`](enum.LiveEvent.html) that owns its payload, and therefore has no
+/// lifetime.
+///
+/// Unlike [`LiveEvent::to_static`](enum.LiveEvent.html#method.to_static), SysEx dumps and
+/// the data of undefined System Common messages are kept, copied into a boxed slice.
+///
+/// This type is only available with the `alloc` feature enabled.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct OwnedLiveEvent {
+    /// The event with its payload (if any) replaced by an empty slice.
+    event: LiveEvent<'static>,
+    payload: Box<[u7]>,
+}
+impl OwnedLiveEvent {
+    /// Copy the given event and its payload into an owned event.
+    pub fn new(event: &LiveEvent) -> OwnedLiveEvent {
+        let payload: &[u7] = match event {
+            LiveEvent::Common(SystemCommon::SysEx(data))
+            | LiveEvent::Common(SystemCommon::Undefined(_, data)) => data,
+            _ => &[],
+        };
+        OwnedLiveEvent {
+            event: event.to_static(),
+            payload: payload.into(),
+        }
+    }
+
+    /// Parse a raw MIDI message into an owned event.
+    ///
+    /// See [`LiveEvent::parse`](enum.LiveEvent.html#method.parse) for details.
+    #[inline]
+    pub fn parse(raw: &[u8]) -> Result<OwnedLiveEvent> {
+        Ok(OwnedLiveEvent::new(&LiveEvent::parse(raw)?))
+    }
+
+    /// Borrow this event as a regular `LiveEvent`.
+    #[inline]
+    pub fn as_event(&self) -> LiveEvent<'_> {
+        match self.event {
+            LiveEvent::Common(SystemCommon::SysEx(_)) => {
+                LiveEvent::Common(SystemCommon::SysEx(&self.payload))
+            }
+            LiveEvent::Common(SystemCommon::Undefined(status, _)) => {
+                LiveEvent::Common(SystemCommon::Undefined(status, &self.payload))
+            }
+            event => event,
+        }
+    }
+
+    /// Write a single live event out.
+    ///
+    /// See [`LiveEvent::write`](enum.LiveEvent.html#method.write) for details.
+    #[inline]
+    pub fn write<W: Write>(&self, out: &mut W) -> WriteResult<W> {
+        self.as_event().write(out)
+    }
+
+    /// Write a single live event out, using running status if possible.
+    ///
+    /// See
+    /// [`LiveEvent::write_with_running_status`](enum.LiveEvent.html#method.write_with_running_status)
+    /// for details.
+    #[inline]
+    pub fn write_with_running_status<W: Write>(
+        &self,
+        running_status: &mut Option<u8>,
+        out: &mut W,
+    ) -> WriteResult<W> {
+        self.as_event()
+            .write_with_running_status(running_status, out)
+    }
+
+    /// Write a single live event out to the given `std::io::Write` writer.
+    ///
+    /// This function is only available with the `std` feature enabled.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn write_std<W: io::Write>(&self, out: W) -> io::Result<()> {
+        self.as_event().write_std(out)
+    }
+}
+impl<'a> From<LiveEvent<'a>> for OwnedLiveEvent {
+    #[inline]
+    fn from(event: LiveEvent<'a>) -> OwnedLiveEvent {
+        OwnedLiveEvent::new(&event)
+    }
+}
+impl<'a, 'b> From<&'b LiveEvent<'a>> for OwnedLiveEvent {
+    #[inline]
+    fn from(event: &'b LiveEvent<'a>) -> OwnedLiveEvent {
+        OwnedLiveEvent::new(event)
+    }
+}
+impl<'a> From<&'a OwnedLiveEvent> for LiveEvent<'a> {
+    #[inline]
+    fn from(event: &'a OwnedLiveEvent) -> LiveEvent<'a> {
+        event.as_event()
+    }
+}
+
+/// An [`Smf`](struct.Smf.html) made up of [`OwnedTrackEvent`](struct.OwnedTrackEvent.html)s, and
+/// therefore independent of the bytes it was parsed from.
+///
+/// Unlike [`Smf::to_static`](struct.Smf.html#method.to_static), no payloads are lost.
+/// A borrowed `Smf` can be recovered through [`to_smf`](#method.to_smf), which only allocates the
+/// track vectors.
+///
+/// This type is only available with the `alloc` feature enabled.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OwnedSmf {
+    /// The header of this MIDI file, indicating tempo information and track format.
+    pub header: Header,
+    /// A list of tracks within this MIDI file.
+    pub tracks: Vec<Vec<OwnedTrackEvent>>,
+}
+impl OwnedSmf {
+    /// Create a new empty `OwnedSmf` with zero tracks, using the given header.
+    #[inline]
+    pub fn new(header: Header) -> OwnedSmf {
+        OwnedSmf {
+            header,
+            tracks: vec![],
+        }
+    }
+
+    /// Parse a `.mid` Standard Midi File from its raw bytes, copying all payloads.
+    #[inline]
+    pub fn parse(raw: &[u8]) -> Result<OwnedSmf> {
+        OwnedSmf::parse_with(raw, ParseOptions::default())
+    }
+
+    /// Parse a `.mid` Standard Midi File from its raw bytes using the given parsing options,
+    /// copying all payloads.
+    #[inline]
+    pub fn parse_with(raw: &[u8], options: ParseOptions) -> Result<OwnedSmf> {
+        Ok(OwnedSmf::from(&Smf::parse_with(raw, options)?))
+    }
+
+    /// Borrow this file as a regular `Smf`.
+    pub fn to_smf(&self) -> Smf<'_> {
+        Smf {
+            header: self.header,
+            tracks: self
+                .tracks
+                .iter()
+                .map(|track| track.iter().map(OwnedTrackEvent::as_event).collect())
+                .collect(),
+        }
+    }
+
+    /// Encodes and writes the file to the given generic writer.
+    ///
+    /// See [`Smf::write`](struct.Smf.html#method.write) for details.
+    #[inline]
+    pub fn write<W: Write>(&self, out: &mut W) -> WriteResult<W> {
+        self.to_smf().write(out)
+    }
+
+    /// Encodes and writes the file to the given `std::io::Write` writer.
+    ///
+    /// This function is only available with the `std` feature enabled.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn write_std<W: io::Write>(&self, out: W) -> io::Result<()> {
+        self.to_smf().write_std(out)
+    }
+
+    /// Encodes and writes the file to the given path.
+    ///
+    /// This function is only available with the `std` feature enabled.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.to_smf().save(path)
+    }
+}
+impl<'a, 'b> From<&'b Smf<'a>> for OwnedSmf {
+    fn from(smf: &'b Smf<'a>) -> OwnedSmf {
+        OwnedSmf {
+            header: smf.header,
+            tracks: smf
+                .tracks
+                .iter()
+                .map(|track| track.iter().map(OwnedTrackEvent::new).collect())
+                .collect(),
+        }
+    }
+}
+impl<'a> From<Smf<'a>> for OwnedSmf {
+    #[inline]
+    fn from(smf: Smf<'a>) -> OwnedSmf {
+        OwnedSmf::from(&smf)
+    }
+}
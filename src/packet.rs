@@ -1,6 +1,7 @@
 use crate::io::{Write, WriteResult};
-use crate::live::LiveEvent;
+use crate::live::{LiveEvent, SystemCommon};
 use crate::num::{u4, u7};
+use crate::prelude::*;
 use crate::usb::*;
 use crate::MidiMessage;
 
@@ -112,6 +113,39 @@ impl<'a> UsbMidiPacket<'a> {
             event: LiveEvent::parse(data).expect("invalid data"),
         }
     }
+    /// Parse a single 4-byte USB MIDI event packet.
+    ///
+    /// Unlike [`read`](#method.read), this method does not panic: packets with a reserved code
+    /// index number and SysEx fragments that do not hold a whole message (from `0xF0` to `0xF7`)
+    /// are reported as errors.
+    pub fn parse(packet: &'a [u8]) -> Result<UsbMidiPacket<'a>> {
+        ensure!(packet.len() >= 4, err_invalid!("usb midi packet too short"));
+        let cn = u4::from_int_lossy(packet[0] >> 4);
+        let cin = CIN::from(u4::from_int_lossy(packet[0]));
+        let data = &packet[1..=Self::packet_length(cin)];
+        match cin {
+            CIN::MiscFunction | CIN::CableEvent => {
+                bail!(err_invalid!("reserved usb midi code index number"))
+            }
+            CIN::SysExStartOrContinue
+            | CIN::SingleByteSysComOrSysExEnd
+            | CIN::TwoByteSysExEnd
+            | CIN::ThreeByteSysExEnd => {
+                let sysex = data[0] == 0xF0 || data[data.len() - 1] == 0xF7;
+                let complete = data[0] == 0xF0 && data.len() > 1 && data[data.len() - 1] == 0xF7;
+                ensure!(
+                    complete || (!sysex && cin == CIN::SingleByteSysComOrSysExEnd),
+                    err_invalid!("incomplete sysex fragment")
+                );
+            }
+            _ => {}
+        }
+        Ok(UsbMidiPacket {
+            cable_number: cn,
+            code_index_number: cin,
+            event: LiveEvent::parse(data)?,
+        })
+    }
     pub fn write<W: Write>(&self, out: &mut W) -> WriteResult<W> {
         let packet_header: u8 = self.code_index_number.as_int() | (self.cable_number.as_int() << 4);
         out.write(&[packet_header])?;
@@ -136,3 +170,92 @@ impl<'a> UsbMidiPacket<'a> {
         Ok(())
     }
 }
+
+/// Encode an event into USB MIDI event packets on the given cable.
+///
+/// SysEx messages are split into as many packets as necessary, including their `0xF0` and `0xF7`
+/// delimiters.
+/// Undefined System Common messages with more than 2 data bytes cannot be encoded and yield no
+/// packets.
+pub(crate) fn packetize<'a>(cable: u4, event: &LiveEvent<'a>) -> Packets<'a> {
+    let mut packets = Packets {
+        cable,
+        sysex: None,
+        single: None,
+        pos: 0,
+    };
+    match event {
+        LiveEvent::Common(SystemCommon::SysEx(data)) => {
+            packets.sysex = Some(u7::slice_as_int(data));
+        }
+        event => {
+            let mut buf = [0; 3];
+            let len = {
+                let mut out = &mut buf[..];
+                if event.write(&mut out).is_err() {
+                    //Undefined System Common messages with too much data
+                    return packets;
+                }
+                3 - out.len()
+            };
+            let cin = match event {
+                LiveEvent::Midi { message, .. } => CIN::from(u4::new(message.status_nibble())),
+                LiveEvent::Common(_) => match len {
+                    1 => CIN::SingleByteSysComOrSysExEnd,
+                    2 => CIN::TwoByteSysCom,
+                    _ => CIN::ThreeByteSysCom,
+                },
+                LiveEvent::Realtime(_) => CIN::SingleByte,
+            };
+            packets.single = Some([packets.header(cin), buf[0], buf[1], buf[2]]);
+        }
+    }
+    packets
+}
+
+/// The USB MIDI event packets of a single event, as returned by `packetize`.
+#[derive(Clone, Debug)]
+pub(crate) struct Packets<'a> {
+    cable: u4,
+    /// The SysEx data without its delimiters, if the event is a SysEx message.
+    sysex: Option<&'a [u8]>,
+    /// The packet of any other event.
+    single: Option<[u8; 4]>,
+    /// The position within the SysEx message, counting the `0xF0` delimiter.
+    pos: usize,
+}
+impl<'a> Packets<'a> {
+    #[inline]
+    fn header(&self, cin: CIN) -> u8 {
+        self.cable.as_int() << 4 | cin.as_int()
+    }
+}
+impl<'a> Iterator for Packets<'a> {
+    type Item = [u8; 4];
+
+    fn next(&mut self) -> Option<[u8; 4]> {
+        let data = match self.sysex {
+            Some(data) => data,
+            None => return self.single.take(),
+        };
+        //Include the `0xF0` and `0xF7` delimiters, 3 bytes per packet
+        let total = data.len() + 2;
+        let remaining = total.checked_sub(self.pos).filter(|&rem| rem > 0)?;
+        let (cin, len) = match remaining {
+            1 => (CIN::SingleByteSysComOrSysExEnd, 1),
+            2 => (CIN::TwoByteSysExEnd, 2),
+            3 => (CIN::ThreeByteSysExEnd, 3),
+            _ => (CIN::SysExStartOrContinue, 3),
+        };
+        let mut packet = [self.header(cin), 0, 0, 0];
+        for (i, byte) in (self.pos..).zip(&mut packet[1..1 + len]) {
+            *byte = match i {
+                0 => 0xF0,
+                i if i == total - 1 => 0xF7,
+                i => data[i - 1],
+            };
+        }
+        self.pos += len;
+        Some(packet)
+    }
+}
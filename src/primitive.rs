@@ -24,12 +24,20 @@ impl<'a> SplitChecked for &'a [u8] {
 pub(crate) trait IntRead: Sized {
     /// Reads a big-endian integer.
     fn read(data: &mut &[u8]) -> StdResult<Self, &'static ErrorKind>;
+
+    /// Reads a big-endian integer, rejecting out-of-range values only if `strict` is set.
+    #[inline]
+    fn read_strict(data: &mut &[u8], _strict: bool) -> StdResult<Self, &'static ErrorKind> {
+        Self::read(data)
+    }
 }
 /// Reads the int from u7 bytes, that is, the top bit in all bytes is ignored.
 /// For raw reading on integer types, use `read_raw`.
 pub(crate) trait IntReadBottom7: Sized {
     /// Read an int from bytes, but only using the bottom 7 bits of each byte.
-    fn read_u7(data: &mut &[u8]) -> StdResult<Self, &'static ErrorKind>;
+    ///
+    /// Malformed integers are only rejected if `strict` is set.
+    fn read_u7(data: &mut &[u8], strict: bool) -> StdResult<Self, &'static ErrorKind>;
 }
 
 /// Implement simple big endian integer reads.
@@ -57,10 +65,10 @@ impl_read_int! {u8,u16,u32}
 macro_rules! int_feature {
     { $name:ident ; $inner:tt : read_u7 } => {
         impl IntReadBottom7 for $name {
-            fn read_u7(raw: &mut &[u8]) -> StdResult<$name, &'static ErrorKind> {
+            fn read_u7(raw: &mut &[u8], strict: bool) -> StdResult<$name, &'static ErrorKind> {
                 let bytes = raw.split_checked(mem::size_of::<$inner>())
                     .ok_or(err_invalid!("failed to read the expected integer"))?;
-                if cfg!(feature = "strict") {
+                if strict {
                     ensure!(bytes.iter().all(|byte| bit_range!(*byte, 7..8)==0), err_malformed!("invalid byte with top bit set"));
                 }
                 let raw = bytes.iter().fold(0, |mut acc,byte| {
@@ -68,7 +76,7 @@ macro_rules! int_feature {
                     acc |= bit_range!(*byte, 0..7) as $inner;
                     acc
                 });
-                Ok(if cfg!(feature = "strict") {
+                Ok(if strict {
                     Self::try_from(raw).ok_or(err_malformed!(stringify!("expected " $name ", found " $inner)))?
                 }else{
                     //Ignore and truncate extra bits
@@ -81,8 +89,12 @@ macro_rules! int_feature {
         impl IntRead for $name {
             #[inline]
             fn read(raw: &mut &[u8]) -> StdResult<Self, &'static ErrorKind> {
+                Self::read_strict(raw, cfg!(feature = "strict"))
+            }
+            #[inline]
+            fn read_strict(raw: &mut &[u8], strict: bool) -> StdResult<Self, &'static ErrorKind> {
                 let raw = $inner::read(raw)?;
-                if cfg!(feature = "strict") {
+                if strict {
                     Ok(Self::try_from(raw).ok_or(err_malformed!(concat!("expected ", stringify!($name), ", found ", stringify!($inner))))?)
                 }else{
                     //Throw away extra bits
@@ -116,6 +128,26 @@ macro_rules! restricted_int {
                 fmt::Display::fmt(&self.0, f)
             }
         }
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $name {
+            #[inline]
+            fn serialize<S: serde::Serializer>(&self, ser: S) -> StdResult<S::Ok, S::Error> {
+                serde::Serialize::serialize(&self.0, ser)
+            }
+        }
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $name {
+            /// Fails if the integer is out of range, instead of masking off the extra bits.
+            fn deserialize<D: serde::Deserializer<'de>>(de: D) -> StdResult<$name, D::Error> {
+                let raw = <$inner as serde::Deserialize>::deserialize(de)?;
+                $name::try_from(raw).ok_or_else(|| {
+                    <D::Error as serde::de::Error>::invalid_value(
+                        serde::de::Unexpected::Unsigned(raw as u64),
+                        &concat!("a ", stringify!($bits), "-bit integer"),
+                    )
+                })
+            }
+        }
         impl $name {
             const MASK: $inner = (1 << $bits) - 1;
 
@@ -350,13 +382,21 @@ restricted_int! {
     u28: u32 => 28;
 }
 impl IntReadBottom7 for u28 {
-    fn read_u7(raw: &mut &[u8]) -> StdResult<u28, &'static ErrorKind> {
+    #[inline]
+    fn read_u7(raw: &mut &[u8], strict: bool) -> StdResult<u28, &'static ErrorKind> {
+        u28::read_varlen(raw, strict)
+    }
+}
+
+impl u28 {
+    /// Read a variable-length integer, only rejecting malformed integers if `strict` is set.
+    pub(crate) fn read_varlen(raw: &mut &[u8], strict: bool) -> StdResult<u28, &'static ErrorKind> {
         let mut int: u32 = 0;
         for _ in 0..4 {
             let byte = match raw.split_checked(1) {
                 Some(slice) => slice[0],
                 None => {
-                    if cfg!(feature = "strict") {
+                    if strict {
                         bail!(err_malformed!("unexpected eof while reading varlen int"))
                     } else {
                         //Stay with what was read
@@ -372,16 +412,14 @@ impl IntReadBottom7 for u28 {
                 return Ok(u28::from(int));
             }
         }
-        if cfg!(feature = "strict") {
+        if strict {
             Err(err_malformed!("varlen integer larger than 4 bytes"))
         } else {
             //Use the 4 bytes as-is
             Ok(u28::from(int))
         }
     }
-}
 
-impl u28 {
     pub(crate) fn write_varlen<W: Write>(&self, out: &mut W) -> WriteResult<W> {
         let int = self.as_int();
         let mut skipping = true;
@@ -406,15 +444,22 @@ impl u28 {
     }
 }
 
+/// Clamp an absolute tick difference into a valid delta time.
+#[inline]
+#[cfg(feature = "alloc")]
+pub(crate) fn clamp_delta(ticks: u64) -> u28 {
+    u28::new(ticks.min(u28::max_value().as_int() as u64) as u32)
+}
+
 /// Reads a slice represented in the input as a `u28` `len` followed by `len` bytes.
-pub(crate) fn read_varlen_slice<'a>(raw: &mut &'a [u8]) -> Result<&'a [u8]> {
-    let len = u28::read_u7(raw)
+pub(crate) fn read_varlen_slice<'a>(raw: &mut &'a [u8], strict: bool) -> Result<&'a [u8]> {
+    let len = u28::read_varlen(raw, strict)
         .context(err_invalid!("failed to read varlen slice length"))?
         .as_int();
     Ok(match raw.split_checked(len as usize) {
         Some(slice) => slice,
         None => {
-            if cfg!(feature = "strict") {
+            if strict {
                 bail!(err_malformed!("incomplete varlen slice"))
             } else {
                 mem::replace(raw, &[])
@@ -436,6 +481,7 @@ pub(crate) fn write_varlen_slice<W: Write>(slice: &[u8], out: &mut W) -> WriteRe
 
 /// The order in which tracks should be laid out when playing back this SMF file.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Format {
     /// This file should have a single track only.
     ///
@@ -476,6 +522,7 @@ impl Format {
 /// The timing for an SMF file.
 /// This can be in ticks/beat or ticks/second.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Timing {
     /// Specifies ticks/beat as a 15-bit integer.
     ///
@@ -524,6 +571,11 @@ impl Timing {
 /// - `frame` is inside [0,fps[
 /// - `subframe` is inside [0,99]
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "crate::serde_impl::RawSmpteTime")
+)]
 pub struct SmpteTime {
     hour: u8,
     minute: u8,
@@ -640,6 +692,7 @@ impl SmpteTime {
 
 /// One of the four FPS values available for SMPTE times, as defined by the MIDI standard.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Fps {
     /// 24 frames per second.
     Fps24,
@@ -0,0 +1,458 @@
+//! Incremental parsing of SMF files out of a byte source, without loading the whole file.
+
+use crate::{
+    event::{kind_payload, kind_with_payload, MidiMessage, TrackEvent, TrackEventKind},
+    io::ReadAt,
+    prelude::*,
+    smf::{Header, ParseOptions},
+};
+
+/// The largest amount of bytes needed to find out the length of an event: a 4-byte delta, a status
+/// byte, a meta type byte and a 4-byte length.
+const EVENT_HEAD_LEN: usize = 4 + 1 + 1 + 4;
+
+/// An error while reading a file incrementally: either the source failed, or the data is invalid.
+#[derive(Clone, Debug)]
+pub enum ReadError<E> {
+    /// The underlying byte source failed.
+    Io(E),
+    /// The file is not a valid MIDI file.
+    Parse(Error),
+}
+impl<E> From<Error> for ReadError<E> {
+    #[inline]
+    fn from(err: Error) -> ReadError<E> {
+        ReadError::Parse(err)
+    }
+}
+impl<E: fmt::Display> fmt::Display for ReadError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Io(err) => write!(f, "failed to read midi: {}", err),
+            ReadError::Parse(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl<E: std::error::Error> std::error::Error for ReadError<E> {}
+
+/// Reads a Standard Midi File (or RMID file) incrementally from a byte source.
+///
+/// Unlike [`parse`](fn.parse.html), the file does not need to be in memory: the reader only reads
+/// the header upfront, and then locates track chunks on demand with
+/// [`next_track`](#method.next_track).
+/// Each track gets its own [`TrackCursor`](struct.TrackCursor.html), which reads events one at a
+/// time into small fixed-size events, so several tracks can be played back at once out of a single
+/// source by interleaving calls to their cursors:
+///
+/// ```rust
+/// use midly_usb::{SmfReader, TrackCursor};
+///
+/// let file: &[u8] = include_bytes!("../test-asset/Clementi.mid");
+/// let mut reader = SmfReader::new(file).unwrap();
+/// let mut cursors: Vec<TrackCursor<64>> = Vec::new();
+/// while let Some(cursor) = reader.next_track().unwrap() {
+///     cursors.push(cursor);
+/// }
+/// for cursor in &mut cursors {
+///     while let Some(event) = cursor.next_event(reader.source()).unwrap() {
+///         println!("{:?}", event);
+///     }
+/// }
+/// ```
+///
+/// This type is always available, even in `no_std` environments.
+#[derive(Clone, Debug)]
+pub struct SmfReader<R> {
+    src: R,
+    header: Header,
+    track_count_hint: u16,
+    options: ParseOptions,
+    /// Position of the next chunk.
+    next_chunk: u64,
+    /// Position of the end of the SMF data.
+    end: u64,
+    /// Index of the next chunk.
+    chunk: usize,
+    track_count: usize,
+}
+impl<R: ReadAt> SmfReader<R> {
+    /// Read the header of a file, using the default parsing options.
+    #[inline]
+    pub fn new(src: R) -> StdResult<SmfReader<R>, ReadError<R::Error>> {
+        SmfReader::new_with(src, ParseOptions::default())
+    }
+
+    /// Read the header of a file, using the given parsing options.
+    ///
+    /// The options are passed on to the cursors created by this reader.
+    pub fn new_with(
+        mut src: R,
+        options: ParseOptions,
+    ) -> StdResult<SmfReader<R>, ReadError<R::Error>> {
+        let locate = |kind| ReadError::Parse(Error::from(kind).locate(0, None, None));
+        let mut head = [0; 12];
+        let len = read_full(&mut src, 0, &mut head)?;
+        let (start, end) = match &head[..len.min(4)] {
+            b"RIFF" if len == 12 && &head[8..12] == b"RMID" => find_riff_data(&mut src)?
+                .ok_or_else(|| locate(err_invalid!("no rmid data chunk")))?,
+            b"RIFF" => return Err(locate(err_invalid!("not an rmid riff file"))),
+            b"MThd" => (0, u64::MAX),
+            _ => return Err(locate(err_invalid!("not a midi file"))),
+        };
+        let mut chunk = [0; 8 + 6];
+        let len = read_full(&mut src, start, &mut chunk)?;
+        let chunk_len = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        let (header, track_count_hint) = match &chunk[..len.min(4)] {
+            b"MThd" => Header::read(&chunk[8..len.max(8).min(8 + chunk_len as usize)])
+                .context(err_invalid!("invalid midi header")),
+            _ => Err(err_invalid!("expected header, found track").into()),
+        }
+        .map_err(|err| err.locate(start as usize, Some(0), None))?;
+        Ok(SmfReader {
+            src,
+            header,
+            track_count_hint,
+            options,
+            next_chunk: start + 8 + chunk_len as u64,
+            end,
+            chunk: 1,
+            track_count: 0,
+        })
+    }
+
+    /// The header of the file.
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The amount of tracks declared in the header.
+    ///
+    /// The actual amount of track chunks may differ in malformed files.
+    #[inline]
+    pub fn track_count_hint(&self) -> u16 {
+        self.track_count_hint
+    }
+
+    /// The byte source, to be passed to [`TrackCursor::next_event`](struct.TrackCursor.html#method.next_event).
+    #[inline]
+    pub fn source(&mut self) -> &mut R {
+        &mut self.src
+    }
+
+    /// Get the byte source back.
+    #[inline]
+    pub fn into_source(self) -> R {
+        self.src
+    }
+
+    /// Locate the next track chunk, returning a cursor over its events.
+    ///
+    /// Unknown chunks are skipped, and so are duplicate headers unless parsing strictly.
+    /// Returns `None` at the end of the file.
+    ///
+    /// The buffer size `N` of the cursor bounds the size of the largest event that can be read.
+    pub fn next_track<const N: usize>(
+        &mut self,
+    ) -> StdResult<Option<TrackCursor<N>>, ReadError<R::Error>> {
+        loop {
+            let pos = self.next_chunk;
+            let chunk = self.chunk;
+            let locate = |kind: &'static ErrorKind| {
+                ReadError::Parse(Error::from(kind).locate(pos as usize, Some(chunk), None))
+            };
+            if pos >= self.end {
+                return Ok(None);
+            }
+            let mut head = [0; 8];
+            match read_full(&mut self.src, pos, &mut head)? {
+                0 => return Ok(None),
+                8 => {}
+                _ if self.options.strict => {
+                    return Err(locate(err_malformed!("failed to read chunk header")))
+                }
+                _ => return Ok(None),
+            }
+            let len = u32::from_be_bytes([head[4], head[5], head[6], head[7]]) as u64;
+            let end = (pos + 8 + len).min(self.end);
+            self.next_chunk = pos + 8 + len;
+            self.chunk += 1;
+            match &head[..4] {
+                b"MTrk" => {
+                    if self.track_count >= self.options.max_tracks {
+                        self.next_chunk = self.end;
+                        return Err(locate(err_invalid!("file exceeds track limit")));
+                    }
+                    self.track_count += 1;
+                    return Ok(Some(TrackCursor {
+                        pos: pos + 8,
+                        end,
+                        running_status: None,
+                        options: self.options,
+                        chunk,
+                        event: 0,
+                    }));
+                }
+                b"MThd" if self.options.strict => {
+                    return Err(locate(err_malformed!("found duplicate header")))
+                }
+                //Unknown chunk or duplicate header, just ignore and read the next one
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A cursor over the events of a single track, created by
+/// [`SmfReader::next_track`](struct.SmfReader.html#method.next_track).
+///
+/// The cursor only holds its position within the source, and reads each event into an
+/// [`InlineTrackEvent`](struct.InlineTrackEvent.html) of `N` bytes.
+/// Events larger than `N` bytes in their encoded form (usually long SysEx messages) cannot be read:
+/// instead an error is returned, but the cursor skips over the event, so reading may continue if
+/// the error is ignored.
+///
+/// This type is always available, even in `no_std` environments.
+#[derive(Clone, Debug)]
+pub struct TrackCursor<const N: usize> {
+    pos: u64,
+    end: u64,
+    running_status: Option<u8>,
+    options: ParseOptions,
+    chunk: usize,
+    event: usize,
+}
+impl<const N: usize> TrackCursor<N> {
+    /// The absolute position of the next event within the source.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Whether all events in the track have been read.
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.pos >= self.end
+    }
+
+    /// Read the next event of the track from the given source, which should be the same source the
+    /// cursor was created from.
+    ///
+    /// The event holds its payload inline, so any amount of events may be kept around.
+    /// Returns `None` at the end of the track.
+    pub fn next_event<R: ReadAt>(
+        &mut self,
+        src: &mut R,
+    ) -> StdResult<Option<InlineTrackEvent<N>>, ReadError<R::Error>> {
+        if self.pos >= self.end {
+            return Ok(None);
+        }
+        let (pos, chunk, event) = (self.pos, Some(self.chunk), Some(self.event));
+        let locate = |err: Error| ReadError::Parse(err.locate(pos as usize, chunk, event));
+        if self.event >= self.options.max_track_events {
+            self.pos = self.end;
+            return Err(locate(err_invalid!("track exceeds event limit").into()));
+        }
+        //Find out the length of the event
+        let mut head = [0; EVENT_HEAD_LEN];
+        let avail = (self.end - pos).min(EVENT_HEAD_LEN as u64) as usize;
+        let got = read_full(src, pos, &mut head[..avail])?;
+        let (len, status) = match event_len(&head[..got], self.running_status, self.options.strict)
+        {
+            Ok(len) => len,
+            Err(err) => return self.fail(locate(err.into())),
+        };
+        //Truncated tracks are handled when parsing the event
+        let len = len.min(self.end - pos);
+        if len > N as u64 {
+            self.pos += len;
+            self.event += 1;
+            //Keep the running status as it would be after the skipped event
+            match status {
+                0x00..=0x7F => {}
+                0x80..=0xEF => self.running_status = Some(status),
+                _ => self.running_status = None,
+            }
+            return Err(locate(err_invalid!("event does not fit in buffer").into()));
+        }
+        let len = len as usize;
+        let mut buf = [0; N];
+        let got = read_full(src, pos, &mut buf[..len])?;
+        if got < len && self.options.strict {
+            return self.fail(locate(
+                err_malformed!("reached eof before chunk ended").into(),
+            ));
+        }
+        if let Err(err) = self.options.check_event(&buf[..got]) {
+            self.pos = self.end;
+            return Err(locate(err.into()));
+        }
+        let mut raw = &buf[..got];
+        match TrackEvent::read(&mut raw, &mut self.running_status, self.options.strict) {
+            Ok(ev) => {
+                self.pos += len as u64;
+                self.event += 1;
+                Ok(Some(InlineTrackEvent::new(&ev)))
+            }
+            Err(err) => {
+                let err = Err(locate(err));
+                self.pos = self.end;
+                if self.options.strict {
+                    err
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Stop reading the track after a corrupt event, raising the error only in strict mode.
+    fn fail<T, E>(&mut self, err: ReadError<E>) -> StdResult<Option<T>, ReadError<E>> {
+        self.pos = self.end;
+        if self.options.strict {
+            Err(err)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// A [`TrackEvent`](struct.TrackEvent.html) read by a [`TrackCursor`](struct.TrackCursor.html),
+/// which holds its payload inline in a buffer of `N` bytes and therefore has no lifetime.
+///
+/// It works like [`OwnedTrackEvent`](struct.OwnedTrackEvent.html), but without allocating: the
+/// event can be borrowed back as a regular `TrackEvent` through [`as_event`](#method.as_event).
+///
+/// This type is always available, even in `no_std` environments.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct InlineTrackEvent<const N: usize> {
+    /// How many MIDI ticks after the previous event should this event fire.
+    pub delta: u28,
+    /// The event with its payload (if any) replaced by an empty slice.
+    kind: TrackEventKind<'static>,
+    payload: [u8; N],
+    len: usize,
+}
+impl<const N: usize> InlineTrackEvent<N> {
+    /// Copy the given event into an inline event, or return `None` if its payload does not fit in
+    /// `N` bytes.
+    pub fn try_new(event: &TrackEvent) -> Option<InlineTrackEvent<N>> {
+        let data = kind_payload(&event.kind);
+        let mut payload = [0; N];
+        payload.get_mut(..data.len())?.copy_from_slice(data);
+        Some(InlineTrackEvent {
+            delta: event.delta,
+            kind: event.kind.to_static(),
+            payload,
+            len: data.len(),
+        })
+    }
+
+    /// Copy an event parsed out of a buffer of `N` bytes, whose payload always fits.
+    #[inline]
+    fn new(event: &TrackEvent) -> InlineTrackEvent<N> {
+        InlineTrackEvent::try_new(event).expect("payload larger than its event")
+    }
+
+    /// Borrow this event as a regular `TrackEvent`.
+    #[inline]
+    pub fn as_event(&self) -> TrackEvent<'_> {
+        TrackEvent {
+            delta: self.delta,
+            kind: self.kind(),
+        }
+    }
+
+    /// Borrow the type of this event along with its data.
+    #[inline]
+    pub fn kind(&self) -> TrackEventKind<'_> {
+        kind_with_payload(self.kind, self.payload())
+    }
+
+    /// The payload of this event, or an empty slice if the event has no payload.
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+}
+impl<'a, const N: usize> From<&'a InlineTrackEvent<N>> for TrackEvent<'a> {
+    #[inline]
+    fn from(event: &'a InlineTrackEvent<N>) -> TrackEvent<'a> {
+        event.as_event()
+    }
+}
+
+/// Read as many bytes as possible into `buf`, stopping only at the end of the source.
+fn read_full<R: ReadAt>(
+    src: &mut R,
+    pos: u64,
+    buf: &mut [u8],
+) -> StdResult<usize, ReadError<R::Error>> {
+    let mut read = 0;
+    while read < buf.len() {
+        match src.read_at(pos + read as u64, &mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) => return Err(ReadError::Io(err)),
+        }
+    }
+    Ok(read)
+}
+
+/// Find the bounds of the SMF data within an RMID file.
+fn find_riff_data<R: ReadAt>(src: &mut R) -> StdResult<Option<(u64, u64)>, ReadError<R::Error>> {
+    let mut pos = 12;
+    loop {
+        let mut head = [0; 8];
+        if read_full(src, pos, &mut head)? < 8 {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes([head[4], head[5], head[6], head[7]]) as u64;
+        if &head[..4] == b"data" {
+            return Ok(Some((pos + 8, pos + 8 + len)));
+        }
+        //Chunks are padded to an even length
+        pos += 8 + len + len % 2;
+    }
+}
+
+/// Find out the full length of an event out of its first few bytes, without parsing it.
+///
+/// Also returns the first byte after the delta time, which is either the status or a data byte.
+fn event_len(
+    head: &[u8],
+    running_status: Option<u8>,
+    strict: bool,
+) -> StdResult<(u64, u8), &'static ErrorKind> {
+    let mut raw = head;
+    let _delta = u28::read_varlen(&mut raw, strict)?;
+    let status = *raw.first().ok_or(err_invalid!("failed to read status"))?;
+    let data_len = match status {
+        0x00..=0x7F => {
+            let status = running_status.ok_or(err_invalid!(
+                "event missing status with no running status active"
+            ))?;
+            MidiMessage::msg_length(status)
+        }
+        0x80..=0xEF => {
+            raw = &raw[1..];
+            MidiMessage::msg_length(status)
+        }
+        0xF0 | 0xF7 | 0xFF => {
+            raw = &raw[1..];
+            if status == 0xFF {
+                raw = raw
+                    .get(1..)
+                    .ok_or(err_invalid!("failed to read meta message type"))?;
+            }
+            u28::read_varlen(&mut raw, strict)?.as_int() as usize
+        }
+        _ => {
+            return Err(err_invalid!(
+                "standard midi files cannot contain system events"
+            ))
+        }
+    };
+    Ok(((head.len() - raw.len()) as u64 + data_len as u64, status))
+}
@@ -0,0 +1,366 @@
+//! Recovery-mode parsing of damaged files.
+//!
+//! The regular parser gives up on a track at its first corrupt event, and silently skips invalid
+//! chunks and duplicate headers.
+//! [`Smf::parse_recover`](../struct.Smf.html#method.parse_recover) instead tries to resynchronize
+//! after corrupt events, and reports everything it had to discard as a list of
+//! [`Warning`](enum.Warning.html)s:
+//!
+//! ```rust
+//! use midly_usb::Smf;
+//!
+//! let (smf, warnings) = Smf::parse_recover(include_bytes!("../test-asset/PiDamaged.mid")).unwrap();
+//! for warning in &warnings {
+//!     println!("{}", warning);
+//! }
+//! ```
+//!
+//! This module is only available with the `alloc` feature enabled.
+#![cfg(feature = "alloc")]
+
+use crate::{
+    event::TrackEvent,
+    prelude::*,
+    riff,
+    smf::{Header, Smf, Track},
+};
+
+/// How many events must parse successfully after a candidate position for it to be considered a
+/// plausible resynchronization point.
+const RESYNC_LOOKAHEAD: usize = 3;
+
+/// Why a chunk was skipped.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum SkipReason {
+    /// The chunk type is not known to this parser.
+    Unknown,
+    /// A header chunk was found after the first one.
+    DuplicateHeader,
+}
+
+/// Something that had to be discarded while recovering a damaged file.
+///
+/// All offsets are absolute byte offsets from the start of the input, and all ranges are
+/// half-open.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Warning {
+    /// A whole chunk was skipped.
+    SkippedChunk {
+        /// The offset of the chunk header.
+        offset: usize,
+        /// The index of the chunk within the file.
+        chunk: usize,
+        /// The 4-byte chunk type.
+        id: [u8; 4],
+        /// Why the chunk was skipped.
+        reason: SkipReason,
+    },
+    /// A chunk declares a length past the end of the file, so the rest of the file was used as
+    /// its contents.
+    TruncatedChunk {
+        /// The offset of the chunk header.
+        offset: usize,
+        /// The index of the chunk within the file.
+        chunk: usize,
+        /// The length declared in the chunk header.
+        declared: u32,
+        /// The amount of bytes actually available.
+        available: usize,
+    },
+    /// There were bytes at the end of the file too short to make up a chunk.
+    TrailingBytes {
+        /// The offset of the first stray byte.
+        offset: usize,
+        /// The amount of stray bytes.
+        len: usize,
+    },
+    /// Some corrupt bytes were dropped from a track, and parsing resumed after them.
+    DroppedBytes {
+        /// The index of the track.
+        track: usize,
+        /// The index of the first event after the dropped bytes, in the recovered track.
+        event: usize,
+        /// The first dropped byte.
+        start: usize,
+        /// The byte after the last dropped byte.
+        end: usize,
+        /// The error that caused the bytes to be dropped.
+        ///
+        /// In release mode this is always a generic error message, since error chains are not
+        /// tracked.
+        error: ErrorKind,
+    },
+    /// A track could not be resynchronized after a corrupt event, so its remaining bytes were
+    /// dropped.
+    TruncatedTrack {
+        /// The index of the track.
+        track: usize,
+        /// The amount of events that were recovered from the track.
+        events: usize,
+        /// The first dropped byte.
+        start: usize,
+        /// The end of the track chunk.
+        end: usize,
+        /// The error that caused the track to be truncated.
+        error: ErrorKind,
+    },
+    /// The header declares a different amount of tracks than the amount found.
+    TrackCountMismatch {
+        /// The amount of tracks declared in the header.
+        declared: u16,
+        /// The amount of tracks found.
+        found: usize,
+    },
+}
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::SkippedChunk {
+                offset,
+                chunk,
+                id,
+                reason,
+            } => {
+                let why = match reason {
+                    SkipReason::Unknown => "unknown",
+                    SkipReason::DuplicateHeader => "duplicate header",
+                };
+                write!(
+                    f,
+                    "skipped {} chunk {} ({:?}) at byte {}",
+                    why,
+                    chunk,
+                    String::from_utf8_lossy(&id),
+                    offset
+                )
+            }
+            Warning::TruncatedChunk {
+                offset,
+                chunk,
+                declared,
+                available,
+            } => write!(
+                f,
+                "chunk {} at byte {} declares {} bytes, but only {} are available",
+                chunk, offset, declared, available
+            ),
+            Warning::TrailingBytes { offset, len } => {
+                write!(f, "dropped {} stray bytes at byte {}", len, offset)
+            }
+            Warning::DroppedBytes {
+                track,
+                event,
+                start,
+                end,
+                error,
+            } => write!(
+                f,
+                "track {}: dropped bytes {}..{} before event {} ({})",
+                track, start, end, event, error
+            ),
+            Warning::TruncatedTrack {
+                track,
+                events,
+                start,
+                end,
+                error,
+            } => write!(
+                f,
+                "track {}: dropped bytes {}..{} after {} events ({})",
+                track, start, end, events, error
+            ),
+            Warning::TrackCountMismatch { declared, found } => write!(
+                f,
+                "header declares {} tracks, but {} were found",
+                declared, found
+            ),
+        }
+    }
+}
+
+impl<'a> Smf<'a> {
+    /// Parse a possibly damaged file, recovering as much as possible.
+    ///
+    /// Unlike [`parse`](#method.parse), which stops reading a track at its first corrupt event,
+    /// this method skips corrupt bytes until it finds a position from which several events can be
+    /// parsed again.
+    /// Every skipped chunk, dropped byte range and truncated track is reported in the returned
+    /// list of warnings.
+    ///
+    /// Only files without a valid header are rejected.
+    /// This method is always lenient, regardless of the `strict` feature.
+    pub fn parse_recover(raw: &'a [u8]) -> Result<(Smf<'a>, Vec<Warning>)> {
+        let data = match raw.get(..4) {
+            Some(b"RIFF") => riff::unwrap(raw).map_err(|err| err.locate(0, None, None))?,
+            Some(b"MThd") => raw,
+            _ => bail!(Error::from(err_invalid!("not a midi file")).locate(0, None, None)),
+        };
+        let mut rec = Recovery {
+            base: raw.as_ptr() as usize,
+            warnings: Vec::new(),
+        };
+        let mut header = None;
+        let mut tracks = Vec::new();
+        let mut rest = data;
+        let mut chunk = 0;
+        while !rest.is_empty() {
+            let offset = rec.offset(rest);
+            if rest.len() < 8 {
+                rec.warnings.push(Warning::TrailingBytes {
+                    offset,
+                    len: rest.len(),
+                });
+                break;
+            }
+            let mut id = [0; 4];
+            id.copy_from_slice(&rest[..4]);
+            let len = u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]);
+            rest = &rest[8..];
+            let body = match rest.split_checked(len as usize) {
+                Some(body) => body,
+                None => {
+                    rec.warnings.push(Warning::TruncatedChunk {
+                        offset,
+                        chunk,
+                        declared: len,
+                        available: rest.len(),
+                    });
+                    let (body, end) = rest.split_at(rest.len());
+                    rest = end;
+                    body
+                }
+            };
+            match &id {
+                b"MThd" if header.is_none() => {
+                    header = Some(
+                        Header::read(body)
+                            .context(err_invalid!("invalid midi header"))
+                            .map_err(|err| err.locate(offset, Some(chunk), None))?,
+                    );
+                }
+                b"MThd" => rec.warnings.push(Warning::SkippedChunk {
+                    offset,
+                    chunk,
+                    id,
+                    reason: SkipReason::DuplicateHeader,
+                }),
+                b"MTrk" if header.is_some() => {
+                    let track = rec.track(tracks.len(), body);
+                    tracks.push(track);
+                }
+                b"MTrk" => bail!(
+                    Error::from(err_invalid!("expected header, found track")).locate(
+                        offset,
+                        Some(chunk),
+                        None
+                    )
+                ),
+                _ => rec.warnings.push(Warning::SkippedChunk {
+                    offset,
+                    chunk,
+                    id,
+                    reason: SkipReason::Unknown,
+                }),
+            }
+            chunk += 1;
+        }
+        let (header, declared) = header.ok_or_else(|| {
+            Error::from(err_invalid!("no midi header chunk")).locate(rec.offset(data), None, None)
+        })?;
+        if declared as usize != tracks.len() {
+            rec.warnings.push(Warning::TrackCountMismatch {
+                declared,
+                found: tracks.len(),
+            });
+        }
+        Ok((Smf { header, tracks }, rec.warnings))
+    }
+}
+
+struct Recovery {
+    /// Address of the start of the input, used to compute offsets out of subslices.
+    base: usize,
+    warnings: Vec<Warning>,
+}
+impl Recovery {
+    #[inline]
+    fn offset(&self, at: &[u8]) -> usize {
+        at.as_ptr() as usize - self.base
+    }
+
+    fn track<'a>(&mut self, track: usize, raw: &'a [u8]) -> Track<'a> {
+        let mut events = Vec::new();
+        let mut rest = raw;
+        let mut running_status = None;
+        while !rest.is_empty() {
+            let start = rest;
+            let err = match TrackEvent::read(&mut rest, &mut running_status, false) {
+                Ok(ev) => {
+                    events.push(ev);
+                    continue;
+                }
+                Err(err) => root_cause(&err),
+            };
+            match resync(&start[1..]) {
+                Some(skip) => {
+                    let (dropped, resumed) = start.split_at(1 + skip);
+                    self.warnings.push(Warning::DroppedBytes {
+                        track,
+                        event: events.len(),
+                        start: self.offset(dropped),
+                        end: self.offset(resumed),
+                        error: err,
+                    });
+                    rest = resumed;
+                }
+                None => {
+                    self.warnings.push(Warning::TruncatedTrack {
+                        track,
+                        events: events.len(),
+                        start: self.offset(start),
+                        end: self.offset(start) + start.len(),
+                        error: err,
+                    });
+                    break;
+                }
+            }
+        }
+        events
+    }
+}
+
+/// Find the first position in `raw` that looks like the start of an event, if any.
+///
+/// A position is considered plausible if it holds a single-byte delta followed by an explicit
+/// status byte, and the events from there on (up to `RESYNC_LOOKAHEAD` events, or the end of the
+/// track) all parse successfully.
+/// Multi-byte deltas are not considered, since corrupt bytes with their top bit set would
+/// otherwise be taken as huge deltas.
+fn resync(raw: &[u8]) -> Option<usize> {
+    (0..raw.len()).find(|&i| {
+        let mut probe = &raw[i..];
+        match probe {
+            [delta, status, ..] if *delta < 0x80 && *status >= 0x80 => {}
+            _ => return false,
+        }
+        let mut running_status = None;
+        for _ in 0..RESYNC_LOOKAHEAD {
+            if probe.is_empty() {
+                break;
+            }
+            if TrackEvent::read(&mut probe, &mut running_status, false).is_err() {
+                return false;
+            }
+        }
+        true
+    })
+}
+
+/// Get the most specific error kind available.
+fn root_cause(err: &Error) -> ErrorKind {
+    let mut err = err;
+    while let Some(src) = err.source() {
+        err = src;
+    }
+    err.kind()
+}
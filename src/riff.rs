@@ -4,7 +4,7 @@
 
 use crate::prelude::*;
 
-struct ChunkIter<'a>(&'a [u8]);
+pub(crate) struct ChunkIter<'a>(pub(crate) &'a [u8]);
 impl<'a> Iterator for ChunkIter<'a> {
     type Item = ([u8; 4], &'a [u8]);
     fn next(&mut self) -> Option<([u8; 4], &'a [u8])> {
@@ -0,0 +1,318 @@
+//! Full support for RMID files, keeping the RIFF metadata around the MIDI data.
+
+#![cfg(feature = "alloc")]
+
+use crate::{
+    prelude::*,
+    riff::ChunkIter,
+    smf::{ParseOptions, Smf},
+};
+
+/// A raw RIFF chunk, identified by its four-byte id.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct RiffChunk<'a> {
+    /// The four-character code identifying the chunk.
+    pub id: [u8; 4],
+    /// The contents of the chunk, without padding.
+    pub data: &'a [u8],
+}
+
+/// The kinds of chunks in an RMID file, used to remember their order.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+enum Slot {
+    Data,
+    Info,
+    SoundBank,
+    /// The next chunk in `Rmid::extra`.
+    Extra,
+}
+
+/// A sound bank embedded in an RMID file, to be used when playing back the MIDI data.
+///
+/// The sound bank is kept as a raw slice containing the contents of the embedded `RIFF` chunk,
+/// starting with its form type (`DLS ` or `sfbk`).
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum SoundBank<'a> {
+    /// A Downloadable Sounds bank.
+    Dls(&'a [u8]),
+    /// A SoundFont 2 bank.
+    Sf2(&'a [u8]),
+}
+impl<'a> SoundBank<'a> {
+    /// The raw contents of the sound bank, starting with its form type.
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        match *self {
+            SoundBank::Dls(data) | SoundBank::Sf2(data) => data,
+        }
+    }
+}
+
+/// Represents a `.rmi` RMID file: a Standard Midi File wrapped in a RIFF container, along with
+/// metadata and an optional sound bank.
+///
+/// Plain [`Smf::parse`](struct.Smf.html#method.parse) can read RMID files too, but it discards
+/// everything except for the MIDI data.
+/// This type keeps all chunks around, so that files can be modified and written back out without
+/// losing information.
+///
+/// When writing, parsed files keep their original chunk order.
+/// Chunks that were not in the parsed file, or all chunks of a new file, are laid out in the
+/// following order: the `data` chunk, any extra chunks, the `LIST/INFO` metadata chunk and the
+/// sound bank.
+///
+/// This type is only available with the `alloc` feature enabled.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Rmid<'a> {
+    /// The MIDI data stored in the `data` chunk.
+    pub smf: Smf<'a>,
+    /// The entries of the `LIST/INFO` chunk, such as `INAM` (name), `IART` (artist), `ICOP`
+    /// (copyright), `ICMT` (comments) or `ISFT` (software).
+    ///
+    /// The data of each entry excludes a single NUL terminator.
+    pub info: Vec<RiffChunk<'a>>,
+    /// An embedded DLS or SF2 sound bank, if any.
+    pub sound_bank: Option<SoundBank<'a>>,
+    /// Any other chunks within the RMID file, such as `DISP` chunks.
+    pub extra: Vec<RiffChunk<'a>>,
+    /// The chunk order of the parsed file, or empty if it matches the default order.
+    layout: Vec<Slot>,
+}
+impl<'a> Rmid<'a> {
+    /// Wrap the given MIDI data in an RMID file without any metadata.
+    #[inline]
+    pub fn new(smf: Smf<'a>) -> Rmid<'a> {
+        Rmid {
+            smf,
+            info: vec![],
+            sound_bank: None,
+            extra: vec![],
+            layout: vec![],
+        }
+    }
+
+    /// Parse an RMID file, using the default parsing options for the MIDI data.
+    #[inline]
+    pub fn parse(raw: &[u8]) -> Result<Rmid<'_>> {
+        Rmid::parse_with(raw, ParseOptions::default())
+    }
+
+    /// Parse an RMID file, using the given parsing options for the MIDI data.
+    pub fn parse_with(raw: &[u8], options: ParseOptions) -> Result<Rmid<'_>> {
+        let (id, mut riff) = ChunkIter(raw)
+            .next()
+            .ok_or(err_invalid!("no main riff chunk"))?;
+        if &id != b"RIFF" {
+            bail!(err_invalid!("invalid main riff chunk"));
+        }
+        let formtype = riff
+            .split_checked(4)
+            .ok_or(err_invalid!("failed to read riff formtype"))?;
+        if formtype != b"RMID" {
+            bail!(err_invalid!("not an rmid riff file"));
+        }
+        let mut smf = None;
+        let mut info = Vec::new();
+        let mut sound_bank = None;
+        let mut extra = Vec::new();
+        let mut layout = Vec::new();
+        for (id, data) in ChunkIter(riff) {
+            let slot = match (&id, data.get(..4)) {
+                (b"data", _) if smf.is_none() => {
+                    smf = Some(
+                        Smf::parse_with(data, options)
+                            .context(err_invalid!("invalid midi data in rmid file"))?,
+                    );
+                    Slot::Data
+                }
+                (b"LIST", Some(b"INFO")) if !layout.contains(&Slot::Info) => {
+                    for (id, mut data) in ChunkIter(&data[4..]) {
+                        if let [rest @ .., 0] = data {
+                            data = rest;
+                        }
+                        info.push(RiffChunk { id, data });
+                    }
+                    Slot::Info
+                }
+                (b"RIFF", Some(b"DLS ")) if sound_bank.is_none() => {
+                    sound_bank = Some(SoundBank::Dls(data));
+                    Slot::SoundBank
+                }
+                (b"RIFF", Some(b"sfbk")) if sound_bank.is_none() => {
+                    sound_bank = Some(SoundBank::Sf2(data));
+                    Slot::SoundBank
+                }
+                _ => {
+                    extra.push(RiffChunk { id, data });
+                    Slot::Extra
+                }
+            };
+            layout.push(slot);
+        }
+        let mut rmid = Rmid {
+            smf: smf.ok_or(err_invalid!("no rmid data chunk"))?,
+            info,
+            sound_bank,
+            extra,
+            layout,
+        };
+        //Only remember unusual layouts, so that files compare equal to the ones they are read from
+        if rmid.resolve_layout(&[]) == rmid.layout {
+            rmid.layout.clear();
+        }
+        Ok(rmid)
+    }
+
+    /// Complete a chunk order with the chunks it is missing, in their default order, and drop the
+    /// chunks that are no longer there.
+    fn resolve_layout(&self, layout: &[Slot]) -> Vec<Slot> {
+        let mut resolved = Vec::with_capacity(self.extra.len() + 3);
+        let mut extras = 0;
+        for &slot in layout {
+            match slot {
+                Slot::Info if self.info.is_empty() => {}
+                Slot::SoundBank if self.sound_bank.is_none() => {}
+                Slot::Extra if extras == self.extra.len() => {}
+                Slot::Extra => {
+                    extras += 1;
+                    resolved.push(slot);
+                }
+                _ => resolved.push(slot),
+            }
+        }
+        if !resolved.contains(&Slot::Data) {
+            resolved.push(Slot::Data);
+        }
+        resolved.extend((extras..self.extra.len()).map(|_| Slot::Extra));
+        if !self.info.is_empty() && !resolved.contains(&Slot::Info) {
+            resolved.push(Slot::Info);
+        }
+        if self.sound_bank.is_some() && !resolved.contains(&Slot::SoundBank) {
+            resolved.push(Slot::SoundBank);
+        }
+        resolved
+    }
+
+    /// Get the value of a `LIST/INFO` entry, such as `b"INAM"`, without its NUL terminator.
+    pub fn info(&self, id: [u8; 4]) -> Option<&'a [u8]> {
+        self.info
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.data)
+    }
+
+    /// Set the value of a `LIST/INFO` entry, such as `b"INAM"`, replacing any previous value.
+    ///
+    /// The value should not include a NUL terminator, it is added automatically when writing.
+    pub fn set_info(&mut self, id: [u8; 4], value: &'a [u8]) {
+        match self.info.iter_mut().find(|entry| entry.id == id) {
+            Some(entry) => entry.data = value,
+            None => self.info.push(RiffChunk { id, data: value }),
+        }
+    }
+
+    /// Encodes and writes the file to the given generic writer.
+    ///
+    /// The MIDI data is encoded in memory first, in order to know the size of the RIFF chunks.
+    pub fn write<W: Write>(&self, out: &mut W) -> WriteResult<W> {
+        let mut data = Vec::new();
+        self.smf.write(&mut data).map_err(W::invalid_input)?;
+        let info_len = self
+            .info
+            .iter()
+            .map(|entry| padded_len(entry.data.len() + 1))
+            .sum::<usize>();
+        let mut riff_len = 4 + padded_len(data.len());
+        if !self.info.is_empty() {
+            riff_len += padded_len(4 + info_len);
+        }
+        if let Some(bank) = self.sound_bank {
+            riff_len += padded_len(bank.data().len());
+        }
+        riff_len += self
+            .extra
+            .iter()
+            .map(|chunk| padded_len(chunk.data.len()))
+            .sum::<usize>();
+
+        write_chunk_head(b"RIFF", riff_len, out)?;
+        out.write(b"RMID")?;
+        let mut extra = self.extra.iter();
+        for slot in self.resolve_layout(&self.layout) {
+            match slot {
+                Slot::Data => write_chunk(b"data", &[&data], out)?,
+                Slot::Info => {
+                    write_chunk_head(b"LIST", 4 + info_len, out)?;
+                    out.write(b"INFO")?;
+                    for entry in self.info.iter() {
+                        write_chunk(&entry.id, &[entry.data, &[0]], out)?;
+                    }
+                }
+                Slot::SoundBank => {
+                    if let Some(bank) = self.sound_bank {
+                        write_chunk(b"RIFF", &[bank.data()], out)?;
+                    }
+                }
+                Slot::Extra => {
+                    if let Some(chunk) = extra.next() {
+                        write_chunk(&chunk.id, &[chunk.data], out)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes and writes the file to the given `std::io::Write` writer.
+    ///
+    /// This function is only available with the `std` feature enabled.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn write_std<W: io::Write>(&self, out: W) -> io::Result<()> {
+        self.write(&mut IoWrap(out))
+    }
+
+    /// Encodes and writes the file to the given path.
+    ///
+    /// This function is only available with the `std` feature enabled.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fn save_impl(rmid: &Rmid, path: &Path) -> io::Result<()> {
+            rmid.write(&mut IoWrap(File::create(path)?))
+        }
+        save_impl(self, path.as_ref())
+    }
+}
+impl<'a> From<Smf<'a>> for Rmid<'a> {
+    #[inline]
+    fn from(smf: Smf<'a>) -> Rmid<'a> {
+        Rmid::new(smf)
+    }
+}
+
+/// The length of a chunk including its header and padding.
+#[inline]
+fn padded_len(len: usize) -> usize {
+    8 + len + len % 2
+}
+
+fn write_chunk_head<W: Write>(id: &[u8; 4], len: usize, out: &mut W) -> WriteResult<W> {
+    let len = u32::try_from(len).map_err(|_| W::invalid_input("riff chunk too large"))?;
+    out.write(id)?;
+    out.write(&len.to_le_bytes())?;
+    Ok(())
+}
+
+/// Write a chunk made up of the concatenation of the given parts, padding it to an even length.
+fn write_chunk<W: Write>(id: &[u8; 4], parts: &[&[u8]], out: &mut W) -> WriteResult<W> {
+    let len = parts.iter().map(|part| part.len()).sum::<usize>();
+    write_chunk_head(id, len, out)?;
+    for part in parts {
+        out.write(part)?;
+    }
+    if len % 2 == 1 {
+        out.write(&[0])?;
+    }
+    Ok(())
+}
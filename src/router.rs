@@ -0,0 +1,384 @@
+//! Routing MIDI between USB cables and DIN ports, as done by MIDI interfaces.
+//!
+//! The [`Router`](struct.Router.html) receives 4-byte USB-MIDI packets and events parsed from DIN
+//! streams, looks up every [`Route`](struct.Route.html) leaving the source port, filters the
+//! message by channel and kind, and emits it to each destination in the appropriate encoding:
+//!
+//! - USB outputs receive 4-byte USB-MIDI packets, with System Exclusive messages split into as many
+//!   packets as required.
+//! - DIN outputs receive raw bytes, using running status whenever possible.
+//!
+//! The router does not allocate and works in `no_std` environments.
+//!
+//! ```rust
+//! use midly_usb::{num::u4, router::{MessageFilter, MessageKind, Output, Port, Route, Router}};
+//!
+//! let mut router = Router::<8, 2>::new();
+//! //Forward everything from USB cable 0 to DIN port 0, except for active sensing
+//! router.add_route(
+//!     Route::new(Port::Usb(u4::new(0)), Port::Din(0))
+//!         .filter(MessageFilter::ALL.deny(MessageKind::ActiveSensing)),
+//! ).unwrap();
+//! //DIN messages may be emitted in several chunks
+//! let mut din = Vec::new();
+//! router.feed_usb([0x09, 0x90, 60, 100], |out| {
+//!     if let Output::Din { port: 0, bytes } = out {
+//!         din.extend_from_slice(bytes);
+//!     }
+//! });
+//! assert_eq!(din, [0x90, 60, 100]);
+//! ```
+
+use crate::{
+    live::{LiveEvent, SystemCommon, SystemRealtime},
+    packet::{packetize, CIN},
+    prelude::*,
+    stream::{Buffer, MidiStream},
+};
+
+/// A MIDI port, either a virtual cable on the USB side or a physical DIN port.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Port {
+    /// A USB-MIDI virtual cable, identified by its cable number.
+    Usb(u4),
+    /// A DIN port (usually a UART), identified by its index.
+    Din(u8),
+}
+
+/// The broad categories of MIDI messages, used to filter routes.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum MessageKind {
+    /// Channel messages, such as notes, controllers or program changes.
+    Channel,
+    /// System Exclusive messages.
+    SysEx,
+    /// System Common messages other than System Exclusive, such as song position or MTC quarter
+    /// frames.
+    Common,
+    /// Clock and transport messages: timing clock, start, continue and stop.
+    Clock,
+    /// Active sensing messages.
+    ActiveSensing,
+    /// System reset messages.
+    Reset,
+    /// Undefined System Realtime messages.
+    OtherRealtime,
+}
+impl MessageKind {
+    /// Classify the given event.
+    pub fn of(event: &LiveEvent) -> MessageKind {
+        match event {
+            LiveEvent::Midi { .. } => MessageKind::Channel,
+            LiveEvent::Common(SystemCommon::SysEx(_)) => MessageKind::SysEx,
+            LiveEvent::Common(_) => MessageKind::Common,
+            LiveEvent::Realtime(realtime) => match realtime {
+                SystemRealtime::TimingClock
+                | SystemRealtime::Start
+                | SystemRealtime::Continue
+                | SystemRealtime::Stop => MessageKind::Clock,
+                SystemRealtime::ActiveSensing => MessageKind::ActiveSensing,
+                SystemRealtime::Reset => MessageKind::Reset,
+                SystemRealtime::Undefined(_) => MessageKind::OtherRealtime,
+            },
+        }
+    }
+
+    #[inline]
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// A set of message kinds allowed through a route.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct MessageFilter(u8);
+impl MessageFilter {
+    /// Allow all messages through.
+    pub const ALL: MessageFilter = MessageFilter(0x7F);
+    /// Block all messages.
+    pub const NONE: MessageFilter = MessageFilter(0);
+
+    /// Also allow the given kind of messages through.
+    #[inline]
+    pub const fn allow(self, kind: MessageKind) -> MessageFilter {
+        MessageFilter(self.0 | 1 << kind as u8)
+    }
+
+    /// Block the given kind of messages.
+    #[inline]
+    pub const fn deny(self, kind: MessageKind) -> MessageFilter {
+        MessageFilter(self.0 & !(1 << kind as u8))
+    }
+
+    /// Whether the given kind of messages is allowed through.
+    #[inline]
+    pub fn allows(self, kind: MessageKind) -> bool {
+        self.0 & kind.bit() != 0
+    }
+}
+impl Default for MessageFilter {
+    #[inline]
+    fn default() -> MessageFilter {
+        MessageFilter::ALL
+    }
+}
+
+/// A connection from one port to another, along with the filters that messages must pass.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Route {
+    /// The port that messages come from.
+    pub from: Port,
+    /// The port that messages are sent to.
+    pub to: Port,
+    /// A bitmask of the channels allowed through, where bit `n` corresponds to channel `n`.
+    ///
+    /// Only applies to channel messages.
+    pub channels: u16,
+    /// The kinds of messages allowed through.
+    pub filter: MessageFilter,
+}
+impl Route {
+    /// Create a route that lets all messages through.
+    #[inline]
+    pub fn new(from: Port, to: Port) -> Route {
+        Route {
+            from,
+            to,
+            channels: 0xFFFF,
+            filter: MessageFilter::ALL,
+        }
+    }
+
+    /// Only let channel messages in the given channel bitmask through.
+    #[inline]
+    pub fn channels(mut self, channels: u16) -> Route {
+        self.channels = channels;
+        self
+    }
+
+    /// Only let the given kinds of messages through.
+    #[inline]
+    pub fn filter(mut self, filter: MessageFilter) -> Route {
+        self.filter = filter;
+        self
+    }
+
+    /// Whether the given event passes the filters of this route.
+    pub fn allows(&self, event: &LiveEvent) -> bool {
+        match event {
+            LiveEvent::Midi { channel, .. } => {
+                self.filter.allows(MessageKind::Channel)
+                    && self.channels & (1 << channel.as_int()) != 0
+            }
+            event => self.filter.allows(MessageKind::of(event)),
+        }
+    }
+}
+
+/// Data emitted by the router for one of its outputs.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Output<'a> {
+    /// A USB-MIDI event packet, whose header holds the destination cable number.
+    Usb([u8; 4]),
+    /// Raw MIDI bytes to be sent out of the given DIN port.
+    ///
+    /// A single message may be emitted in several chunks.
+    Din {
+        /// The index of the destination DIN port.
+        port: u8,
+        /// The bytes to send.
+        bytes: &'a [u8],
+    },
+}
+
+/// Routes MIDI messages between USB cables and DIN ports.
+///
+/// Holds up to `R` routes, and tracks running status for up to `D` DIN outputs (messages for DIN
+/// ports beyond that are still emitted, but always with a status byte).
+///
+/// See the [module-level documentation](index.html) for more details.
+#[derive(Clone, Debug)]
+pub struct Router<const R: usize = 16, const D: usize = 4> {
+    routes: [Option<Route>; R],
+    din_status: [Option<u8>; D],
+    running_status: bool,
+}
+impl<const R: usize, const D: usize> Router<R, D> {
+    /// Create a router without any routes.
+    pub fn new() -> Router<R, D> {
+        Router {
+            routes: [None; R],
+            din_status: [None; D],
+            running_status: true,
+        }
+    }
+
+    /// Add a route, failing if there are already `R` routes.
+    ///
+    /// Duplicate routes are allowed, and cause messages to be emitted once per route.
+    pub fn add_route(&mut self, route: Route) -> StdResult<(), Route> {
+        match self.routes.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(route);
+                Ok(())
+            }
+            None => Err(route),
+        }
+    }
+
+    /// Remove all routes connecting the given ports.
+    pub fn remove_routes(&mut self, from: Port, to: Port) {
+        for slot in self.routes.iter_mut() {
+            if matches!(slot, Some(route) if route.from == from && route.to == to) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Remove all routes.
+    pub fn clear_routes(&mut self) {
+        self.routes = [None; R];
+    }
+
+    /// Iterate over all routes.
+    pub fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter().flatten()
+    }
+
+    /// Enable or disable running status on DIN outputs.
+    #[inline]
+    pub fn set_running_status(&mut self, enabled: bool) {
+        self.running_status = enabled;
+        self.refresh_running_status();
+    }
+
+    /// Make the next channel message sent to each DIN output include its status byte.
+    #[inline]
+    pub fn refresh_running_status(&mut self) {
+        self.din_status = [None; D];
+    }
+
+    /// Route a single USB-MIDI event packet received from the host.
+    ///
+    /// System Exclusive packets are forwarded as they arrive, without waiting for the whole
+    /// message.
+    /// Any other non-realtime message routed to the same DIN port before the System Exclusive
+    /// message ends will interrupt it.
+    /// Packets with reserved or invalid contents are dropped.
+    pub fn feed_usb(&mut self, packet: [u8; 4], mut emit: impl FnMut(Output)) {
+        let cable = u4::new(packet[0] >> 4);
+        let from = Port::Usb(cable);
+        let len = match CIN::from(u4::new(packet[0])) {
+            CIN::MiscFunction | CIN::CableEvent => return,
+            CIN::SysExStartOrContinue => 3,
+            CIN::SingleByteSysComOrSysExEnd if packet[1] == 0xF7 => 1,
+            CIN::TwoByteSysExEnd => 2,
+            CIN::ThreeByteSysExEnd => 3,
+            cin => {
+                //A complete message in a single packet
+                let len = crate::packet::UsbMidiPacket::packet_length(cin);
+                if let Ok(event) = LiveEvent::parse(&packet[1..1 + len]) {
+                    self.route(from, &event, emit);
+                }
+                return;
+            }
+        };
+        //A System Exclusive fragment
+        let fragment = &packet[1..1 + len];
+        for route in self.routes.into_iter().flatten() {
+            if route.from != from || !route.filter.allows(MessageKind::SysEx) {
+                continue;
+            }
+            match route.to {
+                Port::Usb(to) => emit(Output::Usb([
+                    to.as_int() << 4 | (packet[0] & 0x0F),
+                    packet[1],
+                    packet[2],
+                    packet[3],
+                ])),
+                Port::Din(port) => {
+                    self.clear_din_status(port);
+                    emit(Output::Din {
+                        port,
+                        bytes: fragment,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Feed raw bytes received from a DIN port through the given stream, routing every complete
+    /// message.
+    ///
+    /// Each DIN port should have its own stream.
+    pub fn feed_din<B: Buffer>(
+        &mut self,
+        port: u8,
+        stream: &mut MidiStream<B>,
+        bytes: &[u8],
+        mut emit: impl FnMut(Output),
+    ) {
+        stream.feed(bytes, |event| {
+            self.route(Port::Din(port), &event, &mut emit)
+        });
+    }
+
+    /// Route a single event coming from the given port.
+    pub fn route(&mut self, from: Port, event: &LiveEvent, mut emit: impl FnMut(Output)) {
+        for route in self.routes.into_iter().flatten() {
+            if route.from != from || !route.allows(event) {
+                continue;
+            }
+            match route.to {
+                Port::Usb(cable) => {
+                    packetize(cable, event).for_each(|packet| emit(Output::Usb(packet)))
+                }
+                Port::Din(port) => self.write_din(port, event, &mut emit),
+            }
+        }
+    }
+
+    fn clear_din_status(&mut self, port: u8) {
+        if let Some(status) = self.din_status.get_mut(port as usize) {
+            *status = None;
+        }
+    }
+
+    fn write_din(&mut self, port: u8, event: &LiveEvent, emit: &mut impl FnMut(Output)) {
+        let mut no_status = None;
+        let status = match self.din_status.get_mut(port as usize) {
+            Some(status) if self.running_status => status,
+            _ => &mut no_status,
+        };
+        let mut out = DinWriter { port, emit };
+        let _ = event.write_with_running_status(status, &mut out);
+    }
+}
+impl<const R: usize, const D: usize> Default for Router<R, D> {
+    #[inline]
+    fn default() -> Router<R, D> {
+        Router::new()
+    }
+}
+
+/// Emits written bytes as DIN output.
+struct DinWriter<'a, F> {
+    port: u8,
+    emit: &'a mut F,
+}
+impl<'a, F: FnMut(Output)> Write for DinWriter<'a, F> {
+    type Error = ();
+    type Seekable = crate::io::NotSeekable<Self>;
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) -> StdResult<(), ()> {
+        (self.emit)(Output::Din {
+            port: self.port,
+            bytes,
+        });
+        Ok(())
+    }
+
+    #[inline]
+    fn invalid_input(_msg: &'static str) {}
+}
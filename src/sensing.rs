@@ -0,0 +1,189 @@
+//! Supervising live MIDI connections through Active Sensing.
+//!
+//! Senders that support Active Sensing transmit an Active Sensing message whenever they have been
+//! idle for about 300 ms, so that receivers can tell a silent connection from a broken one.
+//! Once a receiver has seen an Active Sensing message, it expects the connection to never be idle
+//! for longer than that, and turns off any sounding notes when it is.
+//!
+//! The [`Supervisor`](struct.Supervisor.html) implements both sides of the protocol for a fixed
+//! amount of inputs and outputs.
+//! It does not measure time by itself: it is fed the elapsed time ticks, usually milliseconds, and
+//! reports what should be done through [`Action`](enum.Action.html)s:
+//!
+//! ```rust
+//! use midly_usb::{live::{LiveEvent, SystemRealtime}, sensing::{Action, Supervisor}};
+//!
+//! let mut supervisor = Supervisor::<1, 1>::new();
+//! supervisor.received(0, &LiveEvent::Realtime(SystemRealtime::ActiveSensing));
+//! supervisor.tick(500, |action| match action {
+//!     Action::SendActiveSensing { output } => println!("ping output {}", output),
+//!     Action::ConnectionLost { input } => println!("input {} is gone", input),
+//!     Action::Silence { input, event } => println!("silence input {}: {:?}", input, event),
+//! });
+//! ```
+//!
+//! The supervisor does not allocate and works in `no_std` environments.
+
+use crate::{
+    event::MidiMessage,
+    live::{LiveEvent, SystemRealtime},
+    prelude::*,
+};
+
+/// The default interval between Active Sensing messages on idle outputs, in milliseconds.
+///
+/// Slightly below 300 ms, so that receivers never time out because of transmission delays.
+pub const SEND_INTERVAL: u32 = 270;
+/// The default time after which an input that sent Active Sensing is considered lost, in
+/// milliseconds.
+///
+/// Slightly above 300 ms, to tolerate senders that are somewhat late.
+pub const TIMEOUT: u32 = 330;
+
+//Controller numbers of the channel mode messages used to silence a channel
+const RESET_ALL_CONTROLLERS: u8 = 121;
+const ALL_NOTES_OFF: u8 = 123;
+
+/// Something that the owner of a [`Supervisor`](struct.Supervisor.html) should do.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Action {
+    /// Transmit an Active Sensing message on the given output, which has been idle for too long.
+    SendActiveSensing {
+        /// The index of the idle output.
+        output: usize,
+    },
+    /// The given input stopped sending Active Sensing, and is no longer supervised until it sends
+    /// Active Sensing again.
+    ConnectionLost {
+        /// The index of the lost input.
+        input: usize,
+    },
+    /// An event to send wherever the messages of a lost input went, to silence stuck notes.
+    ///
+    /// These actions directly follow the `ConnectionLost` action of their input.
+    Silence {
+        /// The index of the lost input.
+        input: usize,
+        /// An *All Notes Off* or *Reset All Controllers* message, for each channel that the
+        /// input used.
+        event: LiveEvent<'static>,
+    },
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Input {
+    /// Whether an Active Sensing message was received since the connection was last lost.
+    supervised: bool,
+    /// The ticks elapsed since the last message.
+    silence: u32,
+    /// A bitmask of the channels that received channel messages.
+    channels: u16,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Output {
+    /// The ticks elapsed since the last message.
+    idle: u32,
+}
+
+/// A supervisor for `I` inputs and `O` outputs, identified by their indices.
+///
+/// # Panics
+///
+/// Every method that takes an input or output index panics if the index is out of range.
+#[derive(Clone, Debug)]
+pub struct Supervisor<const I: usize = 1, const O: usize = 1> {
+    inputs: [Input; I],
+    outputs: [Output; O],
+    send_interval: u32,
+    timeout: u32,
+}
+impl<const I: usize, const O: usize> Supervisor<I, O> {
+    /// Create a supervisor that counts time in milliseconds, with the default
+    /// [`SEND_INTERVAL`](constant.SEND_INTERVAL.html) and [`TIMEOUT`](constant.TIMEOUT.html).
+    #[inline]
+    pub fn new() -> Supervisor<I, O> {
+        Supervisor::with_timing(SEND_INTERVAL, TIMEOUT)
+    }
+
+    /// Create a supervisor with the given send interval and timeout, expressed in the same ticks
+    /// as the ones given to [`tick`](#method.tick).
+    #[inline]
+    pub fn with_timing(send_interval: u32, timeout: u32) -> Supervisor<I, O> {
+        Supervisor {
+            inputs: [Input::default(); I],
+            outputs: [Output::default(); O],
+            send_interval,
+            timeout,
+        }
+    }
+
+    /// Notify the supervisor that an event arrived on the given input.
+    ///
+    /// Supervision of the input starts with its first Active Sensing message.
+    pub fn received(&mut self, input: usize, event: &LiveEvent) {
+        let input = &mut self.inputs[input];
+        input.silence = 0;
+        match event {
+            LiveEvent::Realtime(SystemRealtime::ActiveSensing) => input.supervised = true,
+            LiveEvent::Midi { channel, .. } => input.channels |= 1 << channel.as_int(),
+            _ => {}
+        }
+    }
+
+    /// Notify the supervisor that some message was sent on the given output, which postpones its
+    /// next Active Sensing message.
+    #[inline]
+    pub fn sent(&mut self, output: usize) {
+        self.outputs[output].idle = 0;
+    }
+
+    /// Whether the given input is currently supervised, that is, it sent Active Sensing and did not
+    /// time out since.
+    #[inline]
+    pub fn is_supervised(&self, input: usize) -> bool {
+        self.inputs[input].supervised
+    }
+
+    /// Advance time by the given amount of ticks, calling `handle` with every resulting action.
+    ///
+    /// Ticks may be fed in steps of any size, but actions are only ever emitted from this
+    /// method, so the step size determines the timing accuracy.
+    pub fn tick(&mut self, elapsed: u32, mut handle: impl FnMut(Action)) {
+        for (idx, output) in self.outputs.iter_mut().enumerate() {
+            output.idle = output.idle.saturating_add(elapsed);
+            if output.idle >= self.send_interval {
+                output.idle = 0;
+                handle(Action::SendActiveSensing { output: idx });
+            }
+        }
+        for (idx, input) in self.inputs.iter_mut().enumerate() {
+            input.silence = input.silence.saturating_add(elapsed);
+            if input.supervised && input.silence > self.timeout {
+                input.supervised = false;
+                handle(Action::ConnectionLost { input: idx });
+                for channel in (0..16).filter(|ch| input.channels & 1 << ch != 0) {
+                    for &controller in &[ALL_NOTES_OFF, RESET_ALL_CONTROLLERS] {
+                        handle(Action::Silence {
+                            input: idx,
+                            event: LiveEvent::Midi {
+                                channel: u4::new(channel),
+                                message: MidiMessage::Controller {
+                                    controller: u7::new(controller),
+                                    value: u7::new(0),
+                                },
+                            },
+                        });
+                    }
+                }
+                input.channels = 0;
+            }
+        }
+    }
+}
+impl<const I: usize, const O: usize> Default for Supervisor<I, O> {
+    #[inline]
+    fn default() -> Supervisor<I, O> {
+        Supervisor::new()
+    }
+}
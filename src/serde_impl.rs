@@ -0,0 +1,292 @@
+//! `serde` support for the types that cannot derive their implementations directly.
+
+#![cfg(feature = "serde")]
+
+use crate::primitive::{Fps, SmpteTime};
+use serde::Deserialize;
+
+/// Serialize byte strings as bytes rather than as sequences, and deserialize them without copying
+/// when the format allows it.
+pub(crate) mod bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &&[u8], ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_bytes(data)
+    }
+
+    pub fn deserialize<'de: 'a, 'a, D: Deserializer<'de>>(de: D) -> Result<&'a [u8], D::Error> {
+        <&'a [u8]>::deserialize(de)
+    }
+}
+
+/// Same as [`bytes`](bytes/index.html), but for 7-bit data, which is checked to be in range when
+/// deserializing.
+pub(crate) mod u7_slice {
+    use crate::num::u7;
+    use serde::{
+        de::{Error, Unexpected},
+        Deserialize, Deserializer, Serializer,
+    };
+
+    pub fn serialize<S: Serializer>(data: &&[u7], ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_bytes(u7::slice_as_int(data))
+    }
+
+    pub fn deserialize<'de: 'a, 'a, D: Deserializer<'de>>(de: D) -> Result<&'a [u7], D::Error> {
+        let raw = <&'a [u8]>::deserialize(de)?;
+        u7::slice_try_from_int(raw).ok_or_else(|| {
+            D::Error::invalid_value(
+                Unexpected::Bytes(raw),
+                &"data bytes in the 0x00..=0x7F range",
+            )
+        })
+    }
+}
+
+/// The unchecked fields of an `SmpteTime`, validated on conversion.
+#[derive(Deserialize)]
+pub(crate) struct RawSmpteTime {
+    hour: u8,
+    minute: u8,
+    second: u8,
+    frame: u8,
+    subframe: u8,
+    fps: Fps,
+}
+impl TryFrom<RawSmpteTime> for SmpteTime {
+    type Error = &'static str;
+    fn try_from(raw: RawSmpteTime) -> Result<SmpteTime, &'static str> {
+        SmpteTime::new(
+            raw.hour,
+            raw.minute,
+            raw.second,
+            raw.frame,
+            raw.subframe,
+            raw.fps,
+        )
+        .ok_or("smpte time out of range")
+    }
+}
+
+/// `serde` support for the owned event types.
+///
+/// Owned events are serialized exactly like their borrowed counterparts, but byte strings are
+/// deserialized into owned storage, so they can be read from formats that cannot borrow bytes, such
+/// as JSON.
+/// Deserialization goes through mirrors of the borrowed types, whose variants must be kept in the
+/// same order.
+#[cfg(feature = "alloc")]
+mod owned {
+    use crate::{
+        event::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind},
+        live::{LiveEvent, MtcQuarterFrameMessage, SystemCommon, SystemRealtime},
+        num::{u14, u24, u28, u4, u7},
+        owned::{OwnedLiveEvent, OwnedTrackEvent},
+        primitive::SmpteTime,
+    };
+    use alloc::vec::Vec;
+    use core::fmt;
+    use serde::{
+        de::{Error, SeqAccess, Unexpected, Visitor},
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+
+    /// A byte string, deserialized either from bytes or from a sequence of integers.
+    struct ByteBuf(Vec<u8>);
+    impl<'de> Deserialize<'de> for ByteBuf {
+        fn deserialize<D: Deserializer<'de>>(de: D) -> Result<ByteBuf, D::Error> {
+            struct BufVisitor;
+            impl<'de> Visitor<'de> for BufVisitor {
+                type Value = ByteBuf;
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("a byte string")
+                }
+                fn visit_bytes<E: Error>(self, data: &[u8]) -> Result<ByteBuf, E> {
+                    Ok(ByteBuf(data.to_vec()))
+                }
+                fn visit_byte_buf<E: Error>(self, data: Vec<u8>) -> Result<ByteBuf, E> {
+                    Ok(ByteBuf(data))
+                }
+                fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+                    //Do not trust the size hint too much
+                    let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+                    while let Some(byte) = seq.next_element()? {
+                        data.push(byte);
+                    }
+                    Ok(ByteBuf(data))
+                }
+            }
+            de.deserialize_byte_buf(BufVisitor)
+        }
+    }
+
+    /// A byte string of 7-bit data, which is checked to be in range.
+    struct U7Buf(Vec<u7>);
+    impl<'de> Deserialize<'de> for U7Buf {
+        fn deserialize<D: Deserializer<'de>>(de: D) -> Result<U7Buf, D::Error> {
+            let ByteBuf(raw) = ByteBuf::deserialize(de)?;
+            match u7::slice_try_from_int(&raw) {
+                Some(data) => Ok(U7Buf(data.to_vec())),
+                None => Err(D::Error::invalid_value(
+                    Unexpected::Bytes(&raw),
+                    &"data bytes in the 0x00..=0x7F range",
+                )),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename = "TrackEvent")]
+    struct TrackEventBuf {
+        delta: u28,
+        kind: TrackEventKindBuf,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename = "TrackEventKind")]
+    enum TrackEventKindBuf {
+        Midi { channel: u4, message: MidiMessage },
+        SysEx(ByteBuf),
+        Escape(ByteBuf),
+        Meta(MetaMessageBuf),
+    }
+    impl TrackEventKindBuf {
+        fn as_kind(&self) -> TrackEventKind<'_> {
+            match self {
+                TrackEventKindBuf::Midi { channel, message } => TrackEventKind::Midi {
+                    channel: *channel,
+                    message: *message,
+                },
+                TrackEventKindBuf::SysEx(data) => TrackEventKind::SysEx(&data.0),
+                TrackEventKindBuf::Escape(data) => TrackEventKind::Escape(&data.0),
+                TrackEventKindBuf::Meta(meta) => TrackEventKind::Meta(meta.as_meta()),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename = "MetaMessage")]
+    enum MetaMessageBuf {
+        TrackNumber(Option<u16>),
+        Text(ByteBuf),
+        Copyright(ByteBuf),
+        TrackName(ByteBuf),
+        InstrumentName(ByteBuf),
+        Lyric(ByteBuf),
+        Marker(ByteBuf),
+        CuePoint(ByteBuf),
+        ProgramName(ByteBuf),
+        DeviceName(ByteBuf),
+        MidiChannel(u4),
+        MidiPort(u7),
+        EndOfTrack,
+        Tempo(u24),
+        SmpteOffset(SmpteTime),
+        TimeSignature(u8, u8, u8, u8),
+        KeySignature(i8, bool),
+        SequencerSpecific(ByteBuf),
+        Unknown(u8, ByteBuf),
+    }
+    impl MetaMessageBuf {
+        fn as_meta(&self) -> MetaMessage<'_> {
+            use self::MetaMessageBuf::*;
+            match self {
+                TrackNumber(v) => MetaMessage::TrackNumber(*v),
+                Text(data) => MetaMessage::Text(&data.0),
+                Copyright(data) => MetaMessage::Copyright(&data.0),
+                TrackName(data) => MetaMessage::TrackName(&data.0),
+                InstrumentName(data) => MetaMessage::InstrumentName(&data.0),
+                Lyric(data) => MetaMessage::Lyric(&data.0),
+                Marker(data) => MetaMessage::Marker(&data.0),
+                CuePoint(data) => MetaMessage::CuePoint(&data.0),
+                ProgramName(data) => MetaMessage::ProgramName(&data.0),
+                DeviceName(data) => MetaMessage::DeviceName(&data.0),
+                MidiChannel(v) => MetaMessage::MidiChannel(*v),
+                MidiPort(v) => MetaMessage::MidiPort(*v),
+                EndOfTrack => MetaMessage::EndOfTrack,
+                Tempo(v) => MetaMessage::Tempo(*v),
+                SmpteOffset(v) => MetaMessage::SmpteOffset(*v),
+                TimeSignature(a, b, c, d) => MetaMessage::TimeSignature(*a, *b, *c, *d),
+                KeySignature(a, b) => MetaMessage::KeySignature(*a, *b),
+                SequencerSpecific(data) => MetaMessage::SequencerSpecific(&data.0),
+                Unknown(kind, data) => MetaMessage::Unknown(*kind, &data.0),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename = "LiveEvent")]
+    enum LiveEventBuf {
+        Midi { channel: u4, message: MidiMessage },
+        Common(SystemCommonBuf),
+        Realtime(SystemRealtime),
+    }
+    impl LiveEventBuf {
+        fn as_event(&self) -> LiveEvent<'_> {
+            match self {
+                LiveEventBuf::Midi { channel, message } => LiveEvent::Midi {
+                    channel: *channel,
+                    message: *message,
+                },
+                LiveEventBuf::Common(common) => LiveEvent::Common(common.as_common()),
+                LiveEventBuf::Realtime(msg) => LiveEvent::Realtime(*msg),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename = "SystemCommon")]
+    enum SystemCommonBuf {
+        SysEx(U7Buf),
+        MidiTimeCodeQuarterFrame(MtcQuarterFrameMessage, u4),
+        SongPosition(u14),
+        SongSelect(u7),
+        TuneRequest,
+        Undefined(u8, U7Buf),
+    }
+    impl SystemCommonBuf {
+        fn as_common(&self) -> SystemCommon<'_> {
+            use self::SystemCommonBuf::*;
+            match self {
+                SysEx(data) => SystemCommon::SysEx(&data.0),
+                MidiTimeCodeQuarterFrame(msg, v) => {
+                    SystemCommon::MidiTimeCodeQuarterFrame(*msg, *v)
+                }
+                SongPosition(v) => SystemCommon::SongPosition(*v),
+                SongSelect(v) => SystemCommon::SongSelect(*v),
+                TuneRequest => SystemCommon::TuneRequest,
+                Undefined(status, data) => SystemCommon::Undefined(*status, &data.0),
+            }
+        }
+    }
+
+    impl Serialize for OwnedTrackEvent {
+        #[inline]
+        fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+            self.as_event().serialize(ser)
+        }
+    }
+    impl<'de> Deserialize<'de> for OwnedTrackEvent {
+        fn deserialize<D: Deserializer<'de>>(de: D) -> Result<OwnedTrackEvent, D::Error> {
+            let event = TrackEventBuf::deserialize(de)?;
+            Ok(OwnedTrackEvent::new(&TrackEvent {
+                delta: event.delta,
+                kind: event.kind.as_kind(),
+            }))
+        }
+    }
+
+    impl Serialize for OwnedLiveEvent {
+        #[inline]
+        fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+            self.as_event().serialize(ser)
+        }
+    }
+    impl<'de> Deserialize<'de> for OwnedLiveEvent {
+        fn deserialize<D: Deserializer<'de>>(de: D) -> Result<OwnedLiveEvent, D::Error> {
+            Ok(OwnedLiveEvent::new(
+                &LiveEventBuf::deserialize(de)?.as_event(),
+            ))
+        }
+    }
+}
@@ -0,0 +1,647 @@
+//! A simulated USB bus, to test USB classes such as [`MidiClass`](../class/struct.MidiClass.html)
+//! on a host machine without any hardware.
+//!
+//! [`SimBus`](struct.SimBus.html) implements the `usb-device` `UsbBus` trait entirely in memory,
+//! and its [`SimHost`](struct.SimHost.html) handle plays the part of the USB host: it resets and
+//! enumerates the device through control transfers on endpoint 0, and exchanges bulk packets with
+//! the class endpoints.
+//!
+//! The host never runs the device by itself.
+//! Instead, every host method that needs the device to respond takes a `poll` closure, which should
+//! poll the `UsbDevice` along with its classes:
+//!
+//! ```rust
+//! use midly_usb::{class::MidiClass, sim::SimBus};
+//! use usb_device::prelude::*;
+//!
+//! let bus = SimBus::new();
+//! let host = bus.host();
+//! let alloc = usb_device::bus::UsbBusAllocator::new(bus);
+//! let mut midi = MidiClass::new(&alloc, 64);
+//! let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x5e4)).build();
+//!
+//! let config = host.enumerate(|| {
+//!     device.poll(&mut [&mut midi]);
+//! }).unwrap();
+//! assert_eq!(config[1], 2);
+//! ```
+//!
+//! This module is only available with the `sim` feature enabled.
+
+#![cfg(any(feature = "sim", all(test, feature = "embedded", feature = "std")))]
+
+use crate::prelude::*;
+use std::sync::{Arc, Mutex, MutexGuard};
+use usb_device::{
+    bus::{PollResult, UsbBus},
+    endpoint::{EndpointAddress, EndpointType},
+    Result as UsbResult, UsbDirection, UsbError,
+};
+
+/// How many endpoints there are in each direction.
+const MAX_ENDPOINTS: usize = 16;
+/// How many times the device is polled while waiting for it to respond, before giving up.
+const MAX_POLLS: usize = 64;
+
+//Standard requests
+const GET_DESCRIPTOR: u8 = 0x06;
+const SET_ADDRESS: u8 = 0x05;
+const SET_CONFIGURATION: u8 = 0x09;
+const SET_INTERFACE: u8 = 0x0B;
+
+/// The DEVICE descriptor type.
+pub const DESCRIPTOR_DEVICE: u8 = 0x01;
+/// The CONFIGURATION descriptor type.
+pub const DESCRIPTOR_CONFIGURATION: u8 = 0x02;
+/// The STRING descriptor type.
+pub const DESCRIPTOR_STRING: u8 = 0x03;
+/// The INTERFACE descriptor type, as found in the second byte of interface descriptors.
+pub const DESCRIPTOR_INTERFACE: u8 = 0x04;
+/// The ENDPOINT descriptor type, as found in the second byte of endpoint descriptors.
+pub const DESCRIPTOR_ENDPOINT: u8 = 0x05;
+
+/// The reasons why a simulated transfer failed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum SimError {
+    /// The device stalled the endpoint, usually because it rejected a control request.
+    Stalled,
+    /// The endpoint is not ready: an OUT endpoint still holds a packet that the device has not
+    /// read, or an IN endpoint has no packet for the host.
+    NotReady,
+    /// The device did not respond to a control transfer after being polled repeatedly.
+    Timeout,
+    /// The endpoint was never allocated by the device.
+    InvalidEndpoint,
+    /// The packet is larger than the max packet size of the endpoint.
+    BufferOverflow,
+}
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SimError::Stalled => "endpoint stalled",
+            SimError::NotReady => "endpoint not ready",
+            SimError::Timeout => "device did not respond",
+            SimError::InvalidEndpoint => "invalid endpoint",
+            SimError::BufferOverflow => "packet exceeds max packet size",
+        })
+    }
+}
+impl std::error::Error for SimError {}
+
+/// The state of a single endpoint.
+#[derive(Clone, Debug, Default)]
+struct Endpoint {
+    ep_type: Option<EndpointType>,
+    max_packet_size: u16,
+    stalled: bool,
+    /// A packet waiting to be read, by the device for OUT endpoints or by the host for IN
+    /// endpoints.
+    packet: Option<Vec<u8>>,
+    /// Whether the pending OUT packet is a SETUP packet.
+    setup: bool,
+    /// Whether the host took an IN packet, which has not been reported by `poll` yet.
+    complete: bool,
+}
+impl Endpoint {
+    #[inline]
+    fn allocated(&self) -> bool {
+        self.ep_type.is_some()
+    }
+
+    /// Clear the transfer state, but keep the allocation.
+    fn reset(&mut self) {
+        self.stalled = false;
+        self.packet = None;
+        self.setup = false;
+        self.complete = false;
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    out_eps: [Endpoint; MAX_ENDPOINTS],
+    in_eps: [Endpoint; MAX_ENDPOINTS],
+    enabled: bool,
+    /// Whether the host requested a reset that `poll` has not reported yet.
+    reset: bool,
+    address: u8,
+}
+impl State {
+    fn endpoint(&mut self, ep_addr: EndpointAddress) -> UsbResult<&mut Endpoint> {
+        let eps = match ep_addr.direction() {
+            UsbDirection::Out => &mut self.out_eps,
+            UsbDirection::In => &mut self.in_eps,
+        };
+        eps.get_mut(ep_addr.index())
+            .filter(|ep| ep.allocated())
+            .ok_or(UsbError::InvalidEndpoint)
+    }
+}
+
+/// An in-memory implementation of the `usb-device` `UsbBus` trait.
+///
+/// Create a [`SimHost`](struct.SimHost.html) with [`host`](#method.host) before handing the bus
+/// over to a `UsbBusAllocator`.
+#[derive(Debug, Default)]
+pub struct SimBus {
+    state: Arc<Mutex<State>>,
+}
+impl SimBus {
+    /// Create a new bus with no endpoints allocated.
+    #[inline]
+    pub fn new() -> SimBus {
+        SimBus::default()
+    }
+
+    /// Get a host handle connected to this bus.
+    #[inline]
+    pub fn host(&self) -> SimHost {
+        SimHost {
+            state: self.state.clone(),
+        }
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+impl UsbBus for SimBus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        _interval: u8,
+    ) -> UsbResult<EndpointAddress> {
+        let mut state = self.lock();
+        let eps = match ep_dir {
+            UsbDirection::Out => &mut state.out_eps,
+            UsbDirection::In => &mut state.in_eps,
+        };
+        let index = match ep_addr {
+            Some(addr) => {
+                ensure!(
+                    addr.index() < MAX_ENDPOINTS && !eps[addr.index()].allocated(),
+                    UsbError::InvalidEndpoint
+                );
+                addr.index()
+            }
+            None => {
+                //Endpoint 0 is reserved for control transfers
+                let first = if ep_type == EndpointType::Control {
+                    0
+                } else {
+                    1
+                };
+                (first..MAX_ENDPOINTS)
+                    .find(|&i| !eps[i].allocated())
+                    .ok_or(UsbError::EndpointOverflow)?
+            }
+        };
+        eps[index] = Endpoint {
+            ep_type: Some(ep_type),
+            max_packet_size,
+            ..Endpoint::default()
+        };
+        Ok(EndpointAddress::from_parts(index, ep_dir))
+    }
+
+    fn enable(&mut self) {
+        self.lock().enabled = true;
+    }
+
+    fn reset(&self) {
+        let state = &mut *self.lock();
+        for ep in state.out_eps.iter_mut().chain(state.in_eps.iter_mut()) {
+            ep.reset();
+        }
+        state.address = 0;
+    }
+
+    fn set_device_address(&self, addr: u8) {
+        self.lock().address = addr;
+    }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> UsbResult<usize> {
+        let mut state = self.lock();
+        let ep = state.endpoint(ep_addr)?;
+        ensure!(ep.packet.is_none(), UsbError::WouldBlock);
+        ensure!(
+            buf.len() <= ep.max_packet_size as usize,
+            UsbError::BufferOverflow
+        );
+        ep.packet = Some(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> UsbResult<usize> {
+        let mut state = self.lock();
+        let ep = state.endpoint(ep_addr)?;
+        let packet = ep.packet.take().ok_or(UsbError::WouldBlock)?;
+        ep.setup = false;
+        //Like real hardware, a packet that does not fit is dropped
+        ensure!(packet.len() <= buf.len(), UsbError::BufferOverflow);
+        buf[..packet.len()].copy_from_slice(&packet);
+        Ok(packet.len())
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+        if let Ok(ep) = self.lock().endpoint(ep_addr) {
+            ep.stalled = stalled;
+        }
+    }
+
+    fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        self.lock()
+            .endpoint(ep_addr)
+            .map(|ep| ep.stalled)
+            .unwrap_or(false)
+    }
+
+    fn suspend(&self) {}
+
+    fn resume(&self) {}
+
+    fn poll(&self) -> PollResult {
+        let mut state = self.lock();
+        if !state.enabled {
+            return PollResult::None;
+        }
+        if state.reset {
+            state.reset = false;
+            return PollResult::Reset;
+        }
+        let (mut ep_out, mut ep_in_complete, mut ep_setup) = (0, 0, 0);
+        for i in 0..MAX_ENDPOINTS {
+            let bit = 1 << i;
+            let out = &state.out_eps[i];
+            if out.packet.is_some() {
+                if out.setup {
+                    ep_setup |= bit;
+                } else {
+                    ep_out |= bit;
+                }
+            }
+            if mem::replace(&mut state.in_eps[i].complete, false) {
+                ep_in_complete |= bit;
+            }
+        }
+        if ep_out | ep_in_complete | ep_setup == 0 {
+            PollResult::None
+        } else {
+            PollResult::Data {
+                ep_out,
+                ep_in_complete,
+                ep_setup,
+            }
+        }
+    }
+}
+
+/// The host side of a [`SimBus`](struct.SimBus.html).
+///
+/// Control transfers are carried out on endpoint 0 through all of their stages, polling the device
+/// with the given closure until it responds.
+/// Bulk transfers only move a single packet in or out of an endpoint, and the device must be
+/// polled afterwards for the class to notice.
+#[derive(Clone, Debug)]
+pub struct SimHost {
+    state: Arc<Mutex<State>>,
+}
+impl SimHost {
+    /// The address assigned to the device, or 0 if it has not been addressed yet.
+    #[inline]
+    pub fn address(&self) -> u8 {
+        self.lock().address
+    }
+
+    /// Reset the device, as done when it is plugged in.
+    pub fn reset(&self, mut poll: impl FnMut()) -> StdResult<(), SimError> {
+        self.lock().reset = true;
+        for _ in 0..MAX_POLLS {
+            poll();
+            if !self.lock().reset {
+                return Ok(());
+            }
+        }
+        Err(SimError::Timeout)
+    }
+
+    /// Reset and enumerate the device like an operating system would, returning its configuration
+    /// descriptor.
+    ///
+    /// The device is assigned address 1 and its first configuration is selected.
+    pub fn enumerate(&self, mut poll: impl FnMut()) -> StdResult<Vec<u8>, SimError> {
+        self.reset(&mut poll)?;
+        self.get_descriptor(&mut poll, DESCRIPTOR_DEVICE, 0, 8)?;
+        self.set_address(&mut poll, 1)?;
+        self.get_descriptor(&mut poll, DESCRIPTOR_DEVICE, 0, 18)?;
+        let config = self.get_configuration_descriptor(&mut poll)?;
+        self.set_configuration(&mut poll, config.get(5).copied().unwrap_or(1))?;
+        Ok(config)
+    }
+
+    /// Issue a GET_DESCRIPTOR request, reading at most `length` bytes.
+    pub fn get_descriptor(
+        &self,
+        poll: impl FnMut(),
+        descriptor_type: u8,
+        index: u8,
+        length: u16,
+    ) -> StdResult<Vec<u8>, SimError> {
+        let value = u16::from(descriptor_type) << 8 | u16::from(index);
+        self.control_in(poll, 0x80, GET_DESCRIPTOR, value, 0, length)
+    }
+
+    /// Read a string descriptor in the given language, decoded from UTF-16.
+    ///
+    /// The device stalls if it has no such string.
+    pub fn get_string(
+        &self,
+        poll: impl FnMut(),
+        index: u8,
+        lang_id: u16,
+    ) -> StdResult<String, SimError> {
+        let value = u16::from(DESCRIPTOR_STRING) << 8 | u16::from(index);
+        let desc = self.control_in(poll, 0x80, GET_DESCRIPTOR, value, lang_id, 255)?;
+        let units = desc
+            .get(2..)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+        Ok(char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect())
+    }
+
+    /// Read the whole configuration descriptor, along with all of the interface, endpoint and
+    /// class-specific descriptors that follow it.
+    pub fn get_configuration_descriptor(
+        &self,
+        mut poll: impl FnMut(),
+    ) -> StdResult<Vec<u8>, SimError> {
+        let header = self.get_descriptor(&mut poll, DESCRIPTOR_CONFIGURATION, 0, 9)?;
+        let total_len = match header[..] {
+            [_, _, lo, hi, ..] => u16::from_le_bytes([lo, hi]),
+            _ => return Ok(header),
+        };
+        self.get_descriptor(poll, DESCRIPTOR_CONFIGURATION, 0, total_len)
+    }
+
+    /// Issue a SET_ADDRESS request.
+    #[inline]
+    pub fn set_address(&self, poll: impl FnMut(), address: u8) -> StdResult<(), SimError> {
+        self.control_out(poll, 0x00, SET_ADDRESS, address.into(), 0, &[])
+    }
+
+    /// Issue a SET_CONFIGURATION request.
+    #[inline]
+    pub fn set_configuration(&self, poll: impl FnMut(), value: u8) -> StdResult<(), SimError> {
+        self.control_out(poll, 0x00, SET_CONFIGURATION, value.into(), 0, &[])
+    }
+
+    /// Issue a SET_INTERFACE request.
+    #[inline]
+    pub fn set_interface(
+        &self,
+        poll: impl FnMut(),
+        interface: u8,
+        alt_setting: u8,
+    ) -> StdResult<(), SimError> {
+        self.control_out(
+            poll,
+            0x01,
+            SET_INTERFACE,
+            alt_setting.into(),
+            interface.into(),
+            &[],
+        )
+    }
+
+    /// Carry out a control transfer from the device to the host, returning the received data.
+    pub fn control_in(
+        &self,
+        mut poll: impl FnMut(),
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> StdResult<Vec<u8>, SimError> {
+        self.setup(
+            &mut poll,
+            request_type | 0x80,
+            request,
+            value,
+            index,
+            length,
+        )?;
+        let max_packet_size = self.lock().in_eps[0].max_packet_size as usize;
+        let mut data = Vec::new();
+        loop {
+            let mut chunk = None;
+            self.wait(&mut poll, |state| {
+                chunk = state.in_eps[0].packet.take();
+                if chunk.is_some() {
+                    state.in_eps[0].complete = true;
+                }
+                chunk.is_some()
+            })?;
+            let chunk = chunk.unwrap_or_default();
+            data.extend_from_slice(&chunk);
+            if chunk.len() < max_packet_size || data.len() >= length as usize {
+                break;
+            }
+        }
+        //Status stage
+        {
+            let mut state = self.lock();
+            state.out_eps[0].packet = Some(Vec::new());
+        }
+        self.wait(&mut poll, |state| state.out_eps[0].packet.is_none())?;
+        Ok(data)
+    }
+
+    /// Carry out a control transfer from the host to the device.
+    pub fn control_out(
+        &self,
+        mut poll: impl FnMut(),
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> StdResult<(), SimError> {
+        let length = u16::try_from(data.len()).map_err(|_| SimError::BufferOverflow)?;
+        self.setup(
+            &mut poll,
+            request_type & !0x80,
+            request,
+            value,
+            index,
+            length,
+        )?;
+        let max_packet_size = self.lock().out_eps[0].max_packet_size as usize;
+        for chunk in data.chunks(max_packet_size) {
+            self.lock().out_eps[0].packet = Some(chunk.to_vec());
+            self.wait(&mut poll, |state| state.out_eps[0].packet.is_none())?;
+        }
+        //Status stage, which must be completed for requests such as SET_ADDRESS to take effect
+        self.wait(&mut poll, |state| {
+            let ep = &mut state.in_eps[0];
+            if ep.packet.take().is_some() {
+                ep.complete = true;
+            }
+            ep.complete
+        })?;
+        self.wait(&mut poll, |state| !state.in_eps[0].complete)
+    }
+
+    /// Send a packet to a bulk or interrupt OUT endpoint.
+    ///
+    /// Fails with `NotReady` if the device has not read the previous packet yet.
+    pub fn bulk_out(&self, endpoint: u8, data: &[u8]) -> StdResult<(), SimError> {
+        let mut state = self.lock();
+        let ep = state
+            .endpoint(EndpointAddress::from_parts(
+                endpoint as usize & 0x0F,
+                UsbDirection::Out,
+            ))
+            .map_err(|_| SimError::InvalidEndpoint)?;
+        ensure!(!ep.stalled, SimError::Stalled);
+        ensure!(ep.packet.is_none(), SimError::NotReady);
+        ensure!(
+            data.len() <= ep.max_packet_size as usize,
+            SimError::BufferOverflow
+        );
+        ep.packet = Some(data.to_vec());
+        ep.setup = false;
+        Ok(())
+    }
+
+    /// Receive a packet from a bulk or interrupt IN endpoint.
+    ///
+    /// Fails with `NotReady` if the device has not written any packet.
+    pub fn bulk_in(&self, endpoint: u8) -> StdResult<Vec<u8>, SimError> {
+        let mut state = self.lock();
+        let ep = state
+            .endpoint(EndpointAddress::from_parts(
+                endpoint as usize & 0x0F,
+                UsbDirection::In,
+            ))
+            .map_err(|_| SimError::InvalidEndpoint)?;
+        ensure!(!ep.stalled, SimError::Stalled);
+        let packet = ep.packet.take().ok_or(SimError::NotReady)?;
+        ep.complete = true;
+        Ok(packet)
+    }
+
+    /// Whether an OUT endpoint can accept a packet from the host.
+    pub fn out_ready(&self, endpoint: u8) -> bool {
+        let mut state = self.lock();
+        match state.endpoint(EndpointAddress::from_parts(
+            endpoint as usize & 0x0F,
+            UsbDirection::Out,
+        )) {
+            Ok(ep) => !ep.stalled && ep.packet.is_none(),
+            Err(_) => false,
+        }
+    }
+
+    /// Whether an IN endpoint has a packet waiting for the host.
+    pub fn in_ready(&self, endpoint: u8) -> bool {
+        let mut state = self.lock();
+        match state.endpoint(EndpointAddress::from_parts(
+            endpoint as usize & 0x0F,
+            UsbDirection::In,
+        )) {
+            Ok(ep) => !ep.stalled && ep.packet.is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// Send a SETUP packet and wait for the device to read it.
+    fn setup(
+        &self,
+        poll: impl FnMut(),
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> StdResult<(), SimError> {
+        {
+            let mut state = self.lock();
+            //A SETUP packet aborts any previous control transfer and clears the stall condition
+            state.in_eps[0].reset();
+            let ep = &mut state.out_eps[0];
+            ep.reset();
+            let [value_lo, value_hi] = value.to_le_bytes();
+            let [index_lo, index_hi] = index.to_le_bytes();
+            let [length_lo, length_hi] = length.to_le_bytes();
+            ep.packet = Some(vec![
+                request_type,
+                request,
+                value_lo,
+                value_hi,
+                index_lo,
+                index_hi,
+                length_lo,
+                length_hi,
+            ]);
+            ep.setup = true;
+        }
+        self.wait(poll, |state| !state.out_eps[0].setup)
+    }
+
+    /// Poll the device until `done` returns true, failing if endpoint 0 stalls.
+    fn wait(
+        &self,
+        mut poll: impl FnMut(),
+        mut done: impl FnMut(&mut State) -> bool,
+    ) -> StdResult<(), SimError> {
+        for _ in 0..MAX_POLLS {
+            {
+                let mut state = self.lock();
+                ensure!(
+                    !state.out_eps[0].stalled && !state.in_eps[0].stalled,
+                    SimError::Stalled
+                );
+                if done(&mut state) {
+                    return Ok(());
+                }
+            }
+            poll();
+        }
+        Err(SimError::Timeout)
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+/// Split a configuration descriptor into its individual descriptors, each starting with its
+/// length and descriptor type.
+///
+/// Iteration stops early at the first truncated or zero-length descriptor.
+pub fn descriptors(mut bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    core::iter::from_fn(move || {
+        let len = *bytes.first()? as usize;
+        if len < 2 || len > bytes.len() {
+            return None;
+        }
+        let (descriptor, rest) = bytes.split_at(len);
+        bytes = rest;
+        Some(descriptor)
+    })
+}
+
+/// Lock the shared state, ignoring poisoning since the state is always consistent.
+#[inline]
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|err| err.into_inner())
+}
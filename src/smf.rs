@@ -78,11 +78,18 @@ impl<'a> Smf<'a> {
 
     /// Parse a `.mid` Standard Midi File from its raw bytes.
     /// If you casually want to parse `.mid` files, this is the function you're looking for.
+    #[inline]
     pub fn parse(raw: &[u8]) -> Result<Smf> {
-        let (header, tracks) = parse(raw)?;
+        Smf::parse_with(raw, ParseOptions::default())
+    }
+
+    /// Parse a `.mid` Standard Midi File from its raw bytes, using the given parsing options.
+    pub fn parse_with(raw: &[u8], options: ParseOptions) -> Result<Smf<'_>> {
+        let (header, tracks) = parse_with(raw, options)?;
         let track_count_hint = tracks.track_count_hint;
         let tracks = tracks.collect_tracks()?;
-        validate_smf(&header, track_count_hint, tracks.len())?;
+        validate_smf(&header, track_count_hint, tracks.len(), &options)
+            .map_err(|err| err.locate(raw.len(), None, None))?;
         Ok(Smf { header, tracks })
     }
 
@@ -135,6 +142,8 @@ impl<'a> Smf<'a> {
     ///
     /// WARNING: Any bytestrings, including meta messages, SysEx dumps and escape sequences will be
     /// replaced by empty bytestrings.
+    ///
+    /// See [`OwnedSmf`](struct.OwnedSmf.html) for a lossless alternative.
     pub fn to_static(&self) -> Smf<'static> {
         self.clone().make_static()
     }
@@ -186,11 +195,19 @@ impl<'a> SmfBytemap<'a> {
 
     /// Parse a Standard Midi File from its raw bytes, keeping a map to the original bytes that
     /// make up each event.
+    #[inline]
     pub fn parse(raw: &[u8]) -> Result<SmfBytemap> {
-        let (header, tracks) = parse(raw)?;
+        SmfBytemap::parse_with(raw, ParseOptions::default())
+    }
+
+    /// Parse a Standard Midi File from its raw bytes using the given parsing options, keeping a
+    /// map to the original bytes that make up each event.
+    pub fn parse_with(raw: &[u8], options: ParseOptions) -> Result<SmfBytemap<'_>> {
+        let (header, tracks) = parse_with(raw, options)?;
         let track_count_hint = tracks.track_count_hint;
         let tracks = tracks.collect_bytemapped()?;
-        validate_smf(&header, track_count_hint, tracks.len())?;
+        validate_smf(&header, track_count_hint, tracks.len(), &options)
+            .map_err(|err| err.locate(raw.len(), None, None))?;
         Ok(SmfBytemap { header, tracks })
     }
 
@@ -239,8 +256,13 @@ impl<'a> SmfBytemap<'a> {
 }
 
 #[cfg(feature = "alloc")]
-fn validate_smf(header: &Header, track_count_hint: u16, track_count: usize) -> Result<()> {
-    if cfg!(feature = "strict") {
+fn validate_smf(
+    header: &Header,
+    track_count_hint: u16,
+    track_count: usize,
+    options: &ParseOptions,
+) -> Result<()> {
+    if options.strict {
         ensure!(
             track_count_hint as usize == track_count,
             err_malformed!("file has a different amount of tracks than declared")
@@ -253,27 +275,146 @@ fn validate_smf(header: &Header, track_count_hint: u16, track_count: usize) -> R
     Ok(())
 }
 
+/// Options controlling how strictly files are parsed, and limits on how large they can be.
+///
+/// Limits are useful to defend against hostile files, and exceeding them is always an error, even
+/// if `strict` is disabled.
+/// Limits are checked after each track or event is parsed, but since parsing borrows from the
+/// input and makes no large allocations of its own, this is enough to bound memory usage.
+///
+/// Options can be built by overriding some fields of the default options:
+///
+/// ```rust
+/// use midly_usb::ParseOptions;
+///
+/// let options = ParseOptions {
+///     max_tracks: 64,
+///     max_sysex_len: 4096,
+///     ..ParseOptions::lenient()
+/// };
+/// ```
+///
+/// This type is always available, even in `no_std` environments.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct ParseOptions {
+    /// Reject corrupted or uncompliant files, even if they could be read anyway.
+    ///
+    /// `ErrorKind::Malformed` errors are only raised if this option is enabled.
+    /// If disabled, corrupted chunks are skipped and tracks are cut short at the first corrupted
+    /// event.
+    ///
+    /// Defaults to `true` if the `strict` feature is enabled, `false` otherwise.
+    pub strict: bool,
+    /// The maximum amount of tracks in a file.
+    pub max_tracks: usize,
+    /// The maximum amount of events in a single track.
+    pub max_track_events: usize,
+    /// The maximum length of the data in a single SysEx or Escape event, in bytes.
+    pub max_sysex_len: usize,
+    /// The maximum length of the data in a single meta message, in bytes.
+    pub max_meta_len: usize,
+}
+impl ParseOptions {
+    /// Options that reject corrupted or uncompliant files, and impose no limits.
+    #[inline]
+    pub const fn strict() -> ParseOptions {
+        ParseOptions {
+            strict: true,
+            max_tracks: usize::MAX,
+            max_track_events: usize::MAX,
+            max_sysex_len: usize::MAX,
+            max_meta_len: usize::MAX,
+        }
+    }
+
+    /// Options that read as much as possible out of corrupted or uncompliant files, and impose no
+    /// limits.
+    #[inline]
+    pub const fn lenient() -> ParseOptions {
+        ParseOptions {
+            strict: false,
+            ..ParseOptions::strict()
+        }
+    }
+
+    /// Check the data length limits on a raw event, including its delta time.
+    pub(crate) fn check_event(&self, mut raw: &[u8]) -> StdResult<(), &'static ErrorKind> {
+        //Avoid decoding the event again in the common case where there are no limits
+        if self.max_sysex_len == usize::MAX && self.max_meta_len == usize::MAX {
+            return Ok(());
+        }
+        let _delta = u28::read_varlen(&mut raw, false)?;
+        let (limit, err) = match raw.first() {
+            Some(0xF0) | Some(0xF7) => {
+                raw = &raw[1..];
+                (
+                    self.max_sysex_len,
+                    err_invalid!("sysex event exceeds size limit"),
+                )
+            }
+            Some(0xFF) => {
+                raw = raw.get(2..).unwrap_or(&[]);
+                (
+                    self.max_meta_len,
+                    err_invalid!("meta event exceeds size limit"),
+                )
+            }
+            _ => return Ok(()),
+        };
+        let _len = u28::read_varlen(&mut raw, false)?;
+        ensure!(raw.len() <= limit, err);
+        Ok(())
+    }
+}
+impl Default for ParseOptions {
+    /// Lenient or strict options depending on the `strict` feature, with no limits.
+    #[inline]
+    fn default() -> ParseOptions {
+        if cfg!(feature = "strict") {
+            ParseOptions::strict()
+        } else {
+            ParseOptions::lenient()
+        }
+    }
+}
+
 /// Parse a raw MIDI file lazily, yielding its header and a lazy track iterator.
 /// No allocations are made.
 ///
 /// The track iterator that is returned yields event iterators, which in turn yield concrete events.
 ///
 /// This function is always available, even in `no_std` environments.
+#[inline]
 pub fn parse(raw: &[u8]) -> Result<(Header, TrackIter)> {
-    let raw = match raw.get(..4) {
-        Some(b"RIFF") => riff::unwrap(raw)?,
+    parse_with(raw, ParseOptions::default())
+}
+
+/// Parse a raw MIDI file lazily using the given parsing options, yielding its header and a lazy
+/// track iterator.
+/// No allocations are made.
+///
+/// The options are passed on to the returned track iterator, and to the event iterators it yields.
+///
+/// This function is always available, even in `no_std` environments.
+pub fn parse_with(raw: &[u8], options: ParseOptions) -> Result<(Header, TrackIter<'_>)> {
+    let data = match raw.get(..4) {
+        Some(b"RIFF") => riff::unwrap(raw).map_err(|err| err.locate(0, None, None))?,
         Some(b"MThd") => raw,
-        _ => bail!(err_invalid!("not a midi file")),
+        _ => bail!(Error::from(err_invalid!("not a midi file")).locate(0, None, None)),
     };
-    let mut chunks = ChunkIter::new(raw);
+    //The SMF data might be nested within a RIFF file, so offsets must be adjusted
+    let start = data.as_ptr() as usize - raw.as_ptr() as usize;
+    let mut chunks = ChunkIter::new(data, start, options.strict);
     let (header, track_count) = match chunks.next() {
         Some(maybe_chunk) => match maybe_chunk.context(err_invalid!("invalid midi header"))? {
             Chunk::Header(header, track_count) => Ok((header, track_count)),
             Chunk::Track(_) => Err(err_invalid!("expected header, found track")),
+            Chunk::Unknown => unreachable!(),
         },
         None => Err(err_invalid!("no midi header chunk")),
-    }?;
-    let tracks = chunks.as_tracks(track_count);
+    }
+    .map_err(|err| Error::from(err).locate(start, Some(0), None))?;
+    let tracks = chunks.as_tracks(track_count, options);
     Ok((header, tracks))
 }
 
@@ -396,18 +537,33 @@ where
 struct ChunkIter<'a> {
     /// Starts at the current index, ends at EOF.
     raw: &'a [u8],
+    /// The absolute offset of `raw` within the original input.
+    offset: usize,
+    /// The absolute offset of the last chunk that was read.
+    last: usize,
+    /// The index of the next chunk.
+    index: usize,
+    strict: bool,
 }
 impl<'a> ChunkIter<'a> {
     #[inline]
-    fn new(raw: &'a [u8]) -> ChunkIter {
-        ChunkIter { raw }
+    fn new(raw: &'a [u8], offset: usize, strict: bool) -> ChunkIter<'a> {
+        ChunkIter {
+            raw,
+            offset,
+            last: offset,
+            index: 0,
+            strict,
+        }
     }
 
     #[inline]
-    fn as_tracks(self, track_count_hint: u16) -> TrackIter<'a> {
+    fn as_tracks(self, track_count_hint: u16, options: ParseOptions) -> TrackIter<'a> {
         TrackIter {
             chunks: self,
             track_count_hint,
+            track_count: 0,
+            options,
         }
     }
 }
@@ -415,16 +571,28 @@ impl<'a> Iterator for ChunkIter<'a> {
     type Item = Result<Chunk<'a>>;
     #[inline]
     fn next(&mut self) -> Option<Result<Chunk<'a>>> {
-        //Flip around option and result
-        match Chunk::read(&mut self.raw) {
-            Ok(Some(chunk)) => Some(Ok(chunk)),
-            Ok(None) => None,
-            Err(err) => {
-                //Ensure `Chunk::read` isn't called again, by setting read pointer to EOF (len 0)
-                //This is to prevent use of corrupted state (such as reading a new Chunk from the
-                //middle of a malformed message)
-                self.raw = &[];
-                Some(Err(err))
+        loop {
+            let len = self.raw.len();
+            //Flip around option and result
+            match Chunk::read(&mut self.raw, self.strict) {
+                Ok(Some(chunk)) => {
+                    self.last = self.offset;
+                    self.offset += len - self.raw.len();
+                    self.index += 1;
+                    match chunk {
+                        //Unknown chunk, just ignore and read the next one
+                        Chunk::Unknown => continue,
+                        chunk => break Some(Ok(chunk)),
+                    }
+                }
+                Ok(None) => break None,
+                Err(err) => {
+                    //Ensure `Chunk::read` isn't called again, by setting read pointer to EOF (len 0)
+                    //This is to prevent use of corrupted state (such as reading a new Chunk from the
+                    //middle of a malformed message)
+                    self.raw = &[];
+                    break Some(Err(err.locate(self.offset, Some(self.index), None)));
+                }
             }
         }
     }
@@ -434,43 +602,39 @@ impl<'a> Iterator for ChunkIter<'a> {
 enum Chunk<'a> {
     Header(Header, u16),
     Track(&'a [u8]),
+    Unknown,
 }
 impl<'a> Chunk<'a> {
     /// Should be called with a byte slice at least as large as the chunk (ideally until EOF).
     /// The slice will be modified to point to the next chunk.
     /// If we're *exactly* at EOF (slice length 0), returns a None signalling no more chunks.
-    fn read(raw: &mut &'a [u8]) -> Result<Option<Chunk<'a>>> {
-        Ok(loop {
-            if raw.is_empty() {
-                break None;
-            }
-            let id = raw
-                .split_checked(4)
-                .ok_or(err_invalid!("failed to read chunkid"))?;
-            let len = u32::read(raw).context(err_invalid!("failed to read chunklen"))?;
-            let chunkdata = match raw.split_checked(len as usize) {
-                Some(chunkdata) => chunkdata,
-                None => {
-                    if cfg!(feature = "strict") {
-                        bail!(err_malformed!("reached eof before chunk ended"));
-                    } else {
-                        //Just use the remainder of the file
-                        mem::replace(raw, &[])
-                    }
-                }
-            };
-            match id {
-                b"MThd" => {
-                    let (header, track_count) = Header::read(chunkdata)?;
-                    break Some(Chunk::Header(header, track_count));
-                }
-                b"MTrk" => {
-                    break Some(Chunk::Track(chunkdata));
+    fn read(raw: &mut &'a [u8], strict: bool) -> Result<Option<Chunk<'a>>> {
+        if raw.is_empty() {
+            return Ok(None);
+        }
+        let id = raw
+            .split_checked(4)
+            .ok_or(err_invalid!("failed to read chunkid"))?;
+        let len = u32::read(raw).context(err_invalid!("failed to read chunklen"))?;
+        let chunkdata = match raw.split_checked(len as usize) {
+            Some(chunkdata) => chunkdata,
+            None => {
+                if strict {
+                    bail!(err_malformed!("reached eof before chunk ended"));
+                } else {
+                    //Just use the remainder of the file
+                    mem::replace(raw, &[])
                 }
-                //Unknown chunk, just ignore and read the next one
-                _ => (),
             }
-        })
+        };
+        Ok(Some(match id {
+            b"MThd" => {
+                let (header, track_count) = Header::read(chunkdata)?;
+                Chunk::Header(header, track_count)
+            }
+            b"MTrk" => Chunk::Track(chunkdata),
+            _ => Chunk::Unknown,
+        }))
     }
 
     /// Write a header chunk into a writer.
@@ -566,6 +730,7 @@ impl<'a> Chunk<'a> {
 
 /// A MIDI file header, indicating metadata about the file.
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     /// Information about how should the tracks be laid out when playing them back.
     pub format: Format,
@@ -584,13 +749,13 @@ impl Header {
     }
 
     /// Read the contents of a header chunk, including the `Header` and the track count.
-    fn read(mut raw: &[u8]) -> Result<(Header, u16)> {
+    pub(crate) fn read(mut raw: &[u8]) -> Result<(Header, u16)> {
         let format = Format::read(&mut raw)?;
         let track_count = u16::read(&mut raw)?;
         let timing = Timing::read(&mut raw)?;
         Ok((Header::new(format, timing), track_count))
     }
-    fn encode(&self, track_count: u16) -> [u8; 6] {
+    pub(crate) fn encode(&self, track_count: u16) -> [u8; 6] {
         let mut bytes = [0; 6];
         bytes[0..2].copy_from_slice(&self.format.encode()[..]);
         bytes[2..4].copy_from_slice(&track_count.to_be_bytes()[..]);
@@ -606,7 +771,10 @@ impl Header {
 #[derive(Clone, Debug)]
 pub struct TrackIter<'a> {
     chunks: ChunkIter<'a>,
-    track_count_hint: u16,
+    pub(crate) track_count_hint: u16,
+    /// How many tracks have been yielded so far.
+    track_count: usize,
+    options: ParseOptions,
 }
 impl<'a> TrackIter<'a> {
     /// Create an event iterator from raw SMF bytes, excluding the header.
@@ -614,10 +782,22 @@ impl<'a> TrackIter<'a> {
     /// The main way to obtain raw SMF without a header is the [`unread`](#method.unread) method.
     #[inline]
     pub fn new(raw: &[u8]) -> TrackIter {
-        TrackIter {
-            chunks: ChunkIter::new(raw),
-            track_count_hint: 0,
-        }
+        TrackIter::new_with(raw, ParseOptions::default())
+    }
+
+    /// Create an event iterator from raw SMF bytes, excluding the header, using the given parsing
+    /// options.
+    ///
+    /// The options are passed on to the event iterators yielded by this iterator.
+    #[inline]
+    pub fn new_with(raw: &[u8], options: ParseOptions) -> TrackIter<'_> {
+        ChunkIter::new(raw, 0, options.strict).as_tracks(0, options)
+    }
+
+    /// The parsing options used by this iterator.
+    #[inline]
+    pub fn options(&self) -> &ParseOptions {
+        &self.options
     }
 
     /// Peek at the remaining unparsed bytes in the file.
@@ -684,18 +864,36 @@ impl<'a> Iterator for TrackIter<'a> {
             if let Some(chunk) = self.chunks.next() {
                 self.track_count_hint = self.track_count_hint.saturating_sub(1);
                 match chunk {
-                    Ok(Chunk::Track(track)) => break Some(Ok(EventIter::new(track))),
+                    Ok(Chunk::Track(track)) => {
+                        //The chunk data always ends at the current read position
+                        let offset = self.chunks.offset - track.len();
+                        let chunk = self.chunks.index - 1;
+                        if self.track_count >= self.options.max_tracks {
+                            //Do not attempt to read any more chunks
+                            self.chunks.raw = &[];
+                            let err = Error::from(err_invalid!("file exceeds track limit"));
+                            break Some(Err(err.locate(self.chunks.last, Some(chunk), None)));
+                        }
+                        self.track_count += 1;
+                        break Some(Ok(EventIter {
+                            inner: EventIterGeneric::located(track, offset, Some(chunk))
+                                .with_options(self.options),
+                        }));
+                    }
                     //Read another header (?)
                     Ok(Chunk::Header(..)) => {
-                        if cfg!(feature = "strict") {
-                            break Some(Err(err_malformed!("found duplicate header").into()));
+                        if self.options.strict {
+                            let err = Error::from(err_malformed!("found duplicate header"));
+                            let chunk = self.chunks.index - 1;
+                            break Some(Err(err.locate(self.chunks.last, Some(chunk), None)));
                         } else {
                             //Ignore duplicate header
                         }
                     }
+                    Ok(Chunk::Unknown) => unreachable!(),
                     //Failed to read chunk
                     Err(err) => {
-                        if cfg!(feature = "strict") {
+                        if self.options.strict {
                             break Some(Err(err).context(err_malformed!("invalid chunk")));
                         } else {
                             //Ignore invalid chunk
@@ -711,21 +909,108 @@ impl<'a> Iterator for TrackIter<'a> {
 
 trait EventKind<'a> {
     type Event: 'a;
-    fn read_ev(raw: &mut &'a [u8], running_status: &mut Option<u8>) -> Result<Self::Event>;
+    fn read_ev(
+        raw: &mut &'a [u8],
+        running_status: &mut Option<u8>,
+        strict: bool,
+    ) -> Result<Self::Event>;
 }
 
 #[derive(Clone, Debug)]
 struct EventIterGeneric<'a, T> {
     raw: &'a [u8],
     running_status: Option<u8>,
+    loc: EventLocation,
+    options: ParseOptions,
     _kind: PhantomData<T>,
 }
+
+/// Keeps track of where events are located, in order to report error positions.
+#[derive(Copy, Clone, Debug)]
+struct EventLocation {
+    /// The absolute offset at which the last byte of the track is located.
+    end: usize,
+    /// The index of the track chunk, if known.
+    chunk: Option<usize>,
+    /// The index of the next event.
+    event: usize,
+}
+
 impl<'a, T: EventKind<'a>> EventIterGeneric<'a, T> {
     #[inline]
     fn new(raw: &[u8]) -> EventIterGeneric<T> {
+        Self::located(raw, 0, None)
+    }
+
+    #[inline]
+    fn located(raw: &[u8], offset: usize, chunk: Option<usize>) -> EventIterGeneric<'_, T> {
         EventIterGeneric {
             raw,
             running_status: None,
+            loc: EventLocation {
+                end: offset + raw.len(),
+                chunk,
+                event: 0,
+            },
+            options: ParseOptions::default(),
+            _kind: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn with_options(mut self, options: ParseOptions) -> EventIterGeneric<'a, T> {
+        self.options = options;
+        self
+    }
+
+    /// Read a single event, keeping track of its position.
+    ///
+    /// Parse errors are only raised in strict mode, otherwise the track is silently cut short by
+    /// returning `None`.
+    /// Limit errors are always raised.
+    /// After any error, the rest of the track is discarded.
+    #[inline]
+    fn read_event(&mut self) -> Result<Option<T::Event>> {
+        let before = self.raw;
+        let offset = self.loc.end - before.len();
+        let (chunk, event) = (self.loc.chunk, Some(self.loc.event));
+        if self.loc.event >= self.options.max_track_events {
+            self.raw = &[];
+            let err = Error::from(err_invalid!("track exceeds event limit"));
+            return Err(err.locate(offset, chunk, event));
+        }
+        match T::read_ev(&mut self.raw, &mut self.running_status, self.options.strict) {
+            Ok(ev) => {
+                if let Err(err) = self
+                    .options
+                    .check_event(&before[..before.len() - self.raw.len()])
+                {
+                    self.raw = &[];
+                    return Err(Error::from(err).locate(offset, chunk, event));
+                }
+                self.loc.event += 1;
+                Ok(Some(ev))
+            }
+            Err(err) => {
+                self.raw = &[];
+                if self.options.strict {
+                    Err(err.locate(offset, chunk, event)).context(err_malformed!("malformed event"))
+                } else {
+                    //Stop reading track silently on failure
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Convert into an event iterator of another kind, keeping all state.
+    #[inline]
+    fn convert<U>(self) -> EventIterGeneric<'a, U> {
+        EventIterGeneric {
+            raw: self.raw,
+            running_status: self.running_status,
+            loc: self.loc,
+            options: self.options,
             _kind: PhantomData,
         }
     }
@@ -759,17 +1044,9 @@ impl<'a, T: EventKind<'a>> EventIterGeneric<'a, T> {
     fn into_vec(mut self) -> Result<Vec<T::Event>> {
         let mut events = Vec::with_capacity(self.estimate_events());
         while !self.raw.is_empty() {
-            match T::read_ev(&mut self.raw, &mut self.running_status) {
-                Ok(ev) => events.push(ev),
-                Err(err) => {
-                    self.raw = &[];
-                    if cfg!(feature = "strict") {
-                        Err(err).context(err_malformed!("malformed event"))?;
-                    } else {
-                        //Stop reading track silently on failure
-                        break;
-                    }
-                }
+            match self.read_event()? {
+                Some(ev) => events.push(ev),
+                None => break,
             }
         }
         Ok(events)
@@ -780,17 +1057,7 @@ impl<'a, T: EventKind<'a>> Iterator for EventIterGeneric<'a, T> {
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
         if !self.raw.is_empty() {
-            match T::read_ev(&mut self.raw, &mut self.running_status) {
-                Ok(ev) => Some(Ok(ev)),
-                Err(err) => {
-                    self.raw = &[];
-                    if cfg!(feature = "strict") {
-                        Some(Err(err).context(err_malformed!("malformed event")))
-                    } else {
-                        None
-                    }
-                }
-            }
+            self.read_event().transpose()
         } else {
             None
         }
@@ -811,8 +1078,8 @@ pub struct EventIter<'a> {
 impl<'a> EventKind<'a> for EventIter<'a> {
     type Event = TrackEvent<'a>;
     #[inline]
-    fn read_ev(raw: &mut &'a [u8], rs: &mut Option<u8>) -> Result<TrackEvent<'a>> {
-        TrackEvent::read(raw, rs)
+    fn read_ev(raw: &mut &'a [u8], rs: &mut Option<u8>, strict: bool) -> Result<TrackEvent<'a>> {
+        TrackEvent::read(raw, rs, strict)
     }
 }
 impl<'a> EventIter<'a> {
@@ -850,11 +1117,7 @@ impl<'a> EventIter<'a> {
     #[inline]
     pub fn bytemapped(self) -> EventBytemapIter<'a> {
         EventBytemapIter {
-            inner: EventIterGeneric {
-                raw: self.inner.raw,
-                running_status: self.inner.running_status,
-                _kind: PhantomData,
-            },
+            inner: self.inner.convert(),
         }
     }
 
@@ -893,8 +1156,8 @@ pub struct EventBytemapIter<'a> {
 impl<'a> EventKind<'a> for EventBytemapIter<'a> {
     type Event = (&'a [u8], TrackEvent<'a>);
     #[inline]
-    fn read_ev(raw: &mut &'a [u8], rs: &mut Option<u8>) -> Result<Self::Event> {
-        TrackEvent::read_bytemap(raw, rs)
+    fn read_ev(raw: &mut &'a [u8], rs: &mut Option<u8>, strict: bool) -> Result<Self::Event> {
+        TrackEvent::read_bytemap(raw, rs, strict)
     }
 }
 impl<'a> EventBytemapIter<'a> {
@@ -932,11 +1195,7 @@ impl<'a> EventBytemapIter<'a> {
     #[inline]
     pub fn not_bytemapped(self) -> EventIter<'a> {
         EventIter {
-            inner: EventIterGeneric {
-                raw: self.inner.raw,
-                running_status: self.inner.running_status,
-                _kind: PhantomData,
-            },
+            inner: self.inner.convert(),
         }
     }
 
@@ -8,9 +8,13 @@
 //!
 //! Handles all of the quirks specific to MIDI streams, including System Realtime messages embedded
 //! in the middle of another message.
+//!
+//! The `MidiStreamWriter` type does the opposite, encoding MIDI messages into a stream of bytes
+//! with the same quirks, for transmission over DIN MIDI for example.
 
 use crate::{
     event::MidiMessage,
+    io::NotSeekable,
     live::{LiveEvent, SystemRealtime},
     prelude::*,
 };
@@ -128,6 +132,169 @@ impl<B: Buffer> MidiStream<B> {
     }
 }
 
+/// A streaming raw MIDI encoder, the counterpart of [`MidiStream`](struct.MidiStream.html).
+///
+/// Channel messages use running status by default, omitting the status byte when it is the same
+/// as the status of the previous channel message.
+/// System Common messages cancel running status, while System Realtime messages leave it
+/// untouched, as required by the MIDI specification.
+///
+/// ```rust
+/// use midly_usb::{live::LiveEvent, stream::MidiStreamWriter, MidiMessage};
+///
+/// let note = |message| LiveEvent::Midi { channel: 0.into(), message };
+/// let mut writer = MidiStreamWriter::new();
+/// writer.set_note_off_as_note_on(true);
+/// let mut bytes = Vec::new();
+/// writer.write(&note(MidiMessage::NoteOn { key: 60.into(), vel: 100.into() }), &mut bytes).unwrap();
+/// writer.write(&note(MidiMessage::NoteOff { key: 60.into(), vel: 64.into() }), &mut bytes).unwrap();
+/// assert_eq!(bytes, [0x90, 60, 100, 60, 0]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct MidiStreamWriter {
+    running_status: Option<u8>,
+    use_running_status: bool,
+    refresh_period: Option<u32>,
+    /// How many channel messages were written since the status byte was last written.
+    since_status: u32,
+    note_off_as_note_on: bool,
+}
+impl MidiStreamWriter {
+    /// Create a new writer, with running status enabled and no conversion of `NoteOff` messages.
+    #[inline]
+    pub fn new() -> MidiStreamWriter {
+        MidiStreamWriter {
+            running_status: None,
+            use_running_status: true,
+            refresh_period: None,
+            since_status: 0,
+            note_off_as_note_on: false,
+        }
+    }
+
+    /// Enable or disable running status for channel messages.
+    #[inline]
+    pub fn set_running_status(&mut self, enabled: bool) {
+        self.use_running_status = enabled;
+        self.refresh_running_status();
+    }
+
+    /// Repeat the status byte at least once every `period` channel messages, even if running
+    /// status could be used, for receivers that may miss the first status byte.
+    ///
+    /// `None` (the default) never repeats the status byte unnecessarily.
+    /// To repeat it periodically in time instead, call
+    /// [`refresh_running_status`](#method.refresh_running_status) from a timer.
+    #[inline]
+    pub fn set_refresh_period(&mut self, period: Option<u32>) {
+        self.refresh_period = period;
+    }
+
+    /// Write `NoteOff` messages as `NoteOn` messages with zero velocity, so that notes can be
+    /// started and stopped without changing the status.
+    ///
+    /// Note that the release velocity of `NoteOff` messages is lost.
+    #[inline]
+    pub fn set_note_off_as_note_on(&mut self, enabled: bool) {
+        self.note_off_as_note_on = enabled;
+    }
+
+    /// Make the next channel message include its status byte, even if running status is
+    /// enabled.
+    ///
+    /// Useful after a receiver was (re)connected, so that it can catch up on the current status.
+    #[inline]
+    pub fn refresh_running_status(&mut self) {
+        self.running_status = None;
+    }
+
+    /// Write a single event to the given output.
+    #[inline]
+    pub fn write<W: Write>(&mut self, event: &LiveEvent, out: &mut W) -> WriteResult<W> {
+        self.write_interleaved(event, out, || None)
+    }
+
+    /// Write a single event to the given output, calling `realtime` before every byte and writing
+    /// the System Realtime messages that it returns until it returns `None`.
+    ///
+    /// This allows for tight timing of realtime messages such as timing clock, even in the middle
+    /// of long messages, as long as `out` does not buffer the bytes.
+    pub fn write_interleaved<W: Write>(
+        &mut self,
+        event: &LiveEvent,
+        out: &mut W,
+        realtime: impl FnMut() -> Option<SystemRealtime>,
+    ) -> WriteResult<W> {
+        let mut out = Interleaved { out, realtime };
+        match event {
+            LiveEvent::Midi { channel, message } => {
+                let message = match *message {
+                    MidiMessage::NoteOff { key, .. } if self.note_off_as_note_on => {
+                        MidiMessage::NoteOn {
+                            key,
+                            vel: u7::new(0),
+                        }
+                    }
+                    message => message,
+                };
+                let status = message.status_nibble() << 4 | channel.as_int();
+                let refresh = match self.refresh_period {
+                    Some(period) => self.since_status >= period,
+                    None => false,
+                };
+                if Some(status) != self.running_status || refresh {
+                    out.write(&[status])?;
+                    self.since_status = 0;
+                }
+                if self.use_running_status {
+                    self.running_status = Some(status);
+                }
+                self.since_status = self.since_status.saturating_add(1);
+                message.write(&mut out)?;
+            }
+            LiveEvent::Common(common) => {
+                self.running_status = None;
+                common.write(&mut out)?;
+            }
+            LiveEvent::Realtime(realtime) => {
+                out.write(&[realtime.encode()])?;
+            }
+        }
+        Ok(())
+    }
+}
+impl Default for MidiStreamWriter {
+    #[inline]
+    fn default() -> MidiStreamWriter {
+        MidiStreamWriter::new()
+    }
+}
+
+/// Writes bytes one at a time, inserting realtime messages before each byte.
+struct Interleaved<'a, W, F> {
+    out: &'a mut W,
+    realtime: F,
+}
+impl<'a, W: Write, F: FnMut() -> Option<SystemRealtime>> Write for Interleaved<'a, W, F> {
+    type Error = W::Error;
+    type Seekable = NotSeekable<Self>;
+
+    fn write(&mut self, bytes: &[u8]) -> WriteResult<W> {
+        for &byte in bytes {
+            while let Some(realtime) = (self.realtime)() {
+                self.out.write(&[realtime.encode()])?;
+            }
+            self.out.write(&[byte])?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn invalid_input(msg: &'static str) -> W::Error {
+        W::invalid_input(msg)
+    }
+}
+
 /// Describes types that can be used as data buffers for the [`MidiStream`](struct.MidiStream.html)
 /// type.
 ///
@@ -1,4 +1,6 @@
-use crate::{EventIter, Result as MidlyResult, TrackEvent};
+use crate::{
+    live::LiveEvent, EventIter, MidiMessage, Result as MidlyResult, TrackEvent, TrackEventKind,
+};
 use std::{fs, path::Path, time::Instant};
 
 /// Open and read the content of a file.
@@ -44,6 +46,44 @@ impl crate::io::Write for Vec<u8> {
     }
 }
 
+/// Build a track event out of its delta time and kind.
+fn ev(delta: u32, kind: TrackEventKind<'static>) -> TrackEvent<'static> {
+    TrackEvent {
+        delta: delta.into(),
+        kind,
+    }
+}
+
+/// Build the track event kind of a channel message.
+fn midi(channel: u8, message: MidiMessage) -> TrackEventKind<'static> {
+    TrackEventKind::Midi {
+        channel: channel.into(),
+        message,
+    }
+}
+
+/// Build a live channel message.
+fn live(channel: u8, message: MidiMessage) -> LiveEvent<'static> {
+    LiveEvent::Midi {
+        channel: channel.into(),
+        message,
+    }
+}
+
+fn note_on(key: u8, vel: u8) -> MidiMessage {
+    MidiMessage::NoteOn {
+        key: key.into(),
+        vel: vel.into(),
+    }
+}
+
+fn note_off(key: u8, vel: u8) -> MidiMessage {
+    MidiMessage::NoteOff {
+        key: key.into(),
+        vel: vel.into(),
+    }
+}
+
 fn test_rewrite(filename: &str) {
     println!("parsing...");
     open! {smf: filename};
@@ -607,3 +647,2777 @@ mod parse {
         assert_send::<crate::Arena>();
     }
 }
+
+#[cfg(feature = "alloc")]
+mod transform {
+    use super::{ev, midi, note_off, note_on};
+    use crate::{
+        num::{u4, u7},
+        transform::{
+            ChangePpq, CompressVelocity, Quantize, QuantizeMode, RemapChannels, ScaleVelocity,
+            ShiftTime, StretchTime, Transform, Transpose,
+        },
+        MetaMessage, MidiMessage, Smf, Timing, Track, TrackEvent, TrackEventKind,
+    };
+
+    fn end_of_track(delta: u32) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        }
+    }
+
+    fn deltas(track: &Track) -> Vec<u32> {
+        track.iter().map(|ev| ev.delta.as_int()).collect()
+    }
+
+    fn keys_and_vels(track: &Track) -> Vec<(u8, u8)> {
+        track
+            .iter()
+            .filter_map(|ev| match ev.kind {
+                TrackEventKind::Midi {
+                    message: MidiMessage::NoteOn { key, vel },
+                    ..
+                } => Some((key.as_int(), vel.as_int())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn transpose_clamps() {
+        let mut track = vec![
+            ev(0, midi(0, note_on(60, 100))),
+            ev(0, midi(0, note_on(125, 100))),
+            ev(0, midi(0, note_on(2, 0))),
+        ];
+        Transpose::new(5).apply_track(&mut track);
+        assert_eq!(keys_and_vels(&track), [(65, 100), (127, 100), (7, 0)]);
+        Transpose::new(-10).apply_track(&mut track);
+        assert_eq!(keys_and_vels(&track), [(55, 100), (117, 100), (0, 0)]);
+    }
+
+    #[test]
+    fn velocity_never_becomes_note_off() {
+        let mut track = vec![
+            ev(0, midi(0, note_on(60, 100))),
+            ev(0, midi(0, note_on(61, 3))),
+            ev(0, midi(0, note_on(62, 0))),
+        ];
+        ScaleVelocity::new(2.0).apply_track(&mut track);
+        assert_eq!(keys_and_vels(&track), [(60, 127), (61, 6), (62, 0)]);
+        ScaleVelocity::new(0.0)
+            .with_offset(-20)
+            .apply_track(&mut track);
+        assert_eq!(keys_and_vels(&track), [(60, 1), (61, 1), (62, 0)]);
+
+        let mut track = vec![
+            ev(0, midi(0, note_on(60, 120))),
+            ev(0, midi(0, note_on(61, 40))),
+        ];
+        CompressVelocity::new(u7::from(80), 2.0).apply_track(&mut track);
+        assert_eq!(keys_and_vels(&track), [(60, 100), (61, 40)]);
+    }
+
+    #[test]
+    fn remap_channels_keeps_timing() {
+        let mut track = vec![
+            ev(10, midi(0, note_on(60, 100))),
+            ev(20, midi(9, note_on(36, 100))),
+            ev(30, midi(1, note_on(62, 100))),
+            end_of_track(5),
+        ];
+        RemapChannels::identity()
+            .map(u4::from(1), u4::from(2))
+            .drop_channel(u4::from(9))
+            .apply_track(&mut track);
+        assert_eq!(deltas(&track), [10, 50, 5]);
+        match track[1].kind {
+            TrackEventKind::Midi { channel, .. } => assert_eq!(channel, 2),
+            _ => panic!("expected a midi event"),
+        }
+
+        let mut track = vec![ev(10, midi(3, note_on(60, 100))), end_of_track(5)];
+        RemapChannels::filter(|ch| ch != 3).apply_track(&mut track);
+        assert_eq!(deltas(&track), [15]);
+    }
+
+    #[test]
+    fn stretch_and_shift() {
+        let mut track = vec![
+            ev(1, midi(0, note_on(60, 100))),
+            ev(1, midi(0, note_on(61, 100))),
+            end_of_track(1),
+        ];
+        StretchTime::new(3, 2).apply_track(&mut track);
+        // Absolute ticks 1, 2, 3 become 1.5, 3, 4.5, rounded to 2, 3, 5
+        assert_eq!(deltas(&track), [2, 1, 2]);
+
+        ShiftTime::new(10).apply_track(&mut track);
+        assert_eq!(deltas(&track), [12, 1, 2]);
+        ShiftTime::new(-13).apply_track(&mut track);
+        assert_eq!(deltas(&track), [0, 0, 2]);
+    }
+
+    #[test]
+    fn change_ppq_roundtrip() {
+        let smf = Smf::parse(include_bytes!("../test-asset/Clementi.mid")).unwrap();
+        let ppq = match smf.header.timing {
+            Timing::Metrical(ppq) => ppq,
+            _ => panic!("expected metrical timing"),
+        };
+        let mut changed = smf.clone();
+        ChangePpq::to((ppq.as_int() * 4).into()).apply(&mut changed);
+        assert_eq!(
+            changed.header.timing,
+            Timing::Metrical((ppq.as_int() * 4).into())
+        );
+        assert_ne!(changed, smf);
+        ChangePpq::to(ppq)
+            .then(Transpose::new(0))
+            .apply(&mut changed);
+        assert_eq!(changed, smf);
+    }
+
+    fn absolute(track: &Track) -> Vec<u32> {
+        track
+            .iter()
+            .scan(0, |abs, ev| {
+                *abs += ev.delta.as_int();
+                Some(*abs)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quantize_starts() {
+        // Sixteenth note grid at 96 ticks per beat is a 24 tick grid
+        let mut track = vec![
+            ev(5, midi(0, note_on(60, 100))),
+            ev(40, midi(0, note_off(60, 64))),
+            ev(20, midi(0, note_on(62, 100))),
+            ev(10, midi(0, note_off(62, 64))),
+            end_of_track(0),
+        ];
+        Quantize::new(96.into(), 16).apply_track(&mut track);
+        assert_eq!(absolute(&track), [0, 40, 72, 82, 82]);
+
+        let mut track = vec![
+            ev(10, midi(0, note_on(60, 100))),
+            ev(10, midi(0, note_off(60, 64))),
+        ];
+        Quantize::new(96.into(), 16)
+            .strength(50)
+            .apply_track(&mut track);
+        assert_eq!(absolute(&track), [5, 15]);
+    }
+
+    #[test]
+    fn quantize_triplets_and_swing() {
+        // Eighth note triplets at 96 ticks per beat are 32 ticks apart
+        let mut track = vec![
+            ev(30, midi(0, note_on(60, 100))),
+            ev(1, midi(0, note_off(60, 64))),
+        ];
+        Quantize::new(96.into(), 12).apply_track(&mut track);
+        assert_eq!(absolute(&track), [32, 33]);
+
+        // Swing delays every other grid point
+        let mut track = vec![
+            ev(24, midi(0, note_on(60, 100))),
+            ev(1, midi(0, note_off(60, 64))),
+        ];
+        Quantize::new(96.into(), 16)
+            .swing(0.5)
+            .apply_track(&mut track);
+        assert_eq!(absolute(&track), [30, 31]);
+    }
+
+    #[test]
+    fn quantize_never_produces_empty_notes() {
+        // Both ends snap to the same grid point
+        let mut track = vec![
+            ev(20, midi(0, note_on(60, 100))),
+            ev(6, midi(0, note_off(60, 64))),
+        ];
+        Quantize::new(96.into(), 16)
+            .mode(QuantizeMode::StartsAndEnds)
+            .apply_track(&mut track);
+        assert_eq!(absolute(&track), [24, 48]);
+        assert!(matches!(
+            track[0].kind,
+            TrackEventKind::Midi {
+                message: MidiMessage::NoteOn { .. },
+                ..
+            }
+        ));
+
+        // Overlapping notes with the same key must not invert
+        let mut track = vec![
+            ev(0, midi(0, note_on(60, 100))),
+            ev(30, midi(0, note_on(60, 100))),
+            ev(5, midi(0, note_off(60, 64))),
+            ev(40, midi(0, note_off(60, 64))),
+        ];
+        Quantize::new(96.into(), 16)
+            .mode(QuantizeMode::StartsAndEnds)
+            .apply_track(&mut track);
+        assert_eq!(absolute(&track), [0, 24, 24, 72]);
+        assert!(matches!(
+            track[1].kind,
+            TrackEventKind::Midi {
+                message: MidiMessage::NoteOff { .. },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn quantize_keeps_notes_with_the_same_start() {
+        // Both notes with the same key snap to tick 0, so the second one moves to the next step
+        let mut track = vec![
+            ev(0, midi(0, note_on(60, 100))),
+            ev(5, midi(0, note_on(60, 90))),
+            ev(5, midi(0, note_off(60, 64))),
+            ev(40, midi(0, note_off(60, 64))),
+            end_of_track(0),
+        ];
+        Quantize::new(96.into(), 16).apply_track(&mut track);
+        assert_eq!(absolute(&track), [0, 10, 24, 69, 69]);
+        assert_eq!(keys_and_vels(&track), [(60, 100), (60, 90)]);
+        assert!(matches!(
+            track[1].kind,
+            TrackEventKind::Midi {
+                message: MidiMessage::NoteOff { .. },
+                ..
+            }
+        ));
+    }
+}
+
+#[cfg(feature = "alloc")]
+mod notes {
+    use super::{ev, midi, note_off, note_on};
+    use crate::{
+        notes::{self, DanglingPolicy, Note, OverlapPolicy, PairingOptions},
+        MetaMessage, MidiMessage, Smf, TrackEvent, TrackEventKind,
+    };
+
+    fn spans(notes: &[Note]) -> Vec<(u8, u64, u64)> {
+        notes
+            .iter()
+            .map(|note| (note.velocity.as_int(), note.start_tick, note.duration))
+            .collect()
+    }
+
+    #[test]
+    fn overlap_policies() {
+        let track = vec![
+            ev(0, midi(0, note_on(60, 10))),
+            ev(10, midi(0, note_on(60, 20))),
+            ev(10, midi(0, note_off(60, 64))),
+            ev(10, midi(0, note_on(60, 0))),
+        ];
+        let fifo = notes::notes(&track, PairingOptions::default());
+        assert_eq!(spans(&fifo), [(10, 0, 20), (20, 10, 20)]);
+        assert_eq!(fifo[0].release_velocity, Some(64.into()));
+        assert_eq!(fifo[1].release_velocity, None);
+
+        let lifo = notes::notes(
+            &track,
+            PairingOptions {
+                overlap: OverlapPolicy::Lifo,
+                ..PairingOptions::default()
+            },
+        );
+        assert_eq!(spans(&lifo), [(10, 0, 30), (20, 10, 10)]);
+    }
+
+    #[test]
+    fn dangling_notes() {
+        let track = vec![
+            ev(0, midi(0, note_on(60, 10))),
+            ev(5, midi(0, note_on(62, 20))),
+            ev(5, midi(0, note_off(62, 0))),
+            TrackEvent {
+                delta: 7.into(),
+                kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+            },
+        ];
+        let closed = notes::notes(&track, PairingOptions::default());
+        assert_eq!(spans(&closed), [(10, 0, 17), (20, 5, 5)]);
+        let dropped = notes::notes(
+            &track,
+            PairingOptions {
+                dangling: DanglingPolicy::Drop,
+                ..PairingOptions::default()
+            },
+        );
+        assert_eq!(spans(&dropped), [(20, 5, 5)]);
+    }
+
+    #[test]
+    fn empty_notes_to_track() {
+        let note = |key: u8, start_tick, duration| Note {
+            channel: 0.into(),
+            key: key.into(),
+            velocity: 100.into(),
+            release_velocity: None,
+            start_tick,
+            duration,
+        };
+        let track = notes::to_track(&[note(60, 0, 10), note(62, 10, 0), note(60, 10, 5)]);
+        //The other note ends first, while the empty note ends right after it starts
+        let expected = [
+            ev(0, midi(0, note_on(60, 100))),
+            ev(10, midi(0, note_on(60, 0))),
+            ev(0, midi(0, note_on(62, 100))),
+            ev(0, midi(0, note_on(62, 0))),
+            ev(0, midi(0, note_on(60, 100))),
+            ev(5, midi(0, note_on(60, 0))),
+        ];
+        assert_eq!(track[..6], expected);
+    }
+
+    #[test]
+    fn notes_roundtrip() {
+        let smf = Smf::parse(include_bytes!("../test-asset/Clementi.mid")).unwrap();
+        for track in smf.tracks.iter() {
+            let extracted = notes::notes(track, PairingOptions::default());
+            let note_ons = track
+                .iter()
+                .filter(|ev| match ev.kind {
+                    TrackEventKind::Midi {
+                        message: MidiMessage::NoteOn { vel, .. },
+                        ..
+                    } => vel > 0,
+                    _ => false,
+                })
+                .count();
+            assert_eq!(extracted.len(), note_ons);
+            let rebuilt = notes::to_track(&extracted);
+            assert_eq!(notes::notes(&rebuilt, PairingOptions::default()), extracted);
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+mod edit {
+    use super::{ev, midi, note_on};
+    use crate::{
+        num::u15, Format, Header, MetaMessage, MidiMessage, Smf, Timing, Track, TrackEventKind,
+    };
+
+    fn song(ppq: u16, tempo: u32, channel: u8) -> Smf<'static> {
+        let mut smf = Smf::new(Header::new(
+            Format::Parallel,
+            Timing::Metrical(u15::from(ppq)),
+        ));
+        smf.tracks.push(vec![
+            ev(0, TrackEventKind::Meta(MetaMessage::Tempo(tempo.into()))),
+            ev(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ]);
+        smf.tracks.push(vec![
+            ev(0, midi(channel, note_on(60, 100))),
+            ev(ppq as u32, midi(channel, note_on(60, 0))),
+            ev(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ]);
+        smf
+    }
+
+    fn count_note_ons(tracks: &[Track]) -> usize {
+        tracks
+            .iter()
+            .flatten()
+            .filter(|ev| match ev.kind {
+                TrackEventKind::Midi {
+                    message: MidiMessage::NoteOn { vel, .. },
+                    ..
+                } => vel > 0,
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn merge_rescales_and_remaps() {
+        let merged = Smf::merge(&[song(96, 500_000, 0), song(480, 400_000, 0)]);
+        assert_eq!(merged.header.timing, Timing::Metrical(480.into()));
+        assert_eq!(merged.tracks.len(), 3);
+        // The second file sets a conflicting tempo at the same tick, so it is dropped
+        assert_eq!(
+            merged.tracks[0],
+            [
+                ev(0, TrackEventKind::Meta(MetaMessage::Tempo(500_000.into()))),
+                ev(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+            ]
+        );
+        assert_eq!(merged.tracks[1][1].delta, 480);
+        assert_eq!(merged.tracks[1][0].kind, midi(0, note_on(60, 100)));
+        assert_eq!(merged.tracks[2][0].kind, midi(1, note_on(60, 100)));
+    }
+
+    #[test]
+    fn slice_restores_state() {
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(96.into()),
+        ));
+        smf.tracks.push(vec![
+            ev(0, TrackEventKind::Meta(MetaMessage::Tempo(400_000.into()))),
+            ev(
+                0,
+                midi(2, MidiMessage::ProgramChange { program: 12.into() }),
+            ),
+            ev(0, midi(2, note_on(60, 100))),
+            ev(50, midi(2, note_on(62, 100))),
+            ev(50, midi(2, note_on(60, 0))),
+            ev(50, midi(2, note_on(62, 0))),
+            ev(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ]);
+        let (head, tail) = smf.split_at(75);
+        assert_eq!(
+            head.tracks[0][3..],
+            [
+                ev(50, midi(2, note_on(62, 100))),
+                ev(
+                    25,
+                    midi(
+                        2,
+                        MidiMessage::NoteOff {
+                            key: 60.into(),
+                            vel: 0.into()
+                        }
+                    )
+                ),
+                ev(
+                    0,
+                    midi(
+                        2,
+                        MidiMessage::NoteOff {
+                            key: 62.into(),
+                            vel: 0.into()
+                        }
+                    )
+                ),
+                ev(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+            ]
+        );
+        assert_eq!(
+            tail.tracks[0],
+            [
+                ev(0, TrackEventKind::Meta(MetaMessage::Tempo(400_000.into()))),
+                ev(
+                    0,
+                    midi(2, MidiMessage::ProgramChange { program: 12.into() },),
+                ),
+                ev(75, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+            ]
+        );
+    }
+
+    #[test]
+    fn slice_restores_controllers_in_order() {
+        let cc = |controller: u8, value: u8| {
+            midi(
+                0,
+                MidiMessage::Controller {
+                    controller: controller.into(),
+                    value: value.into(),
+                },
+            )
+        };
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(96.into()),
+        ));
+        smf.tracks.push(vec![
+            ev(0, cc(7, 100)),
+            ev(0, cc(121, 0)),
+            ev(0, cc(6, 2)),
+            ev(0, cc(101, 0)),
+            ev(0, cc(100, 0)),
+            ev(0, cc(99, 1)),
+            ev(0, cc(98, 2)),
+            ev(0, cc(101, 0)),
+            ev(0, cc(96, 0)),
+            ev(0, midi(0, MidiMessage::ProgramChange { program: 5.into() })),
+            ev(0, cc(0, 1)),
+            ev(0, cc(32, 2)),
+            ev(100, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+        ]);
+        let (_head, tail) = smf.split_at(50);
+        //Bank before program, the last selected parameter right before its data, and no channel
+        //mode or relative messages
+        assert_eq!(
+            tail.tracks[0],
+            [
+                ev(0, cc(0, 1)),
+                ev(0, cc(32, 2)),
+                ev(0, midi(0, MidiMessage::ProgramChange { program: 5.into() })),
+                ev(0, cc(99, 1)),
+                ev(0, cc(98, 2)),
+                ev(0, cc(101, 0)),
+                ev(0, cc(100, 0)),
+                ev(0, cc(6, 2)),
+                ev(0, cc(7, 100)),
+                ev(50, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_keeps_notes() {
+        let smf = Smf::parse(include_bytes!("../test-asset/Clementi.mid")).unwrap();
+        let (head, tail) = smf.split_at(2000);
+        assert_eq!(
+            count_note_ons(&head.tracks) + count_note_ons(&tail.tracks),
+            count_note_ons(&smf.tracks)
+        );
+        let merged = Smf::merge(&[head, tail]);
+        assert_eq!(count_note_ons(&merged.tracks), count_note_ons(&smf.tracks));
+    }
+}
+
+#[cfg(feature = "alloc")]
+mod validate {
+    use super::*;
+    use crate::{
+        num::{u4, u7},
+        validate::{Diagnostic, Violation},
+    };
+
+    fn diag(offset: usize, track: usize, event: usize, violation: Violation) -> Diagnostic {
+        Diagnostic {
+            offset,
+            track: Some(track),
+            event: Some(event),
+            violation,
+        }
+    }
+
+    #[test]
+    fn valid_files() {
+        for name in &["Clementi.mid", "Beethoven.rmi", "Sandstorm.mid"] {
+            open! {file: name};
+            let report = crate::validate(&file);
+            assert!(report.is_valid(), "{}: {:?}", name, report);
+        }
+    }
+
+    #[test]
+    fn real_world_violations() {
+        open! {file: "Levels.mid"};
+        assert_eq!(
+            crate::validate(&file).diagnostics,
+            [diag(
+                29,
+                0,
+                1,
+                Violation::MetaLength {
+                    meta_type: 0x58,
+                    expected: 4,
+                    found: 2,
+                }
+            )]
+        );
+
+        open! {file: "PiDamaged.mid"};
+        let report = crate::validate(&file);
+        assert_eq!(
+            report.diagnostics[0].violation,
+            Violation::ChunkLengthMismatch {
+                declared: 1194067,
+                available: 65461,
+            }
+        );
+        assert_eq!(report.diagnostics[0].track, Some(2));
+        assert_eq!(
+            report.diagnostics.last().unwrap().violation,
+            Violation::TrackCountMismatch {
+                declared: 30,
+                found: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn track_violations() {
+        #[rustfmt::skip]
+        let file: &[u8] = &[
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 2, 0, 96,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 30,
+            //Note on, never ended
+            0x00, 0x90, 60, 100,
+            //SysEx, followed by running status
+            0x00, 0xF0, 2, 0x01, 0xF7,
+            0x00, 62, 0,
+            //Non-7-bit data byte
+            0x00, 0xB0, 0x85, 0x00,
+            //Tempo with the wrong length
+            0x00, 0xFF, 0x51, 2, 0x07, 0xA1,
+            //Two end of tracks, the second being after the end of the track
+            0x00, 0xFF, 0x2F, 0,
+            0x00, 0xFF, 0x2F, 0,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 4,
+            0x00, 0x80, 60, 0,
+        ];
+        let report = crate::validate(file);
+        assert_eq!(
+            report.diagnostics,
+            [
+                diag(
+                    22,
+                    0,
+                    0,
+                    Violation::UnmatchedNoteOn {
+                        channel: u4::from(0),
+                        key: u7::from(60),
+                    }
+                ),
+                diag(31, 0, 2, Violation::RunningStatusAfterSysEx),
+                diag(34, 0, 3, Violation::NonDataByte(0x85)),
+                diag(
+                    38,
+                    0,
+                    4,
+                    Violation::MetaLength {
+                        meta_type: 0x51,
+                        expected: 3,
+                        found: 2,
+                    }
+                ),
+                diag(48, 0, 6, Violation::EventAfterEndOfTrack),
+                diag(48, 0, 6, Violation::DuplicateEndOfTrack),
+                Diagnostic {
+                    offset: 64,
+                    track: Some(1),
+                    event: None,
+                    violation: Violation::MissingEndOfTrack,
+                },
+                Diagnostic {
+                    offset: 64,
+                    track: None,
+                    event: None,
+                    violation: Violation::SingleTrackWithMultipleTracks(2),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn long_varlen() {
+        #[rustfmt::skip]
+        let file: &[u8] = &[
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 9,
+            //A 5 byte delta, whose last byte must not be read as a status byte
+            0x81, 0x81, 0x81, 0x81, 0x00, 0xFF, 0x2F, 0,
+            0x00,
+        ];
+        let report = crate::validate(file);
+        assert_eq!(report.diagnostics[0], diag(22, 0, 0, Violation::LongVarlen));
+    }
+}
+
+mod error_position {
+    use crate::{live::LiveEvent, parse, EventIter};
+
+    #[rustfmt::skip]
+    const FILE: &[u8] = &[
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 1, 0, 96,
+        b'X', b'Y', b'Z', b'W', 0, 0, 0, 1, 0,
+        b'M', b'T', b'r', b'k', 0, 0, 0, 10,
+        0x00, 0x90, 60, 100,
+        0x10, 0x80, 60, 0,
+        //System Common messages cannot appear in files
+        0x00, 0xF1,
+    ];
+
+    #[test]
+    fn header_errors() {
+        let err = parse(b"RIFX").unwrap_err();
+        assert_eq!(
+            (err.offset(), err.chunk(), err.event()),
+            (Some(0), None, None)
+        );
+        let err = parse(&FILE[..10]).unwrap_err();
+        assert_eq!(
+            (err.offset(), err.chunk(), err.event()),
+            (Some(0), Some(0), None)
+        );
+        assert!(err.to_string().ends_with("(at byte 0, chunk 0)"));
+    }
+
+    #[test]
+    fn event_errors() {
+        let (_header, mut tracks) = parse(FILE).unwrap();
+        let events = tracks.next().unwrap().unwrap();
+        let last = events.last().unwrap();
+        if cfg!(feature = "strict") {
+            let err = last.unwrap_err();
+            assert_eq!(err.offset(), Some(39));
+            //The unknown chunk still counts as a chunk
+            assert_eq!(err.chunk(), Some(2));
+            assert_eq!(err.event(), Some(2));
+            assert!(err.to_string().ends_with("(at byte 39, chunk 2, event 2)"));
+        } else {
+            assert!(last.is_ok());
+        }
+
+        //Event iterators created by hand count offsets from the start of their slice
+        let mut events = EventIter::new(&FILE[35..]);
+        assert!(events.next().unwrap().is_ok());
+        if let Some(Err(err)) = events.next() {
+            assert_eq!(
+                (err.offset(), err.chunk(), err.event()),
+                (Some(4), None, Some(1))
+            );
+        }
+    }
+
+    #[test]
+    fn live_errors() {
+        let err = LiveEvent::parse(&[0x90, 60]).unwrap_err();
+        assert_eq!(err.offset(), Some(0));
+    }
+}
+
+#[cfg(feature = "alloc")]
+mod recover {
+    use super::*;
+    use crate::{
+        recover::{SkipReason, Warning},
+        MetaMessage, Smf, TrackEventKind,
+    };
+
+    #[test]
+    fn damaged_file() {
+        open! {file: "PiDamaged.mid"};
+        let (smf, warnings) = Smf::parse_recover(&file).unwrap();
+        assert_eq!(smf.tracks.len(), 3);
+        assert_eq!(
+            warnings[0],
+            Warning::TruncatedChunk {
+                offset: 67,
+                chunk: 3,
+                declared: 1194067,
+                available: 65461,
+            }
+        );
+        assert!(matches!(
+            warnings[1],
+            Warning::TruncatedTrack {
+                track: 2,
+                events: 16361,
+                start: 65533,
+                end: 65536,
+                ..
+            }
+        ));
+        assert_eq!(
+            warnings[2],
+            Warning::TrackCountMismatch {
+                declared: 30,
+                found: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn resync_after_corrupt_events() {
+        #[rustfmt::skip]
+        let file: &[u8] = &[
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 1, 0, 96,
+            b'X', b'F', b'I', b'H', 0, 0, 0, 2, 1, 2,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 26,
+            0x00, 0x90, 60, 100,
+            //System Common messages cannot appear in files
+            0x00, 0xF1, 0xF2, 0x05,
+            0x10, 0x80, 60, 0,
+            0x00, 0x90, 62, 100,
+            0x10, 0x80, 62, 0,
+            0x00, 0xFF, 0x2F, 0,
+            0x7F, 0xF3,
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 1, 0, 96,
+            0, 0, 0,
+        ];
+        let (smf, warnings) = Smf::parse_recover(file).unwrap();
+        assert_eq!(smf.tracks[0].len(), 5);
+        assert_eq!(smf.tracks[0][1].delta, 0x10);
+        assert_eq!(
+            smf.tracks[0][4].kind,
+            TrackEventKind::Meta(MetaMessage::EndOfTrack)
+        );
+        assert!(matches!(
+            warnings[1],
+            Warning::DroppedBytes {
+                track: 0,
+                event: 1,
+                start: 36,
+                end: 40,
+                ..
+            }
+        ));
+        assert_eq!(
+            warnings[0],
+            Warning::SkippedChunk {
+                offset: 14,
+                chunk: 1,
+                id: *b"XFIH",
+                reason: SkipReason::Unknown,
+            }
+        );
+        assert!(matches!(
+            warnings[2],
+            Warning::TruncatedTrack {
+                track: 0,
+                events: 5,
+                start: 56,
+                end: 58,
+                ..
+            }
+        ));
+        assert_eq!(
+            warnings[3..],
+            [
+                Warning::SkippedChunk {
+                    offset: 58,
+                    chunk: 3,
+                    id: *b"MThd",
+                    reason: SkipReason::DuplicateHeader,
+                },
+                Warning::TrailingBytes { offset: 72, len: 3 },
+            ]
+        );
+    }
+}
+
+#[cfg(feature = "alloc")]
+mod parse_options {
+    use super::*;
+    use crate::{parse_with, ErrorKind, ParseOptions, Smf};
+
+    #[test]
+    fn runtime_strictness() {
+        open! {file: "PiDamaged.mid"};
+        assert!(Smf::parse_with(&file, ParseOptions::lenient()).is_ok());
+        let err = Smf::parse_with(&file, ParseOptions::strict()).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Malformed(_)));
+
+        //Options are passed on to the event iterators
+        let (_header, mut tracks) = parse_with(&file, ParseOptions::lenient()).unwrap();
+        assert_eq!(tracks.options(), &ParseOptions::lenient());
+        assert!(tracks.nth(2).unwrap().unwrap().all(|ev| ev.is_ok()));
+    }
+
+    #[test]
+    fn limits() {
+        open! {file: "Clementi.mid"};
+        let track_limit = ParseOptions {
+            max_tracks: 1,
+            ..ParseOptions::lenient()
+        };
+        let err = Smf::parse_with(&file, track_limit).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Invalid("file exceeds track limit"));
+        assert_eq!(err.chunk(), Some(2));
+
+        let event_limit = ParseOptions {
+            max_track_events: 10,
+            ..ParseOptions::lenient()
+        };
+        let err = Smf::parse_with(&file, event_limit).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Invalid("track exceeds event limit"));
+        assert_eq!(err.event(), Some(10));
+
+        let meta_limit = ParseOptions {
+            max_meta_len: 3,
+            ..ParseOptions::lenient()
+        };
+        let err = Smf::parse_with(&file, meta_limit).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            ErrorKind::Invalid("meta event exceeds size limit")
+        );
+
+        open! {file: "SysExTest.mid"};
+        let sysex_limit = ParseOptions {
+            max_sysex_len: 4,
+            ..ParseOptions::lenient()
+        };
+        assert!(Smf::parse_with(&file, ParseOptions::lenient()).is_ok());
+        let err = Smf::parse_with(&file, sysex_limit).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            ErrorKind::Invalid("sysex event exceeds size limit")
+        );
+    }
+}
+
+mod smf_writer {
+    use super::*;
+    use crate::{
+        io::{Cursor, IoWrap},
+        Smf, SmfWriter,
+    };
+
+    fn stream<W: crate::io::Write>(smf: &Smf, mut writer: SmfWriter<W>) -> W
+    where
+        W::Error: std::fmt::Debug,
+    {
+        for track in &smf.tracks {
+            writer.open_track().unwrap();
+            for ev in track {
+                writer.push(ev).unwrap();
+            }
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn matches_batch_writer() {
+        open! {file: "Clementi.mid"};
+        let smf = Smf::parse(&file).unwrap();
+        let mut expected = Vec::new();
+        smf.write(&mut expected).unwrap();
+
+        //Seekable, back-patching lengths
+        let out = stream(&smf, SmfWriter::new(smf.header, Vec::new()).unwrap());
+        assert_eq!(out, expected);
+
+        //Non-seekable, buffering tracks
+        let writer = SmfWriter::with_track_count(smf.header, 3, IoWrap(Vec::new())).unwrap();
+        assert_eq!(stream(&smf, writer).0, expected);
+
+        //Seekable without allocation
+        let mut buf = vec![0; expected.len()];
+        let writer = SmfWriter::new(smf.header, Cursor::new(&mut buf)).unwrap();
+        assert_eq!(stream(&smf, writer).written(), &expected[..]);
+    }
+
+    #[test]
+    fn misuse() {
+        open! {file: "Clementi.mid"};
+        let smf = Smf::parse(&file).unwrap();
+        assert!(SmfWriter::new(smf.header, IoWrap(Vec::new())).is_err());
+
+        let mut writer = SmfWriter::with_track_count(smf.header, 2, Vec::new()).unwrap();
+        assert!(writer.push(&smf.tracks[0][0]).is_err());
+        writer.open_track().unwrap();
+        writer.push(&smf.tracks[0][0]).unwrap();
+        assert!(writer.finish().is_err());
+    }
+}
+
+#[cfg(feature = "alloc")]
+mod owned {
+    use super::*;
+    use crate::{
+        live::{LiveEvent, OwnedLiveEvent, SystemCommon},
+        num::u7,
+        MetaMessage, OwnedSmf, OwnedTrackEvent, Smf, SmfWriter, TrackEventKind,
+    };
+
+    #[test]
+    fn keeps_payloads() {
+        open! {file: "SysExTest.mid"};
+        let smf = Smf::parse(&file).unwrap();
+        let owned = OwnedSmf::parse(&file).unwrap();
+        assert_eq!(owned.to_smf(), smf);
+        assert_ne!(smf.to_static(), smf);
+
+        let mut expected = Vec::new();
+        smf.write(&mut expected).unwrap();
+        let mut out = Vec::new();
+        owned.write(&mut out).unwrap();
+        assert_eq!(out, expected);
+
+        //Owned events outlive the raw bytes and can be streamed into a writer
+        drop(file);
+        let handle = std::thread::spawn(move || {
+            let mut writer = SmfWriter::new(owned.header, Vec::new()).unwrap();
+            for track in &owned.tracks {
+                writer.open_track().unwrap();
+                for ev in track {
+                    writer.push_owned(ev).unwrap();
+                }
+            }
+            writer.finish().unwrap()
+        });
+        assert_eq!(handle.join().unwrap(), expected);
+    }
+
+    #[test]
+    fn event_conversions() {
+        let name = b"Lead".to_vec();
+        let event = TrackEvent {
+            delta: 10.into(),
+            kind: TrackEventKind::Meta(MetaMessage::Unknown(0x7E, &name)),
+        };
+        let mut owned = OwnedTrackEvent::from(event);
+        drop(name);
+        assert_eq!(owned.payload(), b"Lead");
+        assert_eq!(
+            owned.kind(),
+            TrackEventKind::Meta(MetaMessage::Unknown(0x7E, b"Lead"))
+        );
+        owned.set_kind(TrackEventKind::Meta(MetaMessage::EndOfTrack));
+        assert_eq!(owned.payload(), b"");
+        assert_eq!(TrackEvent::from(&owned).delta, crate::num::u28::new(10));
+
+        let raw = [0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7];
+        let owned = OwnedLiveEvent::parse(&raw).unwrap();
+        assert_eq!(
+            owned.as_event(),
+            LiveEvent::Common(SystemCommon::SysEx(u7::slice_from_int(&raw[1..5])))
+        );
+        let mut out = Vec::new();
+        owned.write(&mut out).unwrap();
+        assert_eq!(out, raw);
+    }
+}
+
+mod smf_reader {
+    use super::*;
+    use crate::{
+        io::{ReadAt, SeekableWrap},
+        Smf, SmfBytemap, SmfReader, TrackCursor, TrackEvent,
+    };
+
+    /// Encode an event standalone, so that events can be compared regardless of running status.
+    fn encode(ev: &TrackEvent) -> Vec<u8> {
+        let mut raw = Vec::new();
+        ev.write(&mut None, &mut raw).unwrap();
+        raw
+    }
+
+    /// Read all tracks, interleaving the cursors one event at a time.
+    fn read_interleaved<R: ReadAt, const N: usize>(reader: &mut SmfReader<R>) -> Vec<Vec<Vec<u8>>>
+    where
+        R::Error: std::fmt::Debug,
+    {
+        let mut cursors: Vec<TrackCursor<N>> = Vec::new();
+        while let Some(cursor) = reader.next_track().unwrap() {
+            cursors.push(cursor);
+        }
+        let mut tracks = vec![Vec::new(); cursors.len()];
+        while cursors.iter().any(|c| !c.is_finished()) {
+            for (cursor, track) in cursors.iter_mut().zip(tracks.iter_mut()) {
+                if let Some(ev) = cursor.next_event(reader.source()).unwrap() {
+                    track.push(encode(&ev.as_event()));
+                }
+            }
+        }
+        tracks
+    }
+
+    #[test]
+    fn matches_batch_parser() {
+        for name in [
+            "Clementi.mid",
+            "Levels.mid",
+            "SysExTest.mid",
+            "Beethoven.rmi",
+        ] {
+            let file = fs::read(format!("test-asset/{}", name)).unwrap();
+            let smf = Smf::parse(&file).unwrap();
+            let expected = smf
+                .tracks
+                .iter()
+                .map(|track| track.iter().map(encode).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+
+            let mut reader = SmfReader::new(&file[..]).unwrap();
+            assert_eq!(*reader.header(), smf.header);
+            assert_eq!(read_interleaved::<_, 1024>(&mut reader), expected);
+
+            let mut reader = SmfReader::new(SeekableWrap(std::io::Cursor::new(&file))).unwrap();
+            assert_eq!(read_interleaved::<_, 1024>(&mut reader), expected);
+        }
+    }
+
+    #[test]
+    fn small_buffer() {
+        open! {file: "SysExTest.mid"};
+        let smf = Smf::parse(&file).unwrap();
+        let mut reader = SmfReader::new(&file[..]).unwrap();
+        let mut read = Vec::new();
+        let mut skipped = 0;
+        while let Some(mut cursor) = reader.next_track::<8>().unwrap() {
+            let mut track = Vec::new();
+            loop {
+                match cursor.next_event(reader.source()) {
+                    Ok(Some(ev)) => track.push(encode(&ev.as_event())),
+                    Ok(None) => break,
+                    Err(_) => skipped += 1,
+                }
+            }
+            read.push(track);
+        }
+        assert!(skipped > 0);
+        let bytemap = SmfBytemap::parse(&file).unwrap();
+        let fitting = bytemap
+            .tracks
+            .iter()
+            .map(|track| track.iter().filter(|(raw, _)| raw.len() <= 8).count());
+        let total = smf.tracks.iter().map(Vec::len).sum::<usize>();
+        assert_eq!(read.iter().map(Vec::len).sum::<usize>() + skipped, total);
+        assert!(read.iter().map(Vec::len).eq(fitting));
+    }
+
+    #[test]
+    fn events_outlive_reads() {
+        open! {file: "Clementi.mid"};
+        let smf = Smf::parse(&file).unwrap();
+        let mut reader = SmfReader::new(&file[..]).unwrap();
+        let mut cursor = reader.next_track::<64>().unwrap().unwrap();
+        let mut events = Vec::new();
+        while let Some(ev) = cursor.next_event(reader.source()).unwrap() {
+            events.push(ev);
+        }
+        let events = events.iter().map(|ev| ev.as_event());
+        assert!(events.eq(smf.tracks[0].iter().copied()));
+    }
+
+    #[test]
+    fn skip_resets_running_status() {
+        let mut file = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk\0\0\0\x18".to_vec();
+        file.extend_from_slice(b"\0\x90\x3C\x40\0\xF0\x0A123456789\xF7\0\x3C\0\0\xFF\x2F\0");
+        let mut reader = SmfReader::new(&file[..]).unwrap();
+        let mut cursor = reader.next_track::<8>().unwrap().unwrap();
+        assert!(cursor.next_event(reader.source()).unwrap().is_some());
+        assert!(cursor.next_event(reader.source()).is_err());
+        //The data bytes after the SysEx have no running status to refer to
+        assert!(!matches!(cursor.next_event(reader.source()), Ok(Some(_))));
+        assert!(cursor.is_finished());
+    }
+
+    #[test]
+    fn bad_files() {
+        assert!(SmfReader::new(&b"MTrk\0\0\0\0"[..]).is_err());
+        assert!(SmfReader::new(&b"RIFF\0\0\0\0RMID"[..]).is_err());
+        assert!(SmfReader::new(&b""[..]).is_err());
+    }
+}
+
+mod rmid {
+    use super::*;
+    use crate::{Rmid, Smf, SoundBank};
+
+    #[test]
+    fn roundtrip() {
+        open! {file: "Beethoven.rmi"};
+        let rmid = Rmid::parse(&file).unwrap();
+        assert_eq!(rmid.smf, Smf::parse(&file).unwrap());
+        assert_eq!(rmid.info(*b"IART"), Some(&b"Ludwig von Beethoven"[..]));
+        assert_eq!(rmid.info(*b"ICOP"), Some(&b"1995 Midisoft Corporation"[..]));
+        assert_eq!(rmid.info.len(), 3);
+        assert_eq!(rmid.extra.len(), 2);
+        assert!(rmid.extra.iter().all(|chunk| &chunk.id == b"DISP"));
+        assert_eq!(rmid.sound_bank, None);
+
+        let mut out = Vec::new();
+        rmid.write(&mut out).unwrap();
+        assert_eq!(Rmid::parse(&out).unwrap(), rmid);
+        //The MIDI data is re-encoded, but all other chunks should be identical
+        let tail = |raw: &[u8]| {
+            let data_len = u32::from_le_bytes([raw[16], raw[17], raw[18], raw[19]]) as usize;
+            raw[20 + data_len + data_len % 2..].to_vec()
+        };
+        assert_eq!(tail(&out), tail(&file));
+    }
+
+    #[test]
+    fn metadata_and_sound_bank() {
+        open! {file: "Clementi.mid"};
+        let mut rmid = Rmid::new(Smf::parse(&file).unwrap());
+        rmid.set_info(*b"INAM", b"Sonatina");
+        rmid.set_info(*b"ISFT", b"midly");
+        rmid.set_info(*b"INAM", b"Sonatina in C");
+        rmid.sound_bank = Some(SoundBank::Sf2(b"sfbkLIST\x00\x00\x00\x00"));
+        let mut out = Vec::new();
+        rmid.write(&mut out).unwrap();
+
+        let parsed = Rmid::parse(&out).unwrap();
+        assert_eq!(parsed, rmid);
+        assert_eq!(parsed.info(*b"INAM"), Some(&b"Sonatina in C"[..]));
+        assert_eq!(parsed.info(*b"ICMT"), None);
+        assert_eq!(Smf::parse(&out).unwrap(), rmid.smf);
+
+        assert!(Rmid::parse(&file).is_err());
+    }
+
+    #[test]
+    fn chunk_order() {
+        #[rustfmt::skip]
+        let file: &[u8] = &[
+            b'R', b'I', b'F', b'F', 74, 0, 0, 0, b'R', b'M', b'I', b'D',
+            //Metadata before the MIDI data, with a value ending in two NULs
+            b'L', b'I', b'S', b'T', 16, 0, 0, 0, b'I', b'N', b'F', b'O',
+            b'I', b'N', b'A', b'M', 3, 0, 0, 0, b'A', 0, 0, 0,
+            b'd', b'a', b't', b'a', 26, 0, 0, 0,
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 4, 0x00, 0xFF, 0x2F, 0,
+            b'D', b'I', b'S', b'P', 4, 0, 0, 0, 1, 0, 0, 0,
+        ];
+        let rmid = Rmid::parse(file).unwrap();
+        assert_eq!(rmid.info(*b"INAM"), Some(&b"A\0"[..]));
+        let mut out = Vec::new();
+        rmid.write(&mut out).unwrap();
+        assert_eq!(out, file);
+
+        //New chunks are added in the default order
+        let mut rmid = rmid;
+        rmid.extra.clear();
+        rmid.sound_bank = Some(SoundBank::Dls(b"DLS "));
+        let mut out = Vec::new();
+        rmid.write(&mut out).unwrap();
+        let ids: Vec<&[u8]> = vec![&out[12..16], &out[36..40], &out[70..74]];
+        assert_eq!(ids, [&b"LIST"[..], b"data", b"RIFF"]);
+    }
+}
+
+mod xmf {
+    use super::*;
+    use crate::{ResourceFormat, Smf, Xmf, XmfContent};
+
+    /// Encode a VLQ, always using 4 bytes to make offsets easy to compute.
+    fn vlq(n: usize) -> Vec<u8> {
+        vec![
+            0x80 | (n >> 21) as u8 & 0x7F,
+            0x80 | (n >> 14) as u8 & 0x7F,
+            0x80 | (n >> 7) as u8 & 0x7F,
+            n as u8 & 0x7F,
+        ]
+    }
+
+    fn meta_item(field: usize, data: &[&[u8]]) -> Vec<u8> {
+        let data = data.concat();
+        [vlq(0), vlq(field), vlq(0), vlq(data.len()), data].concat()
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        meta_item(1, &[&vlq(0), name.as_bytes()])
+    }
+
+    fn format(id: usize) -> Vec<u8> {
+        meta_item(3, &[&vlq(0), &vlq(id)])
+    }
+
+    /// Build a node out of its metadata, unpackers, item count and content (including the
+    /// reference type).
+    fn node(meta: &[u8], unpackers: &[u8], items: usize, content: &[u8]) -> Vec<u8> {
+        let header_len = 4 * 5 + meta.len() + unpackers.len();
+        let len = header_len + content.len();
+        [
+            &vlq(len)[..],
+            &vlq(items),
+            &vlq(header_len),
+            &vlq(meta.len()),
+            meta,
+            &vlq(unpackers.len()),
+            unpackers,
+            content,
+        ]
+        .concat()
+    }
+
+    /// The length of the file header for the given version.
+    fn header_len(version: &[u8; 4]) -> usize {
+        if version == b"2.00" {
+            8 + 8 + 4 * 4
+        } else {
+            8 + 4 * 4
+        }
+    }
+
+    /// Build a file out of a tree that starts right after the header, followed by extra data.
+    fn xmf_file(version: &[u8; 4], tree: &[u8], extra: &[u8]) -> Vec<u8> {
+        let start = header_len(version);
+        let mut raw = [&b"XMF_"[..], version].concat();
+        if version == b"2.00" {
+            raw.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 1]);
+        }
+        raw.extend(vlq(start + tree.len() + extra.len()));
+        raw.extend(vlq(0));
+        raw.extend(vlq(start));
+        raw.extend(vlq(start + tree.len()));
+        assert_eq!(raw.len(), start);
+        [&raw[..], tree, extra].concat()
+    }
+
+    #[test]
+    fn inline_tree() {
+        open! {file: "Clementi.mid"};
+        let smf_node = node(
+            &[name("song"), format(1)].concat(),
+            &[],
+            0,
+            &[&vlq(1)[..], &file].concat(),
+        );
+        let dls_node = node(&format(3), &[], 0, &[&vlq(1)[..], b"RIFF"].concat());
+        let root = node(
+            &name("root"),
+            &[],
+            2,
+            &[&vlq(1)[..], &dls_node, &smf_node].concat(),
+        );
+        let raw = xmf_file(b"1.00", &root, &[]);
+
+        let xmf = Xmf::parse(&raw).unwrap();
+        assert_eq!(xmf.version, *b"1.00");
+        assert_eq!(xmf.file_type, None);
+        assert_eq!(xmf.root.name, Some(&b"root"[..]));
+        let children = match &xmf.root.content {
+            XmfContent::Folder(children) => children,
+            other => panic!("expected folder, found {:?}", other),
+        };
+        assert_eq!(children.len(), 2);
+        assert_eq!(
+            children[0].format,
+            Some(ResourceFormat {
+                format_type: 0,
+                id: 3
+            })
+        );
+        assert!(children[0].format.unwrap().is_dls());
+        assert_eq!(children[0].content, XmfContent::Resource(b"RIFF"));
+        assert_eq!(children[1].name, Some(&b"song"[..]));
+        assert!(children[1].is_smf());
+
+        let mut count = 0;
+        xmf.walk(|_| count += 1);
+        assert_eq!(count, 3);
+        assert_eq!(xmf.smf_data(), vec![&file[..]]);
+        assert_eq!(xmf.smfs().unwrap(), vec![Smf::parse(&file).unwrap()]);
+    }
+
+    #[test]
+    fn mobile_in_file_references() {
+        open! {file: "Levels.mid"};
+        //The resource is referenced by offset, and its format is detected by content
+        let leaf_len = 4 * 5 + 8;
+        let start = header_len(b"2.00");
+        let leaf = node(&[], &[], 0, &[vlq(2), vlq(start + leaf_len)].concat());
+        let raw = xmf_file(b"2.00", &leaf, &file);
+
+        let xmf = Xmf::parse(&raw).unwrap();
+        assert_eq!(xmf.file_type, Some((2, 1)));
+        assert_eq!(xmf.root.format, None);
+        assert!(xmf.root.is_smf());
+        assert_eq!(xmf.smfs().unwrap(), vec![Smf::parse(&file).unwrap()]);
+
+        //Packed content cannot be parsed
+        let leaf = node(&format(0), &[0], 0, &[&vlq(1)[..], &file].concat());
+        let raw = xmf_file(b"2.00", &leaf, &[]);
+        let xmf = Xmf::parse(&raw).unwrap();
+        assert!(xmf.root.packed);
+        assert!(xmf.smfs().is_err());
+    }
+
+    #[test]
+    fn bad_files() {
+        assert!(Xmf::parse(b"MThd\0\0\0\x06").is_err());
+        assert!(Xmf::parse(&xmf_file(b"1.00", &[], &[])).is_err());
+        //A folder that contains itself
+        let start = header_len(b"1.00");
+        let root = node(&[], &[], 1, &[vlq(2), vlq(start)].concat());
+        assert!(Xmf::parse(&xmf_file(b"1.00", &root, &[])).is_err());
+
+        //Pairs of folders that both reference the next pair, which would expand exponentially
+        let pair = |level: usize| start + 28 + 56 * level;
+        let folder = |level: usize| node(&[], &[], 2, &[vlq(2), vlq(pair(level + 1))].concat());
+        let leaf = node(&[], &[], 0, &[&vlq(1)[..], b"RIFF"].concat());
+        let mut tree = node(&[], &[], 2, &[vlq(2), vlq(pair(0))].concat());
+        for level in 0..40 {
+            let item = if level < 39 {
+                folder(level)
+            } else {
+                leaf.clone()
+            };
+            tree.extend([&item[..], &item].concat());
+        }
+        assert_eq!(tree.len(), pair(40) - start);
+        assert!(Xmf::parse(&xmf_file(b"1.00", &tree, &[])).is_err());
+    }
+}
+
+mod dump {
+    use super::*;
+    use crate::{
+        dump::{Dump, DumpKind},
+        Smf,
+    };
+
+    /// Strip any RIFF wrapper from a file.
+    fn smf_bytes(raw: &[u8]) -> &[u8] {
+        match raw.get(..4) {
+            Some(b"RIFF") => crate::riff::unwrap(raw).unwrap(),
+            _ => raw,
+        }
+    }
+
+    #[test]
+    fn text_roundtrip() {
+        for name in [
+            "Clementi.mid",
+            "Levels.mid",
+            "SysExTest.mid",
+            "Beethoven.rmi",
+            "CrabRave.mid",
+            "RiverFlowsInYou.mid",
+            "Sandstorm.mid",
+        ] {
+            let file = fs::read(format!("test-asset/{}", name)).unwrap();
+            let dump = Dump::read(&file).unwrap();
+            let text = dump.to_string();
+            let reread = Dump::from_text(&text).unwrap();
+            assert_eq!(reread, dump, "{}", name);
+            assert_eq!(reread.to_bytes().unwrap(), smf_bytes(&file), "{}", name);
+
+            let smf = Smf::parse(&file).unwrap();
+            let dump = Dump::from_smf(&smf).unwrap();
+            let reread = Dump::from_text(&dump.to_string()).unwrap();
+            assert_eq!(Smf::parse(&reread.to_bytes().unwrap()).unwrap(), smf);
+        }
+    }
+
+    /// A single-track file exercising the corners of the format.
+    fn corner_cases() -> Vec<u8> {
+        let track: &[u8] = &[
+            0x00, 0x90, 0x3C, 0x64, //Note on
+            0x00, 0x90, 0x3C, 0x00, //Note off, without using running status
+            0x10, 0x3E, 0x64, //Note on, using running status
+            0x00, 0xF7, 0x02, 0xF3, 0x01, //Escape
+            0x00, 0xF0, 0x03, 0x7E, 0x7F, 0xF7, //SysEx
+            0x00, 0xFF, 0x60, 0x02, 0xAB, 0xCD, //Unknown meta
+            0x00, 0xFF, 0x03, 0x06, b'a', b',', b'"', b'b', b'\n', b'\\', //Track name
+            0x00, 0xFF, 0x01, 0x02, 0xFF, 0xFE, //Non-UTF8 text
+            0x00, 0xFF, 0x59, 0x02, 0xFD, 0x01, //Key signature
+            0x80, 0x00, 0xE0, 0x00, 0x40, //Non-canonical delta time
+            0x83, 0x60, 0xFF, 0x2F, 0x00, //End of track
+        ];
+        let mut file = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk".to_vec();
+        file.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        file.extend_from_slice(track);
+        file
+    }
+
+    #[test]
+    fn corner_case_roundtrip() {
+        let file = corner_cases();
+        let dump = Dump::read(&file).unwrap();
+        let kinds = dump.tracks[0]
+            .iter()
+            .map(|ev| ev.kind.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds[1],
+            DumpKind::NoteOn {
+                channel: 0,
+                key: 0x3C,
+                vel: 0,
+                running: false
+            }
+        );
+        assert_eq!(
+            kinds[2],
+            DumpKind::NoteOn {
+                channel: 0,
+                key: 0x3E,
+                vel: 0x64,
+                running: true
+            }
+        );
+        assert_eq!(
+            kinds[7],
+            DumpKind::Meta {
+                meta_type: 0x01,
+                data: vec![0xFF, 0xFE]
+            }
+        );
+        assert_eq!(
+            kinds[9],
+            DumpKind::Raw {
+                data: vec![0x80, 0x00, 0xE0, 0x00, 0x40]
+            }
+        );
+        assert_eq!(dump.tracks[0][10].tick, 0x10 + 0x1E0);
+
+        let text = dump.to_string();
+        assert!(text.contains("0, 16, Escape, f301\n"));
+        assert!(text.contains("0, 16, Text, 3, \"a,\\\"b\\n\\\\\"\n"));
+        assert!(text.contains("0, 16, KeySignature, -3, minor\n"));
+        let reread = Dump::from_text(&text).unwrap();
+        assert_eq!(reread, dump);
+        assert_eq!(reread.to_bytes().unwrap(), file);
+    }
+
+    #[test]
+    fn text_errors() {
+        let text = Dump::read(&corner_cases()).unwrap().to_string();
+        //Running status that does not match the previous event
+        let bad = text.replacen(
+            "NoteOn, 0, 62, 100, running",
+            "NoteOn, 1, 62, 100, running",
+            1,
+        );
+        assert!(Dump::from_text(&bad).unwrap().to_bytes().is_err());
+        //Decreasing ticks
+        let bad = text.replacen("0, 496, EndOfTrack", "0, 0, EndOfTrack", 1);
+        assert!(Dump::from_text(&bad).unwrap().to_bytes().is_err());
+        //Syntax errors are located at their line
+        let bad = text.replacen("Escape, f301", "Escape, f3g1", 1);
+        let err = Dump::from_text(&bad).unwrap_err();
+        assert_eq!(err.offset(), bad.find("0, 16, Escape"));
+        assert!(Dump::from_text("Track, 0").is_err());
+        assert!(Dump::from_text("Header, 0, 1, 96\n0, 0, EndOfTrack").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_roundtrip() {
+        let file = corner_cases();
+        let dump = Dump::read(&file).unwrap();
+        let json = serde_json::to_string(&dump).unwrap();
+        assert!(json.contains(
+            r#"{"tick":0,"type":"NoteOn","channel":0,"key":60,"vel":100,"running":false}"#
+        ));
+        let reread: Dump = serde_json::from_str(&json).unwrap();
+        assert_eq!(reread, dump);
+        assert_eq!(reread.to_bytes().unwrap(), file);
+
+        open! {file: "Clementi.mid"};
+        let dump = Dump::read(&file).unwrap();
+        let reread: Dump = serde_json::from_str(&serde_json::to_string(&dump).unwrap()).unwrap();
+        assert_eq!(reread.to_bytes().unwrap(), file);
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde {
+    use super::*;
+    use crate::{
+        live::{LiveEvent, OwnedLiveEvent, SystemCommon},
+        num::{u14, u15, u28, u4, u7},
+        Format, Fps, Header, MetaMessage, MidiMessage, OwnedSmf, OwnedTrackEvent, PitchBend,
+        SmpteTime, Timing, TrackEventKind,
+    };
+
+    #[test]
+    fn json_roundtrip() {
+        let msg = MidiMessage::NoteOn {
+            key: u7::new(60),
+            vel: u7::new(100),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"NoteOn":{"key":60,"vel":100}}"#);
+        assert_eq!(serde_json::from_str::<MidiMessage>(&json).unwrap(), msg);
+
+        let header = Header::new(Format::Parallel, Timing::Timecode(Fps::Fps25, 40));
+        let json = serde_json::to_string(&header).unwrap();
+        assert_eq!(serde_json::from_str::<Header>(&json).unwrap(), header);
+
+        let bend = PitchBend(u14::new(0x2000));
+        let json = serde_json::to_string(&bend).unwrap();
+        assert_eq!(json, "8192");
+        assert_eq!(serde_json::from_str::<PitchBend>(&json).unwrap(), bend);
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(serde_json::from_str::<u7>("127").unwrap(), u7::new(127));
+        assert!(serde_json::from_str::<u7>("128").is_err());
+        assert!(serde_json::from_str::<u15>("40000").is_err());
+        assert!(serde_json::from_str::<u28>("268435456").is_err());
+        assert!(
+            serde_json::from_str::<MidiMessage>(r#"{"NoteOn":{"key":60,"vel":200}}"#).is_err()
+        );
+
+        let time = SmpteTime::new(23, 59, 59, 24, 99, Fps::Fps25).unwrap();
+        let json = serde_json::to_string(&time).unwrap();
+        assert_eq!(serde_json::from_str::<SmpteTime>(&json).unwrap(), time);
+        let bad = json.replace("\"frame\":24", "\"frame\":25");
+        assert!(serde_json::from_str::<SmpteTime>(&bad).is_err());
+    }
+
+    #[test]
+    fn zero_copy() {
+        let event = TrackEvent {
+            delta: u28::new(96),
+            kind: TrackEventKind::Meta(MetaMessage::TrackName(b"Piano")),
+        };
+        let raw = bincode::serialize(&event).unwrap();
+        let reread: TrackEvent = bincode::deserialize(&raw).unwrap();
+        assert_eq!(reread, event);
+        match reread.kind {
+            TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+                assert!(raw.as_ptr_range().contains(&name.as_ptr()))
+            }
+            _ => panic!("wrong event kind"),
+        }
+
+        let sysex = [0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00, 0xF7];
+        let event = LiveEvent::Common(SystemCommon::SysEx(u7::slice_from_int(&sysex)));
+        let raw = bincode::serialize(&event).unwrap();
+        assert_eq!(bincode::deserialize::<LiveEvent>(&raw).unwrap(), event);
+        //Data bytes with the top bit set are rejected
+        let mut bad = raw.clone();
+        *bad.iter_mut().rev().find(|&&mut b| b == 0x7E).unwrap() = 0xFE;
+        assert!(bincode::deserialize::<LiveEvent>(&bad).is_err());
+
+        let event = LiveEvent::Midi {
+            channel: u4::new(9),
+            message: MidiMessage::Controller {
+                controller: u7::new(7),
+                value: u7::new(127),
+            },
+        };
+        let raw = bincode::serialize(&event).unwrap();
+        assert_eq!(bincode::deserialize::<LiveEvent>(&raw).unwrap(), event);
+    }
+
+    #[test]
+    fn owned_payloads() {
+        //Borrowed payloads cannot be read back from JSON, but owned ones can
+        let event = LiveEvent::Common(SystemCommon::SysEx(u7::slice_from_int(&[1, 2, 3])));
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, r#"{"Common":{"SysEx":[1,2,3]}}"#);
+        assert!(serde_json::from_str::<LiveEvent>(&json).is_err());
+        let owned = serde_json::from_str::<OwnedLiveEvent>(&json).unwrap();
+        assert_eq!(owned.as_event(), event);
+        assert_eq!(serde_json::to_string(&owned).unwrap(), json);
+        let bad = r#"{"Common":{"SysEx":[1,128,3]}}"#;
+        assert!(serde_json::from_str::<OwnedLiveEvent>(bad).is_err());
+
+        let event = TrackEvent {
+            delta: u28::new(96),
+            kind: TrackEventKind::Meta(MetaMessage::Text(b"Allegro")),
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        let owned = serde_json::from_str::<OwnedTrackEvent>(&json).unwrap();
+        assert_eq!(owned.as_event(), event);
+
+        //Owned events are stored like borrowed ones, so binary formats are interchangeable
+        let raw = bincode::serialize(&event).unwrap();
+        assert_eq!(bincode::serialize(&owned).unwrap(), raw);
+        let reread: OwnedTrackEvent = bincode::deserialize(&raw).unwrap();
+        assert_eq!(reread, owned);
+
+        open! {file: "SysExTest.mid"};
+        let smf = OwnedSmf::parse(&file).unwrap();
+        let json = serde_json::to_string(&smf).unwrap();
+        assert_eq!(serde_json::from_str::<OwnedSmf>(&json).unwrap(), smf);
+    }
+}
+
+#[cfg(feature = "embedded")]
+mod serial_midi {
+    use super::{live, note_on};
+    use crate::{
+        embedded::{SendError, SerialMidi},
+        live::{LiveEvent, SystemCommon, SystemRealtime},
+        num::{u4, u7},
+        MidiMessage,
+    };
+    use std::collections::VecDeque;
+
+    /// A fake UART that accepts a limited amount of bytes per poll.
+    #[derive(Default)]
+    struct FakeSerial {
+        rx: VecDeque<u8>,
+        tx: Vec<u8>,
+        tx_room: usize,
+    }
+    impl embedded_hal::serial::Read<u8> for FakeSerial {
+        type Error = ();
+        fn read(&mut self) -> nb::Result<u8, ()> {
+            self.rx.pop_front().ok_or(nb::Error::WouldBlock)
+        }
+    }
+    impl embedded_hal::serial::Write<u8> for FakeSerial {
+        type Error = ();
+        fn write(&mut self, byte: u8) -> nb::Result<(), ()> {
+            if self.tx_room == 0 {
+                return Err(nb::Error::WouldBlock);
+            }
+            self.tx_room -= 1;
+            self.tx.push(byte);
+            Ok(())
+        }
+        fn flush(&mut self) -> nb::Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transmit() {
+        let mut midi = SerialMidi::<_, 8>::new(FakeSerial::default());
+        midi.send(&live(0, note_on(60, 64))).unwrap();
+        midi.send(&live(0, note_on(62, 64))).unwrap();
+        assert_eq!(midi.pending(), 5);
+        //Does not fit, and is not queued partially
+        assert_eq!(
+            midi.send(&LiveEvent::Common(SystemCommon::SongPosition(0.into()))),
+            Ok(())
+        );
+        assert_eq!(
+            midi.send(&live(0, note_on(64, 64))),
+            Err(SendError::QueueFull)
+        );
+        assert_eq!(midi.pending(), 8);
+
+        //Realtime bytes jump ahead, even in the middle of a message
+        midi.serial_mut().tx_room = 2;
+        assert!(midi.poll_write().is_err());
+        midi.send(&LiveEvent::Realtime(SystemRealtime::TimingClock))
+            .unwrap();
+        midi.serial_mut().tx_room = 100;
+        midi.poll_write().unwrap();
+        assert_eq!(
+            midi.serial().tx,
+            [0x90, 60, 0xF8, 64, 62, 64, 0xF2, 0x00, 0x00]
+        );
+
+        //Running status is reset by system common messages, and can be disabled
+        midi.send(&live(0, note_on(64, 64))).unwrap();
+        midi.send(&live(0, note_on(65, 64))).unwrap();
+        midi.set_running_status(false);
+        midi.send(&live(0, note_on(66, 64))).unwrap();
+        midi.poll_write().unwrap();
+        assert_eq!(midi.release().tx[9..], [0x90, 64, 64, 65, 64, 0x90, 66, 64]);
+    }
+
+    #[test]
+    fn receive() {
+        let mut midi = SerialMidi::<_>::new(FakeSerial::default());
+        midi.serial_mut()
+            .rx
+            .extend([0x90, 60, 0xF8, 64, 0xF0, 0x01, 0x02, 0xF7, 0x80]);
+        let mut events = Vec::new();
+        midi.poll_read(|ev| events.push(ev.to_static())).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], LiveEvent::Realtime(SystemRealtime::TimingClock));
+        assert_eq!(events[1], live(0, note_on(60, 64)));
+        assert!(matches!(
+            events[2],
+            LiveEvent::Common(SystemCommon::SysEx(_))
+        ));
+        midi.serial_mut().rx.extend([60, 0]);
+        midi.poll_read(|ev| events.push(ev.to_static())).unwrap();
+        assert_eq!(
+            events[3],
+            LiveEvent::Midi {
+                channel: u4::new(0),
+                message: MidiMessage::NoteOff {
+                    key: u7::new(60),
+                    vel: u7::new(0),
+                },
+            }
+        );
+    }
+}
+
+mod router {
+    use crate::{
+        live::{LiveEvent, SystemCommon},
+        num::{u4, u7},
+        router::{MessageFilter, MessageKind, Output, Port, Route, Router},
+        stream::MidiStream,
+    };
+
+    /// Collect the router output as USB packets and DIN bytes.
+    #[derive(Default)]
+    struct Collected {
+        usb: Vec<[u8; 4]>,
+        din: Vec<(u8, Vec<u8>)>,
+    }
+    impl Collected {
+        fn push(&mut self, out: Output) {
+            match out {
+                Output::Usb(packet) => self.usb.push(packet),
+                Output::Din { port, bytes } => match self.din.last_mut() {
+                    Some((last, data)) if *last == port => data.extend_from_slice(bytes),
+                    _ => self.din.push((port, bytes.to_vec())),
+                },
+            }
+        }
+    }
+
+    fn usb(cable: u8) -> Port {
+        Port::Usb(u4::new(cable))
+    }
+
+    #[test]
+    fn usb_to_din() {
+        let mut router = Router::<4, 2>::new();
+        router
+            .add_route(
+                Route::new(usb(0), Port::Din(0))
+                    .channels(1 << 0 | 1 << 1)
+                    .filter(MessageFilter::ALL.deny(MessageKind::ActiveSensing)),
+            )
+            .unwrap();
+        router.add_route(Route::new(usb(1), Port::Din(1))).unwrap();
+        let mut out = Collected::default();
+        for packet in [
+            [0x09, 0x90, 60, 100],
+            [0x0F, 0xFE, 0, 0],
+            [0x09, 0x90, 62, 100],
+            [0x09, 0x92, 64, 100],
+            [0x08, 0x81, 60, 0],
+            [0x04, 0xF0, 0x7E, 0x7F],
+            [0x19, 0x90, 60, 100],
+            [0x06, 0x01, 0xF7, 0],
+            [0x09, 0x90, 60, 0],
+            [0x00, 0x00, 0x00, 0x00],
+        ] {
+            router.feed_usb(packet, |o| out.push(o));
+        }
+        assert!(out.usb.is_empty());
+        assert_eq!(
+            out.din,
+            [
+                (
+                    0,
+                    vec![0x90, 60, 100, 62, 100, 0x81, 60, 0, 0xF0, 0x7E, 0x7F]
+                ),
+                (1, vec![0x90, 60, 100]),
+                (0, vec![0x01, 0xF7, 0x90, 60, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn din_to_usb() {
+        let mut router = Router::<4, 1>::new();
+        router.add_route(Route::new(Port::Din(0), usb(2))).unwrap();
+        router
+            .add_route(
+                Route::new(Port::Din(0), usb(3))
+                    .filter(MessageFilter::NONE.allow(MessageKind::Clock)),
+            )
+            .unwrap();
+        let mut stream = MidiStream::new();
+        let mut out = Collected::default();
+        router.feed_din(
+            0,
+            &mut stream,
+            &[0xC5, 10, 0xF8, 0xF0, 1, 2, 3, 4, 0xF7, 0xF6, 0xF0, 5, 0xF7],
+            |o| out.push(o),
+        );
+        assert!(out.din.is_empty());
+        assert_eq!(
+            out.usb,
+            [
+                [0x2C, 0xC5, 10, 0],
+                [0x2F, 0xF8, 0, 0],
+                [0x3F, 0xF8, 0, 0],
+                [0x24, 0xF0, 1, 2],
+                [0x27, 3, 4, 0xF7],
+                [0x25, 0xF6, 0, 0],
+                [0x27, 0xF0, 5, 0xF7],
+            ][..]
+        );
+    }
+
+    #[test]
+    fn sysex_packetization() {
+        let mut router = Router::<1, 0>::new();
+        router.add_route(Route::new(Port::Din(0), usb(0))).unwrap();
+        for (len, expected) in [
+            (0, vec![[0x06, 0xF0, 0xF7, 0]]),
+            (1, vec![[0x07, 0xF0, 1, 0xF7]]),
+            (2, vec![[0x04, 0xF0, 1, 2], [0x05, 0xF7, 0, 0]]),
+            (3, vec![[0x04, 0xF0, 1, 2], [0x06, 3, 0xF7, 0]]),
+        ] {
+            let data = [1, 2, 3].map(u7::new);
+            let event = LiveEvent::Common(SystemCommon::SysEx(&data[..len]));
+            let mut out = Collected::default();
+            router.route(Port::Din(0), &event, |o| out.push(o));
+            assert_eq!(out.usb, expected);
+        }
+    }
+}
+
+mod stream_writer {
+    use super::{live, note_off, note_on};
+    use crate::{
+        live::{LiveEvent, SystemCommon, SystemRealtime},
+        num::u7,
+        stream::{MidiStream, MidiStreamWriter},
+    };
+
+    fn write_all(writer: &mut MidiStreamWriter, events: &[LiveEvent]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for event in events {
+            writer.write(event, &mut bytes).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn running_status() {
+        let events = [
+            live(0, note_on(60, 100)),
+            LiveEvent::Realtime(SystemRealtime::TimingClock),
+            live(0, note_on(62, 100)),
+            live(0, note_off(60, 64)),
+            live(1, note_on(60, 100)),
+            LiveEvent::Common(SystemCommon::TuneRequest),
+            live(1, note_on(62, 100)),
+        ];
+        let mut writer = MidiStreamWriter::new();
+        assert_eq!(
+            write_all(&mut writer, &events),
+            [0x90, 60, 100, 0xF8, 62, 100, 0x80, 60, 64, 0x91, 60, 100, 0xF6, 0x91, 62, 100]
+        );
+
+        let mut writer = MidiStreamWriter::new();
+        writer.set_note_off_as_note_on(true);
+        assert_eq!(
+            write_all(&mut writer, &events[..4]),
+            [0x90, 60, 100, 0xF8, 62, 100, 60, 0]
+        );
+
+        writer.set_running_status(false);
+        assert_eq!(
+            write_all(&mut writer, &events[2..4]),
+            [0x90, 62, 100, 0x90, 60, 0]
+        );
+    }
+
+    #[test]
+    fn refresh_period() {
+        let mut writer = MidiStreamWriter::new();
+        writer.set_refresh_period(Some(2));
+        let events = [
+            live(0, note_on(1, 1)),
+            live(0, note_on(2, 2)),
+            live(0, note_on(3, 3)),
+        ];
+        assert_eq!(
+            write_all(&mut writer, &events),
+            [0x90, 1, 1, 2, 2, 0x90, 3, 3]
+        );
+        writer.refresh_running_status();
+        assert_eq!(write_all(&mut writer, &events[..1]), [0x90, 1, 1]);
+    }
+
+    #[test]
+    fn interleave_realtime() {
+        let sysex = LiveEvent::Common(SystemCommon::SysEx(u7::slice_from_int(&[1, 2, 3])));
+        let mut writer = MidiStreamWriter::new();
+        let mut bytes = Vec::new();
+        //A clock becomes due on every third poll
+        let mut byte = 0;
+        writer
+            .write_interleaved(&sysex, &mut bytes, || {
+                byte += 1;
+                if byte % 3 == 0 {
+                    Some(SystemRealtime::TimingClock)
+                } else {
+                    None
+                }
+            })
+            .unwrap();
+        assert_eq!(bytes, [0xF0, 1, 0xF8, 2, 3, 0xF8, 0xF7]);
+
+        //The stream parser sees the clocks before the message they interrupted
+        let mut events = Vec::new();
+        let mut stream = MidiStream::new();
+        stream.feed(&bytes, |event| events.push(event.to_static()));
+        stream.flush(|event| events.push(event.to_static()));
+        let clock = LiveEvent::Realtime(SystemRealtime::TimingClock);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[..2], [clock, clock]);
+        assert!(matches!(
+            events[2],
+            LiveEvent::Common(SystemCommon::SysEx(_))
+        ));
+    }
+}
+
+mod sensing {
+    use crate::{
+        live::{LiveEvent, SystemRealtime},
+        num::{u4, u7},
+        sensing::{Action, Supervisor},
+        MidiMessage,
+    };
+
+    fn tick<const I: usize, const O: usize>(
+        supervisor: &mut Supervisor<I, O>,
+        elapsed: u32,
+    ) -> Vec<Action> {
+        let mut actions = Vec::new();
+        supervisor.tick(elapsed, |action| actions.push(action));
+        actions
+    }
+
+    fn controller(channel: u8, controller: u8) -> LiveEvent<'static> {
+        LiveEvent::Midi {
+            channel: u4::new(channel),
+            message: MidiMessage::Controller {
+                controller: u7::new(controller),
+                value: u7::new(0),
+            },
+        }
+    }
+
+    #[test]
+    fn send_when_idle() {
+        let mut supervisor = Supervisor::<1, 2>::with_timing(300, 330);
+        assert_eq!(tick(&mut supervisor, 200), []);
+        supervisor.sent(1);
+        assert_eq!(
+            tick(&mut supervisor, 100),
+            [Action::SendActiveSensing { output: 0 }]
+        );
+        assert_eq!(
+            tick(&mut supervisor, 200),
+            [Action::SendActiveSensing { output: 1 }]
+        );
+        assert_eq!(
+            tick(&mut supervisor, 100),
+            [Action::SendActiveSensing { output: 0 }]
+        );
+    }
+
+    #[test]
+    fn connection_lost() {
+        let mut supervisor = Supervisor::<2, 0>::new();
+        let note_on = |channel: u8| LiveEvent::Midi {
+            channel: u4::new(channel),
+            message: MidiMessage::NoteOn {
+                key: u7::new(60),
+                vel: u7::new(100),
+            },
+        };
+        let sensing = LiveEvent::Realtime(SystemRealtime::ActiveSensing);
+
+        //Inputs that never sent active sensing are not supervised
+        supervisor.received(0, &note_on(3));
+        supervisor.received(1, &note_on(0));
+        assert_eq!(tick(&mut supervisor, 1000), []);
+        assert!(!supervisor.is_supervised(0));
+
+        supervisor.received(0, &sensing);
+        supervisor.received(0, &note_on(9));
+        assert!(supervisor.is_supervised(0));
+        assert_eq!(tick(&mut supervisor, 300), []);
+        supervisor.received(0, &sensing);
+        assert_eq!(tick(&mut supervisor, 300), []);
+        assert_eq!(
+            tick(&mut supervisor, 100),
+            [
+                Action::ConnectionLost { input: 0 },
+                Action::Silence {
+                    input: 0,
+                    event: controller(3, 123)
+                },
+                Action::Silence {
+                    input: 0,
+                    event: controller(3, 121)
+                },
+                Action::Silence {
+                    input: 0,
+                    event: controller(9, 123)
+                },
+                Action::Silence {
+                    input: 0,
+                    event: controller(9, 121)
+                },
+            ]
+        );
+        assert!(!supervisor.is_supervised(0));
+        //The connection is only lost once
+        assert_eq!(tick(&mut supervisor, 1000), []);
+
+        //Supervision resumes with the next active sensing message
+        supervisor.received(0, &sensing);
+        assert_eq!(
+            tick(&mut supervisor, 400),
+            [Action::ConnectionLost { input: 0 }]
+        );
+    }
+}
+
+mod usb_packet {
+    use crate::{
+        live::{LiveEvent, SystemCommon},
+        num::{u4, u7},
+        packet::{packetize, UsbMidiPacket, CIN},
+        MidiMessage,
+    };
+
+    #[test]
+    fn parse_packetized() {
+        let event = LiveEvent::Midi {
+            channel: 2.into(),
+            message: MidiMessage::NoteOn {
+                key: 60.into(),
+                vel: 100.into(),
+            },
+        };
+        let packets: Vec<_> = packetize(u4::new(3), &event).collect();
+        assert_eq!(packets, [[0x39, 0x92, 60, 100]]);
+        let packet = UsbMidiPacket::parse(&packets[0]).unwrap();
+        assert_eq!(packet.cable_number, u4::new(3));
+        assert_eq!(packet.code_index_number, CIN::NoteOn);
+        assert_eq!(packet.event, event);
+    }
+
+    #[test]
+    fn sysex() {
+        let data = u7::slice_from_int(&[1, 2, 3, 4]);
+        let event = LiveEvent::Common(SystemCommon::SysEx(data));
+        let packets: Vec<_> = packetize(u4::new(0), &event).collect();
+        assert_eq!(packets, [[0x04, 0xF0, 1, 2], [0x07, 3, 4, 0xF7]]);
+        //Fragments of a longer message cannot be parsed on their own
+        assert!(UsbMidiPacket::parse(&packets[0]).is_err());
+        assert!(UsbMidiPacket::parse(&packets[1]).is_err());
+
+        let event = LiveEvent::Common(SystemCommon::SysEx(&data[..1]));
+        let packets: Vec<_> = packetize(u4::new(0), &event).collect();
+        assert_eq!(packets, [[0x07, 0xF0, 1, 0xF7]]);
+        assert_eq!(UsbMidiPacket::parse(&packets[0]).unwrap().event, event);
+    }
+
+    #[test]
+    fn reserved() {
+        assert!(UsbMidiPacket::parse(&[0x00, 0x90, 60, 100]).is_err());
+        assert!(UsbMidiPacket::parse(&[0x01, 0x90, 60, 100]).is_err());
+        assert!(UsbMidiPacket::parse(&[0x09, 0x90, 60]).is_err());
+    }
+}
+
+#[cfg(all(feature = "embedded", feature = "std"))]
+mod usb_sim {
+    use crate::{
+        class::{Element, ElementCap, ElementCaps, MidiClass, MidiQueues, SendError},
+        live::{LiveEvent, SystemCommon, SystemRealtime},
+        num::{u4, u7},
+        packet::{packetize, UsbMidiPacket},
+        sim::{self, SimBus, SimError},
+        MidiMessage,
+    };
+    use usb_device::{bus::UsbBusAllocator, prelude::*};
+
+    /// Find the bulk endpoint addresses in a configuration descriptor, OUT first.
+    fn bulk_endpoints(config: &[u8]) -> (u8, u8) {
+        let mut eps = sim::descriptors(config)
+            .filter(|desc| desc[1] == sim::DESCRIPTOR_ENDPOINT && desc[3] == 0x02)
+            .map(|desc| desc[2]);
+        let out_ep = eps.next().unwrap();
+        let in_ep = eps.next().unwrap();
+        assert_eq!(out_ep & 0x80, 0);
+        assert_eq!(in_ep & 0x80, 0x80);
+        (out_ep, in_ep)
+    }
+
+    #[test]
+    fn enumerate() {
+        let bus = SimBus::new();
+        let host = bus.host();
+        let alloc = UsbBusAllocator::new(bus);
+        let mut midi = MidiClass::new(&alloc, 64);
+        let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x5e4)).build();
+        let mut poll = || {
+            device.poll(&mut [&mut midi]);
+        };
+
+        let config = host.enumerate(&mut poll).unwrap();
+        assert_eq!(host.address(), 1);
+        assert_eq!(
+            usize::from(u16::from_le_bytes([config[2], config[3]])),
+            config.len()
+        );
+
+        let descs: Vec<&[u8]> = sim::descriptors(&config).collect();
+        assert_eq!(descs.iter().map(|d| d.len()).sum::<usize>(), config.len());
+        let interfaces: Vec<_> = descs
+            .iter()
+            .filter(|d| d[1] == sim::DESCRIPTOR_INTERFACE)
+            .map(|d| (d[2], d[5], d[6]))
+            .collect();
+        assert_eq!(interfaces, [(0, 1, 1), (1, 1, 3)]);
+        //The Audio Control header points at the MIDI Streaming interface
+        let ac_header = descs.iter().find(|d| d[1] == 0x24).unwrap();
+        assert_eq!(ac_header[8], 1);
+        //The MIDI Streaming header covers everything after the streaming interface
+        let ms_pos = descs
+            .iter()
+            .position(|d| d[1] == 0x24 && d[2] == 0x01 && d.len() == 7);
+        let ms_pos = ms_pos.unwrap();
+        let ms_len: usize = descs[ms_pos..].iter().map(|d| d.len()).sum();
+        assert_eq!(
+            usize::from(u16::from_le_bytes([descs[ms_pos][5], descs[ms_pos][6]])),
+            ms_len
+        );
+        let jacks = descs
+            .iter()
+            .filter(|d| d[1] == 0x24 && (d[2] == 0x02 || d[2] == 0x03));
+        assert_eq!(jacks.count(), 4);
+        bulk_endpoints(&config);
+
+        host.set_interface(&mut poll, 1, 0).unwrap();
+        assert_eq!(host.set_interface(&mut poll, 1, 1), Err(SimError::Stalled));
+        //The device recovers from the stall on the next request
+        host.set_configuration(&mut poll, 1).unwrap();
+    }
+
+    #[test]
+    fn named_cables() {
+        let bus = SimBus::new();
+        let host = bus.host();
+        let alloc = UsbBusAllocator::new(bus);
+        let names = ["Synth A", "Synth B", "Synth C", "Synth D"];
+        let mut midi = names
+            .iter()
+            .fold(
+                MidiClass::builder(&alloc, 64).streaming_interface_name("Synth"),
+                |builder, name| builder.cable(name),
+            )
+            .build();
+        assert_eq!(midi.cables(), 4);
+        let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x5e4)).build();
+        let mut poll = || {
+            device.poll(&mut [&mut midi]);
+        };
+
+        let config = host.enumerate(&mut poll).unwrap();
+        let descs: Vec<&[u8]> = sim::descriptors(&config).collect();
+        let ms_pos = descs
+            .iter()
+            .position(|d| d[1] == 0x24 && d[2] == 0x01 && d.len() == 7)
+            .unwrap();
+        let ms_len: usize = descs[ms_pos..].iter().map(|d| d.len()).sum();
+        assert_eq!(
+            usize::from(u16::from_le_bytes([descs[ms_pos][5], descs[ms_pos][6]])),
+            ms_len
+        );
+        //The streaming interface is named, the control interface is not
+        let interfaces: Vec<_> = descs
+            .iter()
+            .filter(|d| d[1] == sim::DESCRIPTOR_INTERFACE)
+            .map(|d| d[8])
+            .collect();
+        assert_eq!(interfaces[0], 0);
+        assert_eq!(
+            host.get_string(&mut poll, interfaces[1], 0x0409).unwrap(),
+            "Synth"
+        );
+        //Every jack of a cable carries the name of the cable
+        let jacks: Vec<&[u8]> = descs
+            .iter()
+            .copied()
+            .filter(|d| d[1] == 0x24 && (d[2] == 0x02 || d[2] == 0x03))
+            .collect();
+        assert_eq!(jacks.len(), 16);
+        for (cable, jacks) in jacks.chunks(4).enumerate() {
+            let ids: Vec<u8> = jacks.iter().map(|jack| jack[4]).collect();
+            let first = 4 * cable as u8 + 1;
+            assert_eq!(ids, [first, first + 1, first + 2, first + 3]);
+            for jack in jacks {
+                let string = *jack.last().unwrap();
+                assert_eq!(
+                    host.get_string(&mut poll, string, 0x0409).unwrap(),
+                    names[cable]
+                );
+            }
+        }
+        //Both endpoints are associated with the embedded jack of every cable
+        let ep_jacks: Vec<&[u8]> = descs.iter().copied().filter(|d| d[1] == 0x25).collect();
+        assert_eq!(ep_jacks[0], [8, 0x25, 0x01, 4, 1, 5, 9, 13]);
+        assert_eq!(ep_jacks[1], [8, 0x25, 0x01, 4, 3, 7, 11, 15]);
+        //Names don't depend on the requested language
+        let string = *jacks[0].last().unwrap();
+        assert_eq!(
+            host.get_string(&mut poll, string, 0x0407).unwrap(),
+            names[0]
+        );
+    }
+
+    #[test]
+    fn elements() {
+        let bus = SimBus::new();
+        let host = bus.host();
+        let alloc = UsbBusAllocator::new(bus);
+        let caps = ElementCaps::NONE
+            .with(ElementCap::MidiClock)
+            .with(ElementCap::Gm1)
+            .with(ElementCap::Gs);
+        assert!(caps.contains(ElementCap::Gs));
+        assert!(!caps.contains(ElementCap::Xg));
+        let mut midi = MidiClass::builder(&alloc, 64)
+            .cable("Synth A")
+            .cable("Synth B")
+            .element(
+                Element::new(caps)
+                    .input(u4::new(0))
+                    .input(u4::new(1))
+                    .output(u4::new(1))
+                    .name("GS Synth"),
+            )
+            .build();
+        let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x5e4)).build();
+        let mut poll = || {
+            device.poll(&mut [&mut midi]);
+        };
+
+        let config = host.enumerate(&mut poll).unwrap();
+        let descs: Vec<&[u8]> = sim::descriptors(&config).collect();
+        let ms_pos = descs
+            .iter()
+            .position(|d| d[1] == 0x24 && d[2] == 0x01 && d.len() == 7)
+            .unwrap();
+        let ms_len: usize = descs[ms_pos..].iter().map(|d| d.len()).sum();
+        assert_eq!(
+            usize::from(u16::from_le_bytes([descs[ms_pos][5], descs[ms_pos][6]])),
+            ms_len
+        );
+        //The element comes after the jacks, fed by the embedded IN jack of both cables
+        let element = descs.iter().find(|d| d[1] == 0x24 && d[2] == 0x04).unwrap();
+        let string = element[15];
+        assert_eq!(
+            element,
+            &[16, 0x24, 0x04, 9, 2, 1, 1, 5, 1, 1, 0, 0, 2, 0x52, 0x00, string]
+        );
+        assert_eq!(
+            host.get_string(&mut poll, string, 0x0409).unwrap(),
+            "GS Synth"
+        );
+        //Its output pin feeds the embedded OUT jack of the second cable
+        let out_jacks: Vec<&[u8]> = descs
+            .iter()
+            .copied()
+            .filter(|d| d[1] == 0x24 && d[2] == 0x03 && d[3] == 0x01)
+            .collect();
+        assert_eq!(out_jacks[0][..8], [9, 0x24, 0x03, 0x01, 3, 1, 2, 1]);
+        assert_eq!(out_jacks[1][..9], [11, 0x24, 0x03, 0x01, 7, 2, 6, 1, 9]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn element_missing_cable() {
+        let alloc = UsbBusAllocator::new(SimBus::new());
+        MidiClass::builder(&alloc, 64)
+            .cable("Synth")
+            .element(Element::new(ElementCaps::NONE.with(ElementCap::Gm2)).input(u4::new(1)))
+            .build();
+    }
+
+    #[test]
+    fn exchange_packets() {
+        let bus = SimBus::new();
+        let host = bus.host();
+        let alloc = UsbBusAllocator::new(bus);
+        let mut midi = MidiClass::new(&alloc, 64);
+        let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x5e4)).build();
+        let config = host
+            .enumerate(|| {
+                device.poll(&mut [&mut midi]);
+            })
+            .unwrap();
+        let (out_ep, in_ep) = bulk_endpoints(&config);
+
+        //Host to device
+        let note_on = LiveEvent::Midi {
+            channel: 0.into(),
+            message: MidiMessage::NoteOn {
+                key: 64.into(),
+                vel: 90.into(),
+            },
+        };
+        let packets: Vec<u8> = packetize(u4::new(1), &note_on).flatten().collect();
+        host.bulk_out(out_ep, &packets).unwrap();
+        assert!(!host.out_ready(out_ep));
+        assert_eq!(host.bulk_out(out_ep, &packets), Err(SimError::NotReady));
+        device.poll(&mut [&mut midi]);
+        let mut buf = [0; 64];
+        let len = midi.read_packet(&mut buf).unwrap();
+        let packet = UsbMidiPacket::parse(&buf[..len]).unwrap();
+        assert_eq!(packet.cable_number, u4::new(1));
+        assert_eq!(packet.event, note_on);
+        assert!(host.out_ready(out_ep));
+        assert!(midi.read_packet(&mut buf).is_err());
+
+        //Device to host
+        assert_eq!(host.bulk_in(in_ep), Err(SimError::NotReady));
+        assert_eq!(midi.write_packet(&[0x0F, 0xF8, 0, 0]).unwrap(), 4);
+        assert!(midi.write_packet(&[0x0F, 0xFA, 0, 0]).is_err());
+        assert!(host.in_ready(in_ep));
+        assert_eq!(host.bulk_in(in_ep).unwrap(), [0x0F, 0xF8, 0, 0]);
+        device.poll(&mut [&mut midi]);
+        assert_eq!(midi.write_packet(&[0x0F, 0xFA, 0, 0]).unwrap(), 4);
+        assert_eq!(host.bulk_in(in_ep).unwrap(), [0x0F, 0xFA, 0, 0]);
+    }
+
+    #[test]
+    fn split_queues() {
+        let bus = SimBus::new();
+        let host = bus.host();
+        let alloc = UsbBusAllocator::new(bus);
+        let mut queues = MidiQueues::<8>::new();
+        let mut midi = MidiClass::<_, 8>::with_queue_size(&alloc, 16);
+        let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x5e4)).build();
+        let (mut sender, mut receiver) = midi.split(&mut queues);
+        let config = host
+            .enumerate(|| {
+                device.poll(&mut [&mut midi]);
+            })
+            .unwrap();
+        let (out_ep, in_ep) = bulk_endpoints(&config);
+
+        //Device to host, with a SysEx message spanning several transfers
+        let sysex = LiveEvent::Common(SystemCommon::SysEx(u7::slice_from_int(&[
+            1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+        ])));
+        sender.send_event(u4::new(2), &sysex).unwrap();
+        assert_eq!(sender.pending(), 5);
+        assert_eq!(
+            sender.send_event(u4::new(2), &sysex),
+            Err(SendError::QueueFull)
+        );
+        midi.poll_queues();
+        assert_eq!(sender.pending(), 1);
+        let mut received = host.bulk_in(in_ep).unwrap();
+        assert_eq!(received.len(), 16);
+        device.poll(&mut [&mut midi]);
+        received.extend(host.bulk_in(in_ep).unwrap());
+        let expected: Vec<u8> = packetize(u4::new(2), &sysex).flatten().collect();
+        assert_eq!(received, expected);
+
+        //A full transfer is ended by a zero-length packet
+        let clock = [0x0F, 0xF8, 0, 0];
+        for _ in 0..4 {
+            sender.send_raw(clock).unwrap();
+        }
+        midi.poll_queues();
+        assert_eq!(host.bulk_in(in_ep).unwrap(), clock.repeat(4));
+        device.poll(&mut [&mut midi]);
+        assert!(host.bulk_in(in_ep).unwrap().is_empty());
+        device.poll(&mut [&mut midi]);
+        assert_eq!(host.bulk_in(in_ep), Err(SimError::NotReady));
+
+        //Host to device, throttled by the queue size: the rest of the second transfer waits in
+        //the class, and the third one in the endpoint
+        for _ in 0..3 {
+            host.bulk_out(out_ep, &clock.repeat(4)).unwrap();
+            device.poll(&mut [&mut midi]);
+        }
+        assert!(!host.out_ready(out_ep));
+        assert_eq!(receiver.pending(), 7);
+        for _ in 0..7 {
+            let packet = receiver.receive().unwrap();
+            assert_eq!(
+                packet.event,
+                LiveEvent::Realtime(SystemRealtime::TimingClock)
+            );
+        }
+        assert!(receiver.receive().is_none());
+        midi.poll_queues();
+        assert!(host.out_ready(out_ep));
+        assert_eq!(receiver.pending(), 5);
+        assert_eq!(receiver.receive_raw(), Some(clock));
+    }
+
+    #[test]
+    fn split_queues_default_sizes() {
+        let bus = SimBus::new();
+        let host = bus.host();
+        let alloc = UsbBusAllocator::new(bus);
+        let mut queues = MidiQueues::new();
+        let mut midi = MidiClass::new(&alloc, 64);
+        let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x5e4)).build();
+        let (_sender, mut receiver) = midi.split(&mut queues);
+        let config = host
+            .enumerate(|| {
+                device.poll(&mut [&mut midi]);
+            })
+            .unwrap();
+        let (out_ep, _) = bulk_endpoints(&config);
+
+        //A full transfer holds one packet more than the queue
+        let packets: Vec<[u8; 4]> = (0..16).map(|key| [0x09, 0x90, key, 100]).collect();
+        host.bulk_out(out_ep, &packets.concat()).unwrap();
+        device.poll(&mut [&mut midi]);
+        assert!(host.out_ready(out_ep));
+        assert_eq!(receiver.pending(), 15);
+        let mut received = Vec::new();
+        while let Some(packet) = receiver.receive_raw() {
+            received.push(packet);
+            midi.poll_queues();
+        }
+        assert_eq!(received, packets);
+
+        //Received events borrow their payload from the receiver
+        let sysex = [0x07, 0xF0, 0x12, 0xF7];
+        host.bulk_out(out_ep, &sysex).unwrap();
+        device.poll(&mut [&mut midi]);
+        assert_eq!(receiver.receive(), UsbMidiPacket::parse(&sysex).ok());
+    }
+}
+
+#[cfg(feature = "embassy")]
+mod embassy {
+    use crate::{
+        embassy::MidiClass,
+        live::{LiveEvent, SystemCommon, SystemRealtime},
+        num::{u4, u7},
+        MidiMessage,
+    };
+    use embassy_futures::block_on;
+    use embassy_usb::{
+        driver::{
+            self, Direction, EndpointAddress, EndpointAllocError, EndpointError, EndpointInfo,
+            EndpointType, Event, Unsupported,
+        },
+        Builder, Config,
+    };
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    /// Transfers that the host sent to the OUT endpoint, and that the device wrote to the IN
+    /// endpoint.
+    #[derive(Default)]
+    struct Transfers {
+        out: VecDeque<Vec<u8>>,
+        written: Vec<Vec<u8>>,
+    }
+
+    /// A driver whose endpoints are always ready, and which is never started.
+    struct FakeDriver {
+        transfers: Rc<RefCell<Transfers>>,
+        next_ep: usize,
+    }
+    impl FakeDriver {
+        fn alloc(&mut self, dir: Direction, ep_type: EndpointType, size: u16) -> FakeEndpoint {
+            self.next_ep += 1;
+            FakeEndpoint {
+                info: EndpointInfo {
+                    addr: EndpointAddress::from_parts(self.next_ep, dir),
+                    ep_type,
+                    max_packet_size: size,
+                    interval_ms: 0,
+                },
+                transfers: self.transfers.clone(),
+            }
+        }
+    }
+    impl<'a> driver::Driver<'a> for FakeDriver {
+        type EndpointOut = FakeEndpoint;
+        type EndpointIn = FakeEndpoint;
+        type ControlPipe = Never;
+        type Bus = Never;
+
+        fn alloc_endpoint_out(
+            &mut self,
+            ep_type: EndpointType,
+            max_packet_size: u16,
+            _interval_ms: u8,
+        ) -> Result<FakeEndpoint, EndpointAllocError> {
+            Ok(self.alloc(Direction::Out, ep_type, max_packet_size))
+        }
+        fn alloc_endpoint_in(
+            &mut self,
+            ep_type: EndpointType,
+            max_packet_size: u16,
+            _interval_ms: u8,
+        ) -> Result<FakeEndpoint, EndpointAllocError> {
+            Ok(self.alloc(Direction::In, ep_type, max_packet_size))
+        }
+        fn start(self, _control_max_packet_size: u16) -> (Never, Never) {
+            panic!("the fake driver cannot be started")
+        }
+    }
+
+    struct FakeEndpoint {
+        info: EndpointInfo,
+        transfers: Rc<RefCell<Transfers>>,
+    }
+    impl driver::Endpoint for FakeEndpoint {
+        fn info(&self) -> &EndpointInfo {
+            &self.info
+        }
+        async fn wait_enabled(&mut self) {}
+    }
+    impl driver::EndpointOut for FakeEndpoint {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+            //Without more transfers the host is gone
+            let data = self.transfers.borrow_mut().out.pop_front();
+            let data = data.ok_or(EndpointError::Disabled)?;
+            let dst = buf
+                .get_mut(..data.len())
+                .ok_or(EndpointError::BufferOverflow)?;
+            dst.copy_from_slice(&data);
+            Ok(data.len())
+        }
+    }
+    impl driver::EndpointIn for FakeEndpoint {
+        async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+            self.transfers.borrow_mut().written.push(buf.to_vec());
+            Ok(())
+        }
+    }
+
+    /// The bus and control pipe of a driver that is never started.
+    enum Never {}
+    impl driver::Bus for Never {
+        async fn enable(&mut self) {}
+        async fn disable(&mut self) {}
+        async fn poll(&mut self) -> Event {
+            match *self {}
+        }
+        fn endpoint_set_enabled(&mut self, _ep_addr: EndpointAddress, _enabled: bool) {}
+        fn endpoint_set_stalled(&mut self, _ep_addr: EndpointAddress, _stalled: bool) {}
+        fn endpoint_is_stalled(&mut self, _ep_addr: EndpointAddress) -> bool {
+            match *self {}
+        }
+        async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+            match *self {}
+        }
+    }
+    impl driver::ControlPipe for Never {
+        fn max_packet_size(&self) -> usize {
+            match *self {}
+        }
+        async fn setup(&mut self) -> [u8; 8] {
+            match *self {}
+        }
+        async fn data_out(
+            &mut self,
+            _buf: &mut [u8],
+            _first: bool,
+            _last: bool,
+        ) -> Result<usize, EndpointError> {
+            match *self {}
+        }
+        async fn data_in(
+            &mut self,
+            _data: &[u8],
+            _first: bool,
+            _last: bool,
+        ) -> Result<(), EndpointError> {
+            match *self {}
+        }
+        async fn accept(&mut self) {}
+        async fn reject(&mut self) {}
+        async fn accept_set_address(&mut self, _addr: u8) {}
+    }
+
+    /// Add a MIDI class to a device on the fake driver, and run `f` with it.
+    ///
+    /// Returns the descriptors written by the class, without the configuration and interface
+    /// association descriptors.
+    fn with_class(
+        max_packet_size: u16,
+        transfers: &Rc<RefCell<Transfers>>,
+        f: impl FnOnce(&mut MidiClass<FakeDriver>),
+    ) -> Vec<Vec<u8>> {
+        let mut config_desc = [0; 256];
+        let (mut bos_desc, mut msos_desc, mut control) = ([0; 64], [0; 0], [0; 64]);
+        {
+            let driver = FakeDriver {
+                transfers: transfers.clone(),
+                next_ep: 0,
+            };
+            let mut builder = Builder::new(
+                driver,
+                Config::new(0x16c0, 0x5e4),
+                &mut config_desc,
+                &mut bos_desc,
+                &mut msos_desc,
+                &mut control,
+            );
+            let mut midi = MidiClass::new(&mut builder, max_packet_size);
+            f(&mut midi);
+        }
+        crate::sim::descriptors(&config_desc)
+            .filter(|desc| desc[1] != 0x02 && desc[1] != 0x0B)
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn same_descriptors_as_usb_device() {
+        use crate::{class, sim::SimBus};
+        use usb_device::{bus::UsbBusAllocator, prelude::*};
+
+        let bus = SimBus::new();
+        let host = bus.host();
+        let alloc = UsbBusAllocator::new(bus);
+        let mut midi = class::MidiClass::new(&alloc, 64);
+        let mut device = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x5e4)).build();
+        let config = host
+            .enumerate(|| {
+                device.poll(&mut [&mut midi]);
+            })
+            .unwrap();
+        let expected: Vec<Vec<u8>> = crate::sim::descriptors(&config)
+            .skip(1)
+            .map(<[u8]>::to_vec)
+            .collect();
+
+        let descs = with_class(64, &Rc::default(), |_| {});
+        assert_eq!(descs, expected);
+    }
+
+    #[test]
+    fn send_and_receive() {
+        let transfers = Rc::<RefCell<Transfers>>::default();
+        let note_on = LiveEvent::Midi {
+            channel: u4::new(2),
+            message: MidiMessage::NoteOn {
+                key: u7::new(60),
+                vel: u7::new(100),
+            },
+        };
+        let sysex = [0x7E, 0x7F, 0x06, 0x01];
+        let sysex = LiveEvent::Common(SystemCommon::SysEx(u7::slice_from_int(&sysex)));
+        transfers.borrow_mut().out.extend([
+            //A reserved packet, a complete message and a fragment
+            vec![0x00, 0, 0, 0, 0x19, 0x92, 60, 100, 0x04, 0xF0, 0x7E, 0x7F],
+            //A single-packet SysEx message and a realtime message
+            vec![0x07, 0xF0, 0x12, 0xF7, 0x0F, 0xFE, 0, 0],
+        ]);
+
+        with_class(16, &transfers, |midi| {
+            assert_eq!(midi.max_packet_size(), 16);
+            block_on(async {
+                let packet = midi.receive().await.unwrap();
+                assert_eq!((packet.cable_number, packet.event), (u4::new(1), note_on));
+                //The payload is borrowed from the receive buffer
+                let packet = midi.receive().await.unwrap();
+                let data = u7::slice_from_int(&[0x12]);
+                assert_eq!(packet.event, LiveEvent::Common(SystemCommon::SysEx(data)));
+                let packet = midi.receive().await.unwrap();
+                assert_eq!(
+                    packet.event,
+                    LiveEvent::Realtime(SystemRealtime::ActiveSensing)
+                );
+                assert_eq!(midi.receive().await, Err(EndpointError::Disabled));
+
+                midi.send_event(u4::new(0), &note_on).await.unwrap();
+                midi.send_event(u4::new(3), &sysex).await.unwrap();
+            });
+        });
+
+        let written = transfers.borrow().written.clone();
+        let packets: Vec<[u8; 4]> = written
+            .concat()
+            .chunks(4)
+            .map(|p| p.try_into().unwrap())
+            .collect();
+        assert_eq!(
+            packets,
+            [
+                [0x09, 0x92, 60, 100],
+                [0x34, 0xF0, 0x7E, 0x7F],
+                [0x37, 0x06, 0x01, 0xF7],
+            ]
+        );
+        assert_eq!(written.len(), 2);
+    }
+}
@@ -0,0 +1,639 @@
+//! Composable editing operations over tracks and whole files.
+//!
+//! Each operation is a small value implementing the [`Transform`](trait.Transform.html) trait,
+//! which can be applied to a single [`Track`](../type.Track.html) or to a whole
+//! [`Smf`](../struct.Smf.html), and chained with other operations through
+//! [`Transform::then`](trait.Transform.html#method.then):
+//!
+//! ```rust
+//! # use midly_usb::{Smf, num::u7};
+//! use midly_usb::transform::{Transform, Transpose, ScaleVelocity, ChangePpq};
+//!
+//! let mut smf = Smf::parse(include_bytes!("../test-asset/Clementi.mid")).unwrap();
+//! Transpose::new(12)
+//!     .then(ScaleVelocity::new(0.8))
+//!     .then(ChangePpq::to(960.into()))
+//!     .apply(&mut smf);
+//! ```
+//!
+//! All operations work on absolute time internally, so event ordering is always preserved and
+//! `TrackEvent::delta` values are re-derived after every operation that moves events around.
+//!
+//! This module is only available with the `alloc` feature enabled.
+#![cfg(feature = "alloc")]
+
+use crate::{
+    event::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind},
+    notes::{self, OverlapPolicy},
+    prelude::*,
+    primitive::{clamp_delta, u15, Timing},
+    smf::{Smf, Track},
+};
+use alloc::collections::BTreeMap;
+
+/// An editing operation that can be applied to tracks and files.
+pub trait Transform {
+    /// Apply this operation to a single track.
+    fn apply_track(&self, track: &mut Track);
+
+    /// Apply this operation to every track in a file.
+    ///
+    /// Operations that depend on header information (such as
+    /// [`ChangePpq`](struct.ChangePpq.html)) override this method to update the header as well.
+    #[inline]
+    fn apply(&self, smf: &mut Smf) {
+        for track in smf.tracks.iter_mut() {
+            self.apply_track(track);
+        }
+    }
+
+    /// Chain another operation to be applied after this one.
+    #[inline]
+    fn then<T: Transform>(self, next: T) -> Then<Self, T>
+    where
+        Self: Sized,
+    {
+        Then(self, next)
+    }
+}
+
+impl<T: Transform + ?Sized> Transform for &T {
+    #[inline]
+    fn apply_track(&self, track: &mut Track) {
+        T::apply_track(self, track)
+    }
+    #[inline]
+    fn apply(&self, smf: &mut Smf) {
+        T::apply(self, smf)
+    }
+}
+
+/// Two operations applied one after the other.
+///
+/// Created by the [`Transform::then`](trait.Transform.html#method.then) method.
+#[derive(Copy, Clone, Debug)]
+pub struct Then<A, B>(pub A, pub B);
+impl<A: Transform, B: Transform> Transform for Then<A, B> {
+    #[inline]
+    fn apply_track(&self, track: &mut Track) {
+        self.0.apply_track(track);
+        self.1.apply_track(track);
+    }
+    #[inline]
+    fn apply(&self, smf: &mut Smf) {
+        self.0.apply(smf);
+        self.1.apply(smf);
+    }
+}
+
+/// Shift the key of all note-related messages by a number of semitones.
+///
+/// Affects `NoteOn`, `NoteOff` and `Aftertouch` messages.
+/// Keys that would fall outside the `0 ..= 127` range are clamped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Transpose {
+    /// How many semitones to move keys up (or down, if negative).
+    pub semitones: i8,
+}
+impl Transpose {
+    /// Create a new transposition by the given amount of semitones.
+    #[inline]
+    pub fn new(semitones: i8) -> Transpose {
+        Transpose { semitones }
+    }
+}
+impl Transform for Transpose {
+    fn apply_track(&self, track: &mut Track) {
+        let shift = |key: &mut u7| {
+            *key = u7::new((key.as_int() as i16 + self.semitones as i16).clamp(0, 127) as u8);
+        };
+        for ev in track.iter_mut() {
+            if let TrackEventKind::Midi {
+                message:
+                    MidiMessage::NoteOn { key, .. }
+                    | MidiMessage::NoteOff { key, .. }
+                    | MidiMessage::Aftertouch { key, .. },
+                ..
+            } = &mut ev.kind
+            {
+                shift(key);
+            }
+        }
+    }
+}
+
+/// Scale note-on velocities by a factor, then add an offset.
+///
+/// Note-on messages with a velocity of 0 are note-offs, and are left untouched.
+/// Any other velocity is clamped to the `1 ..= 127` range, so notes are never turned into
+/// note-offs by accident.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScaleVelocity {
+    /// The factor to multiply velocities by.
+    pub factor: f32,
+    /// A constant to add to velocities after scaling.
+    pub offset: i16,
+}
+impl ScaleVelocity {
+    /// Scale velocities by the given factor, without an offset.
+    #[inline]
+    pub fn new(factor: f32) -> ScaleVelocity {
+        ScaleVelocity { factor, offset: 0 }
+    }
+
+    /// Add a constant offset after scaling.
+    #[inline]
+    pub fn with_offset(self, offset: i16) -> ScaleVelocity {
+        ScaleVelocity { offset, ..self }
+    }
+}
+impl Transform for ScaleVelocity {
+    fn apply_track(&self, track: &mut Track) {
+        map_note_on_velocity(track, |vel| {
+            round_f32(vel as f32 * self.factor) + self.offset as i32
+        });
+    }
+}
+
+/// Reduce the dynamic range of note-on velocities above a threshold.
+///
+/// Velocities above `threshold` are brought closer to it by dividing their excess by `ratio`,
+/// much like an audio compressor.
+/// A ratio below `1.0` expands the dynamic range instead.
+///
+/// Note-on messages with a velocity of 0 are left untouched.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CompressVelocity {
+    /// Velocities at or below this value are not affected.
+    pub threshold: u7,
+    /// How much to divide the excess above the threshold by.
+    pub ratio: f32,
+}
+impl CompressVelocity {
+    /// Create a new velocity compressor.
+    #[inline]
+    pub fn new(threshold: u7, ratio: f32) -> CompressVelocity {
+        CompressVelocity { threshold, ratio }
+    }
+}
+impl Transform for CompressVelocity {
+    fn apply_track(&self, track: &mut Track) {
+        let threshold = self.threshold.as_int() as i32;
+        map_note_on_velocity(track, |vel| {
+            if vel > threshold {
+                threshold + round_f32((vel - threshold) as f32 / self.ratio)
+            } else {
+                vel
+            }
+        });
+    }
+}
+
+/// Move channel messages to other channels, or drop them altogether.
+///
+/// Events that are dropped have their delta time carried over to the next event, so the timing of
+/// the rest of the track is unaffected.
+/// Non-channel events (meta messages, SysEx, etc...) are never touched.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RemapChannels {
+    /// For each source channel, the channel to move its messages to, or `None` to drop them.
+    pub map: [Option<u4>; 16],
+}
+impl RemapChannels {
+    /// A mapping that leaves every channel as-is.
+    #[inline]
+    pub fn identity() -> RemapChannels {
+        let mut map = [None; 16];
+        for (i, target) in map.iter_mut().enumerate() {
+            *target = Some(u4::new(i as u8));
+        }
+        RemapChannels { map }
+    }
+
+    /// A mapping that keeps only the channels for which `keep` returns `true`, dropping the rest.
+    #[inline]
+    pub fn filter(mut keep: impl FnMut(u4) -> bool) -> RemapChannels {
+        let mut remap = RemapChannels::identity();
+        for target in remap.map.iter_mut() {
+            *target = target.filter(|&ch| keep(ch));
+        }
+        remap
+    }
+
+    /// Move the messages of channel `from` to channel `to`.
+    #[inline]
+    pub fn map(mut self, from: u4, to: u4) -> RemapChannels {
+        self.map[from.as_int() as usize] = Some(to);
+        self
+    }
+
+    /// Drop all messages in the given channel.
+    #[inline]
+    pub fn drop_channel(mut self, channel: u4) -> RemapChannels {
+        self.map[channel.as_int() as usize] = None;
+        self
+    }
+}
+impl Default for RemapChannels {
+    #[inline]
+    fn default() -> RemapChannels {
+        RemapChannels::identity()
+    }
+}
+impl Transform for RemapChannels {
+    fn apply_track(&self, track: &mut Track) {
+        let mut carry = 0;
+        track.retain_mut(|ev| {
+            if let TrackEventKind::Midi { channel, .. } = &mut ev.kind {
+                match self.map[channel.as_int() as usize] {
+                    Some(target) => *channel = target,
+                    None => {
+                        carry += ev.delta.as_int() as u64;
+                        return false;
+                    }
+                }
+            }
+            ev.delta = clamp_delta(ev.delta.as_int() as u64 + carry);
+            carry = 0;
+            true
+        });
+    }
+}
+
+/// Stretch (or compress) time by a rational factor.
+///
+/// Event times are scaled as absolute ticks and rounded to the nearest tick, so rounding errors
+/// never accumulate along the track, and events that were in order stay in order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StretchTime {
+    /// The numerator of the stretch factor.
+    pub num: u32,
+    /// The denominator of the stretch factor.
+    ///
+    /// A zero denominator leaves tracks untouched.
+    pub den: u32,
+}
+impl StretchTime {
+    /// Stretch time by the factor `num / den`.
+    #[inline]
+    pub fn new(num: u32, den: u32) -> StretchTime {
+        StretchTime { num, den }
+    }
+}
+impl Transform for StretchTime {
+    fn apply_track(&self, track: &mut Track) {
+        if self.den == 0 || self.num == self.den {
+            return;
+        }
+        let (num, den) = (self.num as u128, self.den as u128);
+        map_absolute_time(track, |tick| ((tick as u128 * num + den / 2) / den) as u64);
+    }
+}
+
+/// Move every event in a track by a fixed amount of ticks.
+///
+/// When shifting backwards, events that would land before tick 0 are clamped to tick 0, keeping
+/// their relative order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShiftTime {
+    /// How many ticks to move events forward (or backward, if negative).
+    pub ticks: i64,
+}
+impl ShiftTime {
+    /// Shift events by the given amount of ticks.
+    #[inline]
+    pub fn new(ticks: i64) -> ShiftTime {
+        ShiftTime { ticks }
+    }
+}
+impl Transform for ShiftTime {
+    fn apply_track(&self, track: &mut Track) {
+        map_absolute_time(track, |tick| {
+            (tick as i64).saturating_add(self.ticks).max(0) as u64
+        });
+    }
+}
+
+/// Change the resolution of a `Timing::Metrical` file, rescaling all deltas to match.
+///
+/// When applied to a single track, ticks are rescaled from `from` to `to`.
+/// When applied to a whole `Smf`, the source resolution is taken from the header instead, and
+/// the header is updated to the new resolution.
+/// Files with `Timing::Timecode` timing are left untouched.
+///
+/// Rounding is done on absolute ticks, so the rounding error of each event is at most half a tick
+/// and does not accumulate along the track.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChangePpq {
+    /// The original ticks per beat.
+    pub from: u15,
+    /// The target ticks per beat.
+    pub to: u15,
+}
+impl ChangePpq {
+    /// Rescale tracks from `from` ticks per beat to `to` ticks per beat.
+    #[inline]
+    pub fn new(from: u15, to: u15) -> ChangePpq {
+        ChangePpq { from, to }
+    }
+
+    /// Rescale a file to `to` ticks per beat, taking the source resolution from its header.
+    ///
+    /// Since the source resolution is unknown, applying the result to a lone track does nothing.
+    #[inline]
+    pub fn to(to: u15) -> ChangePpq {
+        ChangePpq { from: to, to }
+    }
+}
+impl Transform for ChangePpq {
+    #[inline]
+    fn apply_track(&self, track: &mut Track) {
+        StretchTime::new(self.to.as_int() as u32, self.from.as_int() as u32).apply_track(track);
+    }
+
+    fn apply(&self, smf: &mut Smf) {
+        if let Timing::Metrical(from) = smf.header.timing {
+            let change = ChangePpq::new(from, self.to);
+            for track in smf.tracks.iter_mut() {
+                change.apply_track(track);
+            }
+            smf.header.timing = Timing::Metrical(self.to);
+        }
+    }
+}
+
+/// Which parts of a note are snapped to the grid by [`Quantize`](struct.Quantize.html).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum QuantizeMode {
+    /// Only note starts are quantized, and every note keeps its original length.
+    Starts,
+    /// Both note starts and note ends are quantized.
+    StartsAndEnds,
+}
+
+/// Snap `NoteOn`/`NoteOff` events to a rhythmic grid, optionally with swing.
+///
+/// Notes are paired (first note-on matched to first note-off of the same key and channel) before
+/// quantizing, and the following guarantees are upheld:
+///
+/// - A note never ends before or at the same tick it starts, even if both ends snap to the same
+///   grid point. In that case the note is extended to the next grid point.
+/// - A note never ends after the next note of the same key and channel starts, so overlapping
+///   notes cannot invert their pairing.
+/// - Notes are never removed. If a note would snap to the same start as the previous note of the
+///   same key and channel, it is moved to the next grid point instead.
+/// - Note-offs are sorted before any other event at the same tick.
+///
+/// All other events (and unpaired note-offs) keep their original absolute time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quantize {
+    /// The length of a grid step in ticks is `step_num / step_den`, allowing for grids that do not
+    /// divide the beat evenly (such as triplets).
+    pub step_num: u32,
+    /// See `step_num`.
+    pub step_den: u32,
+    /// How much to move events towards the grid, as a percentage from `0` (not at all) to `100`
+    /// (all the way).
+    pub strength: u8,
+    /// How much to delay every other grid point, as a fraction of half a grid step.
+    ///
+    /// `0.0` is a straight grid, while around `0.67` gives a triplet feel (delaying the off-beat by a
+    /// third of a grid step).
+    pub swing: f32,
+    /// Whether to quantize note ends as well as note starts.
+    pub mode: QuantizeMode,
+}
+impl Quantize {
+    /// Quantize to the given note division, given the ticks per beat of the file.
+    ///
+    /// `division` is the note value of a grid step, as the amount of steps in a whole note: `4` is
+    /// a quarter note grid, `16` is a sixteenth note grid, and `12` is an eighth note triplet grid.
+    ///
+    /// Defaults to full strength, no swing and quantizing only note starts.
+    #[inline]
+    pub fn new(ticks_per_beat: u15, division: u16) -> Quantize {
+        Quantize::with_step(ticks_per_beat.as_int() as u32 * 4, division as u32)
+    }
+
+    /// Quantize to a grid with a step of `num / den` ticks.
+    ///
+    /// Defaults to full strength, no swing and quantizing only note starts.
+    #[inline]
+    pub fn with_step(num: u32, den: u32) -> Quantize {
+        Quantize {
+            step_num: num,
+            step_den: den,
+            strength: 100,
+            swing: 0.0,
+            mode: QuantizeMode::Starts,
+        }
+    }
+
+    /// Set the quantization strength as a percentage.
+    #[inline]
+    pub fn strength(self, strength: u8) -> Quantize {
+        Quantize {
+            strength: strength.min(100),
+            ..self
+        }
+    }
+
+    /// Set the swing amount.
+    #[inline]
+    pub fn swing(self, swing: f32) -> Quantize {
+        Quantize { swing, ..self }
+    }
+
+    /// Set which parts of the notes to quantize.
+    #[inline]
+    pub fn mode(self, mode: QuantizeMode) -> Quantize {
+        Quantize { mode, ..self }
+    }
+
+    /// The absolute tick of the grid point with the given index, including swing.
+    fn grid_point(&self, idx: u64) -> u64 {
+        let (num, den) = (self.step_num as u128, self.step_den as u128);
+        let mut tick = ((idx as u128 * num + den / 2) / den) as u64;
+        if idx % 2 == 1 {
+            tick += round_f32(self.swing * self.step_num as f32 / self.step_den as f32 / 2.0).max(0)
+                as u64;
+        }
+        tick
+    }
+
+    /// Find the grid point closest to the given tick.
+    fn nearest_grid_point(&self, tick: u64) -> (u64, u64) {
+        let (num, den) = (self.step_num as u128, self.step_den as u128);
+        let idx = ((tick as u128 * den + num / 2) / num) as u64;
+        //Swing may bring the neighbouring grid points closer than the straight closest one
+        let candidates = [idx.saturating_sub(1), idx, idx + 1];
+        let mut best = (idx, self.grid_point(idx));
+        for &cand in candidates.iter() {
+            let point = self.grid_point(cand);
+            if point.abs_diff(tick) < best.1.abs_diff(tick) {
+                best = (cand, point);
+            }
+        }
+        best
+    }
+
+    /// Move a tick towards its closest grid point, according to `strength`.
+    fn snap(&self, tick: u64) -> u64 {
+        let (_idx, target) = self.nearest_grid_point(tick);
+        let strength = self.strength as i64;
+        let diff = target as i64 - tick as i64;
+        (tick as i64 + (diff * strength + diff.signum() * 50) / 100) as u64
+    }
+
+    /// The first grid point after the given tick, always at least one tick later.
+    fn next_grid_point(&self, tick: u64) -> u64 {
+        let (idx, point) = self.nearest_grid_point(tick);
+        let next = if point > tick {
+            point
+        } else {
+            self.grid_point(idx + 1)
+        };
+        next.max(tick + 1)
+    }
+
+    /// Move a note end tick towards the grid, making sure the note stays at least one tick long.
+    fn snap_end(&self, start: u64, end: u64) -> u64 {
+        let snapped = self.snap(end);
+        if snapped > start {
+            return snapped;
+        }
+        //Extend the note to the next grid point after its start
+        self.next_grid_point(start)
+    }
+}
+impl Transform for Quantize {
+    fn apply_track(&self, track: &mut Track) {
+        if self.step_num == 0 || self.step_den == 0 {
+            return;
+        }
+        //Compute absolute times
+        let mut times = Vec::with_capacity(track.len());
+        let mut abs = 0;
+        for ev in track.iter() {
+            abs += ev.delta.as_int() as u64;
+            times.push(abs);
+        }
+        let mut new_times = times.clone();
+        //Pair notes and quantize them
+        let notes = notes::pair(track, OverlapPolicy::Fifo);
+        //The index of the previous note with the same key, for every note
+        let mut prev_same = Vec::with_capacity(notes.len());
+        let mut last_by_key = BTreeMap::new();
+        for (i, note) in notes.iter().enumerate() {
+            prev_same.push(last_by_key.insert((note.channel, note.key), i));
+        }
+        for (note, prev) in notes.iter().zip(prev_same.iter()) {
+            let mut start = self.snap(times[note.on]);
+            //Keep notes with the same key apart, so that they can keep their own length
+            if let Some(prev) = *prev {
+                let prev_start = new_times[notes[prev].on];
+                if start <= prev_start {
+                    start = self.next_grid_point(prev_start);
+                }
+            }
+            new_times[note.on] = start;
+        }
+        //The index of the next note with the same key, for every note
+        let mut next_same = vec![None; notes.len()];
+        for (i, prev) in prev_same.iter().enumerate() {
+            if let Some(prev) = *prev {
+                next_same[prev] = Some(i);
+            }
+        }
+        for (note, next) in notes.iter().zip(next_same.iter()) {
+            let off = match note.off {
+                Some(off) => off,
+                None => continue,
+            };
+            let start = new_times[note.on];
+            let mut end = match self.mode {
+                QuantizeMode::Starts => start + (times[off] - times[note.on]).max(1),
+                QuantizeMode::StartsAndEnds => self.snap_end(start, times[off]),
+            };
+            //Never overlap the next note with the same key, which always starts later
+            if let Some(next) = *next {
+                end = end.min(new_times[notes[next].on]);
+            }
+            new_times[off] = end;
+        }
+        //Keep end of track after everything else
+        let last = new_times.iter().copied().max().unwrap_or(0);
+        for (ev, time) in track.iter().zip(new_times.iter_mut()) {
+            if let TrackEventKind::Meta(MetaMessage::EndOfTrack) = ev.kind {
+                *time = last;
+            }
+        }
+        //Reorder and recompute deltas
+        let order_class = |ev: &TrackEvent| match ev.kind {
+            TrackEventKind::Meta(MetaMessage::EndOfTrack) => 2,
+            _ if is_note_off(ev) => 0,
+            _ => 1,
+        };
+        let mut order = (0..track.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| (new_times[i], order_class(&track[i]), i));
+        let old = mem::take(track);
+        let mut prev = 0;
+        track.extend(order.into_iter().map(|i| {
+            let mut ev = old[i];
+            ev.delta = clamp_delta(new_times[i] - prev);
+            prev = new_times[i];
+            ev
+        }));
+    }
+}
+
+/// Whether the event is a `NoteOff` or a `NoteOn` with zero velocity.
+fn is_note_off(ev: &TrackEvent) -> bool {
+    match ev.kind {
+        TrackEventKind::Midi { message, .. } => match message {
+            MidiMessage::NoteOff { .. } => true,
+            MidiMessage::NoteOn { vel, .. } => vel == 0,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Round a float to the nearest integer, without requiring `std`.
+#[inline]
+fn round_f32(x: f32) -> i32 {
+    if x < 0.0 {
+        (x - 0.5) as i32
+    } else {
+        (x + 0.5) as i32
+    }
+}
+
+/// Remap the velocity of every sounding note-on, keeping it in the `1 ..= 127` range.
+fn map_note_on_velocity(track: &mut Track, mut map: impl FnMut(i32) -> i32) {
+    for ev in track.iter_mut() {
+        if let TrackEventKind::Midi {
+            message: MidiMessage::NoteOn { vel, .. },
+            ..
+        } = &mut ev.kind
+        {
+            if *vel > 0 {
+                *vel = u7::new(map(vel.as_int() as i32).clamp(1, 127) as u8);
+            }
+        }
+    }
+}
+
+/// Remap the absolute time of every event in a track, re-deriving deltas afterwards.
+///
+/// `map` must be monotonic (non-decreasing) in order to preserve event order.
+pub(crate) fn map_absolute_time(track: &mut Track, mut map: impl FnMut(u64) -> u64) {
+    let mut abs = 0;
+    let mut prev_new = 0;
+    for ev in track.iter_mut() {
+        abs += ev.delta.as_int() as u64;
+        let new = map(abs).max(prev_new);
+        ev.delta = clamp_delta(new - prev_new);
+        prev_new = new;
+    }
+}
@@ -0,0 +1,529 @@
+//! Detailed validation of Standard Midi Files.
+//!
+//! The parser in this crate is lenient by default, and even with the `strict` feature enabled it
+//! only reports the first problem it runs into.
+//! The [`validate`](fn.validate.html) function instead scans the whole file and lists every
+//! violation of the spec it finds, along with its location:
+//!
+//! ```rust
+//! let report = midly_usb::validate::validate(include_bytes!("../test-asset/Clementi.mid"));
+//! for diagnostic in report.iter() {
+//!     println!("{}", diagnostic);
+//! }
+//! ```
+//!
+//! Validation does not depend on the `strict` feature.
+//! This module is only available with the `alloc` feature enabled.
+#![cfg(feature = "alloc")]
+
+use crate::{event::MidiMessage, prelude::*, riff};
+
+/// A single violation of the Standard Midi File spec.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Violation {
+    /// The file is neither a raw SMF file nor an RMID file.
+    NotMidi,
+    /// The first chunk is not an `MThd` chunk.
+    MissingHeader,
+    /// The header chunk does not have a length of 6.
+    HeaderLength(u32),
+    /// The header declares an unknown format.
+    UnknownFormat(u16),
+    /// A second header chunk was found.
+    DuplicateHeader,
+    /// The amount of track chunks differs from the amount declared in the header.
+    TrackCountMismatch {
+        /// The amount of tracks declared in the header.
+        declared: u16,
+        /// The amount of track chunks actually present.
+        found: usize,
+    },
+    /// A format 0 file has more than one track.
+    SingleTrackWithMultipleTracks(usize),
+    /// A chunk declares a length that goes past the end of the file.
+    ChunkLengthMismatch {
+        /// The length declared in the chunk header.
+        declared: u32,
+        /// The amount of bytes actually available.
+        available: usize,
+    },
+    /// There are stray bytes at the end of the file that do not make up a chunk header.
+    TrailingBytes(usize),
+    /// A track does not end with an `EndOfTrack` meta event.
+    MissingEndOfTrack,
+    /// A track contains more than one `EndOfTrack` meta event.
+    DuplicateEndOfTrack,
+    /// An event was found after the `EndOfTrack` meta event.
+    EventAfterEndOfTrack,
+    /// A note-on was never ended by a note-off.
+    UnmatchedNoteOn {
+        /// The channel of the note.
+        channel: u4,
+        /// The key of the note.
+        key: u7,
+    },
+    /// A meta message with a fixed length has the wrong length.
+    MetaLength {
+        /// The meta message type byte.
+        meta_type: u8,
+        /// The length required by the spec.
+        expected: u32,
+        /// The length found in the file.
+        found: u32,
+    },
+    /// A MIDI message relies on running status right after a SysEx, Escape or Meta event, which
+    /// cancel running status.
+    RunningStatusAfterSysEx,
+    /// A MIDI message uses running status, but no status byte was ever set.
+    MissingRunningStatus,
+    /// A byte with its top bit set was found where a 7-bit data byte was expected.
+    NonDataByte(u8),
+    /// A System Common or System Realtime status byte was found inside a track.
+    UnexpectedStatus(u8),
+    /// A variable-length integer is longer than the maximum of 4 bytes.
+    LongVarlen,
+    /// The event runs past the end of its track chunk.
+    TruncatedEvent,
+}
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Violation::*;
+        match *self {
+            NotMidi => write!(f, "not a midi file"),
+            MissingHeader => write!(f, "missing header chunk"),
+            HeaderLength(len) => write!(f, "header chunk has length {} instead of 6", len),
+            UnknownFormat(fmt) => write!(f, "unknown format {}", fmt),
+            DuplicateHeader => write!(f, "duplicate header chunk"),
+            TrackCountMismatch { declared, found } => write!(
+                f,
+                "header declares {} tracks, but {} were found",
+                declared, found
+            ),
+            SingleTrackWithMultipleTracks(count) => {
+                write!(f, "format 0 file has {} tracks", count)
+            }
+            ChunkLengthMismatch {
+                declared,
+                available,
+            } => write!(
+                f,
+                "chunk declares {} bytes, but only {} are available",
+                declared, available
+            ),
+            TrailingBytes(len) => write!(f, "{} stray bytes at end of file", len),
+            MissingEndOfTrack => write!(f, "track does not end with an end of track event"),
+            DuplicateEndOfTrack => write!(f, "duplicate end of track event"),
+            EventAfterEndOfTrack => write!(f, "event after end of track"),
+            UnmatchedNoteOn { channel, key } => write!(
+                f,
+                "note-on for key {} on channel {} is never ended",
+                key, channel
+            ),
+            MetaLength {
+                meta_type,
+                expected,
+                found,
+            } => write!(
+                f,
+                "meta message 0x{:02X} has length {} instead of {}",
+                meta_type, found, expected
+            ),
+            RunningStatusAfterSysEx => write!(f, "running status used after sysex or meta event"),
+            MissingRunningStatus => write!(f, "running status used without a previous status"),
+            NonDataByte(byte) => write!(f, "expected data byte, found 0x{:02X}", byte),
+            UnexpectedStatus(byte) => write!(f, "unexpected status byte 0x{:02X}", byte),
+            LongVarlen => write!(f, "variable-length integer longer than 4 bytes"),
+            TruncatedEvent => write!(f, "event runs past the end of the track"),
+        }
+    }
+}
+
+/// A spec violation together with its location in the file.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Diagnostic {
+    /// The byte offset of the violation, counted from the start of the input slice.
+    pub offset: usize,
+    /// The index of the track chunk the violation was found in, if any.
+    pub track: Option<usize>,
+    /// The index of the event within its track, if the violation concerns a single event.
+    pub event: Option<usize>,
+    /// What exactly is wrong.
+    pub violation: Violation,
+}
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at byte {}", self.offset)?;
+        if let Some(track) = self.track {
+            write!(f, ", track {}", track)?;
+        }
+        if let Some(event) = self.event {
+            write!(f, ", event {}", event)?;
+        }
+        write!(f, ": {}", self.violation)
+    }
+}
+
+/// The result of validating a file: a list of diagnostics, sorted by byte offset.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Report {
+    /// All violations found in the file.
+    pub diagnostics: Vec<Diagnostic>,
+}
+impl Report {
+    /// Whether the file is fully compliant with the spec.
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// Iterate over the diagnostics in the report.
+    #[inline]
+    pub fn iter(&self) -> core::slice::Iter<'_, Diagnostic> {
+        self.diagnostics.iter()
+    }
+}
+impl<'a> IntoIterator for &'a Report {
+    type Item = &'a Diagnostic;
+    type IntoIter = core::slice::Iter<'a, Diagnostic>;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Validate a raw Standard Midi File (or RMID file), listing every spec violation found.
+///
+/// Unlike [`parse`](../fn.parse.html), this function never gives up on the first error: when an
+/// event cannot be decoded the rest of its track is skipped, but the remaining tracks are still
+/// checked.
+pub fn validate(raw: &[u8]) -> Report {
+    let mut v = Validator {
+        base: raw.as_ptr() as usize,
+        diagnostics: Vec::new(),
+    };
+    v.file(raw);
+    v.diagnostics.sort_by_key(|diag| diag.offset);
+    Report {
+        diagnostics: v.diagnostics,
+    }
+}
+
+struct Validator {
+    /// Address of the start of the input, used to compute offsets out of subslices.
+    base: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+impl Validator {
+    #[inline]
+    fn offset(&self, at: &[u8]) -> usize {
+        at.as_ptr() as usize - self.base
+    }
+
+    fn report(&mut self, at: &[u8], track: Option<usize>, event: Option<usize>, v: Violation) {
+        let offset = self.offset(at);
+        self.diagnostics.push(Diagnostic {
+            offset,
+            track,
+            event,
+            violation: v,
+        });
+    }
+
+    fn file(&mut self, raw: &[u8]) {
+        let mut raw = match raw.get(..4) {
+            Some(b"RIFF") => match riff::unwrap(raw) {
+                Ok(data) => data,
+                Err(_) => return self.report(raw, None, None, Violation::NotMidi),
+            },
+            Some(b"MThd") => raw,
+            _ => return self.report(raw, None, None, Violation::NotMidi),
+        };
+        let mut declared = None;
+        let mut track_count = 0;
+        while !raw.is_empty() {
+            let start = raw;
+            if raw.len() < 8 {
+                self.report(start, None, None, Violation::TrailingBytes(raw.len()));
+                break;
+            }
+            let (id, rest) = raw.split_at(4);
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+            raw = &rest[4..];
+            let data = match raw.split_checked(len as usize) {
+                Some(data) => data,
+                None => {
+                    let track = if id == b"MTrk" {
+                        Some(track_count)
+                    } else {
+                        None
+                    };
+                    self.report(
+                        start,
+                        track,
+                        None,
+                        Violation::ChunkLengthMismatch {
+                            declared: len,
+                            available: raw.len(),
+                        },
+                    );
+                    //Keep pointing into the input, so that offsets can still be computed
+                    let (data, end) = raw.split_at(raw.len());
+                    raw = end;
+                    data
+                }
+            };
+            match id {
+                b"MThd" if declared.is_none() => declared = Some(self.header(start, data)),
+                b"MThd" => self.report(start, None, None, Violation::DuplicateHeader),
+                b"MTrk" => {
+                    if declared.is_none() {
+                        self.report(start, None, None, Violation::MissingHeader);
+                        declared = Some((None, None));
+                    }
+                    self.track(track_count, data);
+                    track_count += 1;
+                }
+                //Unknown chunks are allowed by the spec
+                _ => {}
+            }
+        }
+        if let Some((declared, format)) = declared {
+            let end = raw;
+            match declared {
+                Some(declared) if declared as usize != track_count => self.report(
+                    end,
+                    None,
+                    None,
+                    Violation::TrackCountMismatch {
+                        declared,
+                        found: track_count,
+                    },
+                ),
+                _ => {}
+            }
+            if format == Some(0) && track_count > 1 {
+                self.report(
+                    end,
+                    None,
+                    None,
+                    Violation::SingleTrackWithMultipleTracks(track_count),
+                );
+            }
+        }
+    }
+
+    /// Check a header chunk, returning the declared track count and format.
+    fn header(&mut self, start: &[u8], data: &[u8]) -> (Option<u16>, Option<u16>) {
+        if data.len() != 6 {
+            self.report(
+                start,
+                None,
+                None,
+                Violation::HeaderLength(data.len() as u32),
+            );
+        }
+        if data.len() < 4 {
+            return (None, None);
+        }
+        let format = u16::from_be_bytes([data[0], data[1]]);
+        if format > 2 {
+            self.report(data, None, None, Violation::UnknownFormat(format));
+        }
+        (Some(u16::from_be_bytes([data[2], data[3]])), Some(format))
+    }
+
+    fn track(&mut self, track: usize, mut raw: &[u8]) {
+        let mut running_status = None;
+        let mut after_sysex = false;
+        let mut end_of_track = false;
+        let mut playing: Vec<(u4, u7, &[u8], usize)> = Vec::new();
+        let mut idx = 0;
+        while !raw.is_empty() {
+            let start = raw;
+            let here = (Some(track), Some(idx));
+            if end_of_track {
+                self.report(start, here.0, here.1, Violation::EventAfterEndOfTrack);
+            }
+            if let Err(v) = read_varlen(&mut raw) {
+                self.report(start, here.0, here.1, v);
+                return;
+            }
+            let status = match raw.first() {
+                Some(&status) => status,
+                None => {
+                    self.report(start, here.0, here.1, Violation::TruncatedEvent);
+                    return;
+                }
+            };
+            match status {
+                0x00..=0xEF => {
+                    let status = if status < 0x80 {
+                        match running_status {
+                            Some(status) => {
+                                if after_sysex {
+                                    self.report(
+                                        start,
+                                        here.0,
+                                        here.1,
+                                        Violation::RunningStatusAfterSysEx,
+                                    );
+                                }
+                                status
+                            }
+                            None => {
+                                self.report(start, here.0, here.1, Violation::MissingRunningStatus);
+                                return;
+                            }
+                        }
+                    } else {
+                        raw = &raw[1..];
+                        status
+                    };
+                    running_status = Some(status);
+                    after_sysex = false;
+                    let data = match raw.split_checked(MidiMessage::msg_length(status)) {
+                        Some(data) => data,
+                        None => {
+                            self.report(start, here.0, here.1, Violation::TruncatedEvent);
+                            return;
+                        }
+                    };
+                    for &byte in data {
+                        if byte >= 0x80 {
+                            self.report(start, here.0, here.1, Violation::NonDataByte(byte));
+                        }
+                    }
+                    let channel = u4::from(status & 0xF);
+                    let key = u7::from(data.first().copied().unwrap_or(0) & 0x7F);
+                    match (status >> 4, data.get(1).copied()) {
+                        (0x9, Some(vel)) if vel != 0 => playing.push((channel, key, start, idx)),
+                        (0x8, _) | (0x9, _) => {
+                            if let Some(i) = playing
+                                .iter()
+                                .position(|&(c, k, ..)| c == channel && k == key)
+                            {
+                                playing.remove(i);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                0xF0 | 0xF7 | 0xFF => {
+                    raw = &raw[1..];
+                    let meta_type = if status == 0xFF {
+                        match raw.split_checked(1) {
+                            Some(ty) => Some(ty[0]),
+                            None => {
+                                self.report(start, here.0, here.1, Violation::TruncatedEvent);
+                                return;
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    let len = match read_varlen(&mut raw) {
+                        Ok(len) => len,
+                        Err(v) => {
+                            self.report(start, here.0, here.1, v);
+                            return;
+                        }
+                    };
+                    let data = match raw.split_checked(len as usize) {
+                        Some(data) => data,
+                        None => {
+                            self.report(start, here.0, here.1, Violation::TruncatedEvent);
+                            return;
+                        }
+                    };
+                    match meta_type {
+                        Some(meta_type) => {
+                            if let Some(expected) = meta_length(meta_type, len) {
+                                self.report(
+                                    start,
+                                    here.0,
+                                    here.1,
+                                    Violation::MetaLength {
+                                        meta_type,
+                                        expected,
+                                        found: len,
+                                    },
+                                );
+                            }
+                            if meta_type == 0x2F {
+                                if end_of_track {
+                                    self.report(
+                                        start,
+                                        here.0,
+                                        here.1,
+                                        Violation::DuplicateEndOfTrack,
+                                    );
+                                }
+                                end_of_track = true;
+                            }
+                        }
+                        None if status == 0xF0 => {
+                            //Every SysEx byte must be 7-bit, except for the trailing 0xF7
+                            let body = match data.split_last() {
+                                Some((0xF7, body)) => body,
+                                _ => data,
+                            };
+                            if let Some(&byte) = body.iter().find(|&&b| b >= 0x80) {
+                                self.report(start, here.0, here.1, Violation::NonDataByte(byte));
+                            }
+                        }
+                        None => {}
+                    }
+                    after_sysex = true;
+                }
+                _ => {
+                    self.report(start, here.0, here.1, Violation::UnexpectedStatus(status));
+                    return;
+                }
+            }
+            idx += 1;
+        }
+        for (channel, key, start, event) in playing {
+            self.report(
+                start,
+                Some(track),
+                Some(event),
+                Violation::UnmatchedNoteOn { channel, key },
+            );
+        }
+        if !end_of_track {
+            self.report(raw, Some(track), None, Violation::MissingEndOfTrack);
+        }
+    }
+}
+
+/// Read a variable-length integer, which must be complete and at most 4 bytes long regardless of
+/// the `strict` feature.
+fn read_varlen(raw: &mut &[u8]) -> StdResult<u32, Violation> {
+    let long = raw.len() >= 4 && raw[..4].iter().all(|&byte| byte >= 0x80);
+    match u28::read_varlen(raw, true) {
+        Ok(int) => Ok(int.as_int()),
+        Err(_) if long => Err(Violation::LongVarlen),
+        Err(_) => Err(Violation::TruncatedEvent),
+    }
+}
+
+/// If the given meta message length is invalid, returns the expected length.
+fn meta_length(meta_type: u8, len: u32) -> Option<u32> {
+    let expected = match meta_type {
+        //Sequence number may be omitted altogether
+        0x00 if len == 0 => return None,
+        0x00 => 2,
+        0x20 | 0x21 => 1,
+        0x2F => 0,
+        0x51 => 3,
+        0x54 => 5,
+        0x58 => 4,
+        0x59 => 2,
+        _ => return None,
+    };
+    if len == expected {
+        None
+    } else {
+        Some(expected)
+    }
+}
@@ -0,0 +1,249 @@
+//! Writing SMF files one event at a time.
+
+#[cfg(feature = "alloc")]
+use crate::owned::OwnedTrackEvent;
+use crate::{event::TrackEvent, prelude::*, smf::Header};
+
+/// Offset of the track count within the header chunk.
+const TRACK_COUNT_OFFSET: u64 = 4 + 4 + 2;
+
+/// Writes a Standard Midi File incrementally, without requiring all tracks and events upfront.
+///
+/// The header is written as soon as the writer is created.
+/// Tracks are then written one after another: open a track with
+/// [`open_track`](#method.open_track), push events into it with [`push`](#method.push), and close
+/// it with [`close_track`](#method.close_track).
+/// Finally, [`finish`](#method.finish) must be called to complete the file.
+///
+/// If the underlying writer is seekable, events are written straight through and the chunk
+/// lengths (and track count) are back-patched once known, so memory usage stays bounded no matter
+/// how long the file is.
+/// Otherwise, each track is buffered in memory until it is closed (which requires the `alloc`
+/// feature), and the track count must be declared upfront with
+/// [`with_track_count`](#method.with_track_count).
+///
+/// ```rust
+/// use midly_usb::{Format, Header, MetaMessage, SmfWriter, Timing, TrackEvent, TrackEventKind};
+///
+/// let header = Header::new(Format::Parallel, Timing::Metrical(480.into()));
+/// let mut writer = SmfWriter::new(header, Vec::new()).unwrap();
+/// writer.open_track().unwrap();
+/// writer.push(&TrackEvent {
+///     delta: 0.into(),
+///     kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+/// }).unwrap();
+/// writer.close_track().unwrap();
+/// let bytes = writer.finish().unwrap();
+/// assert_eq!(midly_usb::Smf::parse(&bytes).unwrap().tracks.len(), 1);
+/// ```
+///
+/// This type is always available, even in `no_std` environments.
+#[derive(Debug)]
+pub struct SmfWriter<W: Write> {
+    out: W,
+    header: Header,
+    /// The position of the header chunk, if the writer is seekable.
+    header_pos: Option<u64>,
+    /// The track count written in the header, if it was known upfront.
+    declared_tracks: Option<u16>,
+    track_count: u16,
+    track: Option<OpenTrack>,
+    running_status: Option<u8>,
+    #[cfg(feature = "alloc")]
+    buf: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum OpenTrack {
+    /// The track is written straight through, and its length is back-patched at the given
+    /// position when closed.
+    Seek(u64),
+    /// The track is buffered in memory and written out when closed.
+    Buffered,
+}
+
+impl<W: Write> SmfWriter<W> {
+    /// Create a writer and write the header, leaving the track count to be filled in by
+    /// [`finish`](#method.finish).
+    ///
+    /// This requires a seekable writer, since the track count must be back-patched.
+    /// For non-seekable writers, use [`with_track_count`](#method.with_track_count) instead.
+    pub fn new(header: Header, mut out: W) -> StdResult<SmfWriter<W>, W::Error> {
+        let header_pos = match out.make_seekable() {
+            Some(out) => out.tell()?,
+            None => {
+                return Err(W::invalid_input(
+                    "track count must be declared upfront for non-seekable writers",
+                ))
+            }
+        };
+        Self::start(header, Some(header_pos), None, out)
+    }
+
+    /// Create a writer and write the header with a known track count.
+    ///
+    /// Works with any writer, but [`finish`](#method.finish) fails if a different amount of
+    /// tracks is written.
+    pub fn with_track_count(
+        header: Header,
+        track_count: u16,
+        out: W,
+    ) -> StdResult<SmfWriter<W>, W::Error> {
+        Self::start(header, None, Some(track_count), out)
+    }
+
+    fn start(
+        header: Header,
+        header_pos: Option<u64>,
+        declared_tracks: Option<u16>,
+        mut out: W,
+    ) -> StdResult<SmfWriter<W>, W::Error> {
+        let mut chunk = [0; 4 + 4 + 6];
+        chunk[0..4].copy_from_slice(b"MThd");
+        chunk[4..8].copy_from_slice(&6u32.to_be_bytes());
+        chunk[8..14].copy_from_slice(&header.encode(declared_tracks.unwrap_or(0)));
+        out.write(&chunk)?;
+        Ok(SmfWriter {
+            out,
+            header,
+            header_pos,
+            declared_tracks,
+            track_count: 0,
+            track: None,
+            running_status: None,
+            #[cfg(feature = "alloc")]
+            buf: Vec::new(),
+        })
+    }
+
+    /// The header that was written.
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// How many tracks have been opened so far.
+    #[inline]
+    pub fn track_count(&self) -> usize {
+        self.track_count as usize
+    }
+
+    /// Whether there is a track currently open.
+    #[inline]
+    pub fn is_track_open(&self) -> bool {
+        self.track.is_some()
+    }
+
+    /// Start a new track.
+    ///
+    /// If a track is already open, it is closed first.
+    pub fn open_track(&mut self) -> WriteResult<W> {
+        self.close_track()?;
+        self.track_count = self
+            .track_count
+            .checked_add(1)
+            .ok_or_else(|| W::invalid_input("track count exceeds 16 bit range"))?;
+        self.running_status = None;
+        if let Some(out) = self.out.make_seekable() {
+            out.write(b"MTrk\0\0\0\0")?;
+            self.track = Some(OpenTrack::Seek(out.tell()?));
+            return Ok(());
+        }
+        #[cfg(feature = "alloc")]
+        {
+            self.buf.clear();
+            self.track = Some(OpenTrack::Buffered);
+            Ok(())
+        }
+        #[cfg(not(feature = "alloc"))]
+        {
+            Err(W::invalid_input(
+                "cannot stream tracks to a non-seekable writer without allocation",
+            ))
+        }
+    }
+
+    /// Write a single event into the open track.
+    ///
+    /// Running status is used whenever possible.
+    pub fn push(&mut self, event: &TrackEvent) -> WriteResult<W> {
+        match self.track {
+            Some(OpenTrack::Seek(_)) => event.write(&mut self.running_status, &mut self.out),
+            #[cfg(feature = "alloc")]
+            Some(OpenTrack::Buffered) => event
+                .write(&mut self.running_status, &mut self.buf)
+                .map_err(W::invalid_input),
+            #[cfg(not(feature = "alloc"))]
+            Some(OpenTrack::Buffered) => unreachable!(),
+            None => Err(W::invalid_input("no track is open")),
+        }
+    }
+
+    /// Write a single owned event into the open track.
+    ///
+    /// This function is only available with the `alloc` feature enabled.
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn push_owned(&mut self, event: &OwnedTrackEvent) -> WriteResult<W> {
+        self.push(&event.as_event())
+    }
+
+    /// Finish the open track, writing down its length.
+    ///
+    /// Does nothing if there is no open track.
+    pub fn close_track(&mut self) -> WriteResult<W> {
+        match self.track.take() {
+            Some(OpenTrack::Seek(start)) => {
+                let out = match self.out.make_seekable() {
+                    Some(out) => out,
+                    None => unreachable!(),
+                };
+                let len = chunk_len::<W>(out.tell()? - start)?;
+                out.write_at(&len, start - 4)?;
+            }
+            #[cfg(feature = "alloc")]
+            Some(OpenTrack::Buffered) => {
+                let len = chunk_len::<W>(self.buf.len() as u64)?;
+                self.out.write(b"MTrk")?;
+                self.out.write(&len)?;
+                self.out.write(&self.buf)?;
+                self.buf.clear();
+            }
+            #[cfg(not(feature = "alloc"))]
+            Some(OpenTrack::Buffered) => unreachable!(),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Close the open track, if any, and complete the file, returning the underlying writer.
+    ///
+    /// If the track count was not declared upfront, it is back-patched into the header.
+    pub fn finish(mut self) -> StdResult<W, W::Error> {
+        self.close_track()?;
+        match (self.declared_tracks, self.header_pos) {
+            (Some(declared), _) => {
+                if declared != self.track_count {
+                    return Err(W::invalid_input(
+                        "wrote a different amount of tracks than declared",
+                    ));
+                }
+            }
+            (None, Some(pos)) => {
+                if let Some(out) = self.out.make_seekable() {
+                    out.write_at(&self.track_count.to_be_bytes(), pos + TRACK_COUNT_OFFSET)?;
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+        Ok(self.out)
+    }
+}
+
+/// Fit a chunk length into a 32-bit big-endian integer.
+#[inline]
+fn chunk_len<W: Write>(len: u64) -> StdResult<[u8; 4], W::Error> {
+    let len =
+        u32::try_from(len).map_err(|_| W::invalid_input("midi chunk size exceeds 32 bit range"))?;
+    Ok(len.to_be_bytes())
+}
@@ -0,0 +1,345 @@
+//! Support for reading XMF (eXtensible Music Format) and Mobile XMF containers, which bundle SMF
+//! and DLS resources in a tree of nodes.
+
+#![cfg(feature = "alloc")]
+
+use crate::{
+    prelude::*,
+    smf::{ParseOptions, Smf},
+};
+use alloc::collections::BTreeSet;
+
+/// How deep the node tree may go before giving up, protecting against stack overflows.
+const MAX_DEPTH: usize = 64;
+
+/// The standard metadata field holding the name of a node.
+const FIELD_NODE_NAME: u32 = 1;
+/// The standard metadata field holding the format of a node's resource.
+const FIELD_RESOURCE_FORMAT: u32 = 3;
+
+/// The reference type for content stored inline, right after the node header.
+const REF_INLINE: u32 = 1;
+/// The reference type for content stored elsewhere in the file, at a given offset.
+const REF_IN_FILE: u32 = 2;
+
+/// The format of a resource stored in an XMF node, as given by its `ResourceFormat` metadata.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct ResourceFormat {
+    /// The format type: `0` for standard formats, `1` for MMA manufacturer formats, and `2` for
+    /// registered formats.
+    pub format_type: u32,
+    /// The format id within the format type.
+    /// For standard formats, `0` and `1` are SMF type 0 and 1, `2` to `4` are DLS 1, 2 and 2.1, and
+    /// `5` is Mobile DLS.
+    pub id: u32,
+}
+impl ResourceFormat {
+    /// Whether the resource is a Standard Midi File.
+    #[inline]
+    pub fn is_smf(&self) -> bool {
+        self.format_type == 0 && self.id <= 1
+    }
+
+    /// Whether the resource is a DLS sound bank.
+    #[inline]
+    pub fn is_dls(&self) -> bool {
+        self.format_type == 0 && (2..=5).contains(&self.id)
+    }
+}
+
+/// The content of an XMF node.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub enum XmfContent<'a> {
+    /// A folder node, containing other nodes.
+    Folder(Vec<XmfNode<'a>>),
+    /// A file node, containing a resource stored within the XMF file.
+    ///
+    /// Resources stored elsewhere in the file may extend up to the end of the file.
+    Resource(&'a [u8]),
+    /// A node whose content is referenced in an unsupported way, such as an external file or URL.
+    External {
+        /// The reference type id.
+        reference_type: u32,
+        /// The raw reference data.
+        data: &'a [u8],
+    },
+}
+
+/// A single node in the XMF tree.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct XmfNode<'a> {
+    /// The name of the node, if any, as a raw string.
+    pub name: Option<&'a [u8]>,
+    /// The format of the resource in this node, if specified.
+    pub format: Option<ResourceFormat>,
+    /// Whether the content is packed (usually compressed).
+    /// Packed resources cannot be parsed directly.
+    pub packed: bool,
+    /// The content of the node.
+    pub content: XmfContent<'a>,
+}
+impl<'a> XmfNode<'a> {
+    /// Whether this node holds a Standard Midi File, either according to its resource format or,
+    /// when it is not specified, judging by its content.
+    pub fn is_smf(&self) -> bool {
+        match (self.format, &self.content) {
+            (Some(format), XmfContent::Resource(_)) => format.is_smf(),
+            (None, XmfContent::Resource(data)) => data.starts_with(b"MThd"),
+            _ => false,
+        }
+    }
+
+    /// Visit all nodes in the subtree rooted at this node, in depth-first order.
+    pub fn walk<F: FnMut(&XmfNode<'a>)>(&self, f: &mut F) {
+        f(self);
+        if let XmfContent::Folder(children) = &self.content {
+            for child in children {
+                child.walk(f);
+            }
+        }
+    }
+}
+
+/// Represents an XMF or Mobile XMF file, providing access to its node tree.
+///
+/// The contained Standard Midi Files are left unparsed, and can be parsed on demand with
+/// [`smfs`](#method.smfs).
+///
+/// This type is only available with the `alloc` feature enabled.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Xmf<'a> {
+    /// The XMF version, such as `b"1.00"` or `b"2.00"` for Mobile XMF.
+    pub version: [u8; 4],
+    /// The file type and file type revision, only present from version 2.00 onwards.
+    pub file_type: Option<(u32, u32)>,
+    /// The root node of the tree.
+    pub root: XmfNode<'a>,
+}
+impl<'a> Xmf<'a> {
+    /// Read the node tree of an XMF file.
+    ///
+    /// Nodes are referenced by offset, so a malicious file could reference a node from several
+    /// places, building a cycle or a tree that grows exponentially when expanded.
+    /// Each node may therefore only be referenced once.
+    pub fn parse(raw: &[u8]) -> Result<Xmf<'_>> {
+        let mut cur = raw;
+        if cur.split_checked(4) != Some(&b"XMF_"[..]) {
+            bail!(err_invalid!("not an xmf file"));
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(
+            cur.split_checked(4)
+                .ok_or(err_invalid!("failed to read xmf version"))?,
+        );
+        let file_type = if &version[..] >= b"2.00" {
+            let file_type = u32::read(&mut cur)?;
+            let revision = u32::read(&mut cur)?;
+            Some((file_type, revision))
+        } else {
+            None
+        };
+        let _file_len = read_vlq(&mut cur)?;
+        let table_len = read_vlq(&mut cur)? as usize;
+        cur.split_checked(table_len)
+            .ok_or(err_invalid!("failed to read xmf metadata types table"))?;
+        let tree_start = read_vlq(&mut cur)? as usize;
+        let _tree_end = read_vlq(&mut cur)?;
+        let root = read_node(raw, tree_start, 0, &mut BTreeSet::new())
+            .context(err_invalid!("invalid xmf node tree"))?;
+        Ok(Xmf {
+            version,
+            file_type,
+            root,
+        })
+    }
+
+    /// Visit all nodes in the tree, in depth-first order.
+    #[inline]
+    pub fn walk<F: FnMut(&XmfNode<'a>)>(&self, mut f: F) {
+        self.root.walk(&mut f)
+    }
+
+    /// Get the raw data of all Standard Midi Files in the tree, in depth-first order.
+    pub fn smf_data(&self) -> Vec<&'a [u8]> {
+        let mut smfs = Vec::new();
+        self.walk(|node| match node.content {
+            XmfContent::Resource(data) if node.is_smf() => smfs.push(data),
+            _ => {}
+        });
+        smfs
+    }
+
+    /// Parse all Standard Midi Files in the tree, in depth-first order.
+    ///
+    /// Fails if any of the files is packed or invalid.
+    pub fn smfs(&self) -> Result<Vec<Smf<'a>>> {
+        self.smfs_with(ParseOptions::default())
+    }
+
+    /// Parse all Standard Midi Files in the tree using the given parsing options.
+    pub fn smfs_with(&self, options: ParseOptions) -> Result<Vec<Smf<'a>>> {
+        let mut smfs = Vec::new();
+        let mut result = Ok(());
+        self.walk(|node| match node.content {
+            XmfContent::Resource(data) if node.is_smf() && result.is_ok() => {
+                if node.packed {
+                    result = Err(err_invalid!("packed xmf content is not supported").into());
+                    return;
+                }
+                match Smf::parse_with(data, options) {
+                    Ok(smf) => smfs.push(smf),
+                    Err(err) => result = Err(err),
+                }
+            }
+            _ => {}
+        });
+        result.map(|()| smfs)
+    }
+}
+
+/// Read a variable-length quantity, as used throughout XMF files.
+///
+/// Since these hold offsets and lengths, malformed quantities are always rejected.
+#[inline]
+fn read_vlq(raw: &mut &[u8]) -> Result<u32> {
+    Ok(u28::read_u7(raw, true)?.as_int())
+}
+
+/// Read the node that starts at the given offset within the file.
+///
+/// `visited` holds the offsets of all nodes read so far, which may not be read again.
+fn read_node<'a>(
+    file: &'a [u8],
+    offset: usize,
+    depth: usize,
+    visited: &mut BTreeSet<usize>,
+) -> Result<XmfNode<'a>> {
+    ensure!(depth < MAX_DEPTH, err_invalid!("xmf tree too deep"));
+    ensure!(
+        visited.insert(offset),
+        err_invalid!("xmf node referenced more than once")
+    );
+    let node = file
+        .get(offset..)
+        .ok_or(err_invalid!("xmf node offset out of bounds"))?;
+    let mut cur = node;
+    let node_len = read_vlq(&mut cur)? as usize;
+    ensure!(node_len > 0, err_invalid!("empty xmf node"));
+    let node = match node.get(..node_len) {
+        Some(node) => node,
+        None => bail!(err_invalid!("xmf node extends past end of file")),
+    };
+    let item_count = read_vlq(&mut cur)? as usize;
+    let header_len = read_vlq(&mut cur)? as usize;
+    ensure!(
+        header_len <= node.len(),
+        err_invalid!("xmf node header longer than node")
+    );
+
+    //Metadata
+    let meta_len = read_vlq(&mut cur)? as usize;
+    let meta = cur
+        .split_checked(meta_len)
+        .ok_or(err_invalid!("failed to read xmf node metadata"))?;
+    let (name, format) = read_metadata(meta);
+
+    //Unpackers
+    let unpackers_len = read_vlq(&mut cur)? as usize;
+    cur.split_checked(unpackers_len)
+        .ok_or(err_invalid!("failed to read xmf node unpackers"))?;
+
+    //Content, starting with a reference type
+    let mut cur = &node[header_len..];
+    let reference_type = read_vlq(&mut cur)?;
+    let content = match reference_type {
+        REF_INLINE if item_count > 0 => {
+            let base = offset + (node.len() - cur.len());
+            XmfContent::Folder(read_children(file, base, item_count, depth, visited)?)
+        }
+        REF_INLINE => XmfContent::Resource(cur),
+        REF_IN_FILE => {
+            let target = read_vlq(&mut cur)? as usize;
+            if item_count > 0 {
+                XmfContent::Folder(read_children(file, target, item_count, depth, visited)?)
+            } else {
+                XmfContent::Resource(
+                    file.get(target..)
+                        .ok_or(err_invalid!("xmf resource offset out of bounds"))?,
+                )
+            }
+        }
+        _ => XmfContent::External {
+            reference_type,
+            data: cur,
+        },
+    };
+    Ok(XmfNode {
+        name,
+        format,
+        packed: unpackers_len > 0,
+        content,
+    })
+}
+
+/// Read consecutive child nodes starting at the given offset.
+fn read_children<'a>(
+    file: &'a [u8],
+    mut offset: usize,
+    count: usize,
+    depth: usize,
+    visited: &mut BTreeSet<usize>,
+) -> Result<Vec<XmfNode<'a>>> {
+    let mut children = Vec::with_capacity(count.min(file.len()));
+    for _ in 0..count {
+        let mut cur = file
+            .get(offset..)
+            .ok_or(err_invalid!("xmf node offset out of bounds"))?;
+        let len = read_vlq(&mut cur)? as usize;
+        children.push(read_node(file, offset, depth + 1, visited)?);
+        offset += len;
+    }
+    Ok(children)
+}
+
+/// Extract the node name and resource format out of the node metadata.
+///
+/// Only standard fields with universal contents are understood, and reading stops at the first
+/// item that cannot be read.
+fn read_metadata(mut meta: &[u8]) -> (Option<&[u8]>, Option<ResourceFormat>) {
+    let mut name = None;
+    let mut format = None;
+    while let Some((field, mut data)) = read_meta_item(&mut meta) {
+        match field {
+            Some(FIELD_NODE_NAME) if read_vlq(&mut data).is_ok() => name = Some(data),
+            Some(FIELD_RESOURCE_FORMAT) => {
+                if let (Ok(format_type), Ok(id)) = (read_vlq(&mut data), read_vlq(&mut data)) {
+                    format = Some(ResourceFormat { format_type, id });
+                }
+            }
+            _ => {}
+        }
+    }
+    (name, format)
+}
+
+/// Read a single metadata item, returning its standard field id (if any) and its data.
+fn read_meta_item<'a>(meta: &mut &'a [u8]) -> Option<(Option<u32>, &'a [u8])> {
+    if meta.is_empty() {
+        return None;
+    }
+    //A field specifier of 0 introduces a standard field id, otherwise it is the length of a
+    //custom field name
+    let field = match read_vlq(meta).ok()? {
+        0 => Some(read_vlq(meta).ok()?),
+        len => {
+            meta.split_checked(len as usize)?;
+            None
+        }
+    };
+    //International contents are not supported
+    if read_vlq(meta).ok()? != 0 {
+        return None;
+    }
+    let data_len = read_vlq(meta).ok()? as usize;
+    Some((field, meta.split_checked(data_len)?))
+}